@@ -175,7 +175,7 @@ object Androidoscopy {
                 }
 
                 val url = "wss://$hostIp:${config.port}/ws/app"
-                webSocketClient = WebSocketClient(url)
+                webSocketClient = WebSocketClient(url, config.clientCertificate, config.certificatePin)
                 webSocketClient?.connect()
 
                 webSocketClient?.events?.collect { event ->
@@ -347,7 +347,8 @@ object Androidoscopy {
                     packageName = ctx.packageName,
                     versionName = packageInfo.versionName ?: "unknown",
                     device = deviceInfo,
-                    dashboard = cfg.dashboardSchema ?: buildJsonObject { }
+                    dashboard = cfg.dashboardSchema ?: buildJsonObject { },
+                    authToken = cfg.authToken
                 )
             )
 
@@ -2,8 +2,11 @@ package com.lelloman.androidoscopy
 
 import com.lelloman.androidoscopy.anr.AnrDataProvider
 import com.lelloman.androidoscopy.anr.AnrWatchdog
+import com.lelloman.androidoscopy.connection.ClientCertificate
 import com.lelloman.androidoscopy.dashboard.DashboardBuilder
 import kotlinx.serialization.json.JsonElement
+import java.net.URI
+import java.net.URLDecoder
 
 typealias ActionHandler = suspend (args: Map<String, Any>) -> ActionResult
 
@@ -31,8 +34,16 @@ class AndroidoscopyConfig {
     var appName: String? = null
     var hostIp: String? = null
     var port: Int = 8889
+    /** Pairing token, required when the server has `[server.auth]` enabled. */
+    var authToken: String? = null
+    /**
+     * SHA-256 pin of the server certificate's public key (`sha256/<base64>`),
+     * as printed by `androidoscopy qr`. Without a pin any certificate is accepted.
+     */
+    var certificatePin: String? = null
     var enableLogging: Boolean = true
 
+    internal var clientCertificate: ClientCertificate? = null
     internal var dashboardSchema: JsonElement? = null
     internal val actionHandlers = mutableMapOf<String, ActionHandler>()
     internal var anrConfig: AnrConfig? = null
@@ -56,6 +67,42 @@ class AndroidoscopyConfig {
         anrDataProvider = AnrDataProvider(thresholdMs, maxHistory)
     }
 
+    /**
+     * Present a client certificate to servers that require mutual TLS.
+     *
+     * @param pkcs12 The file written by `androidoscopy enroll`.
+     * @param password The password printed by `androidoscopy enroll`.
+     */
+    fun clientCertificate(pkcs12: ByteArray, password: String) {
+        clientCertificate = ClientCertificate(pkcs12, password)
+    }
+
+    /**
+     * Configure the connection from the onboarding QR code shown by the
+     * dashboard or `androidoscopy qr`.
+     *
+     * @param uri The `androidoscopy://connect?...` URI encoded in the QR code.
+     */
+    fun onboarding(uri: String) {
+        val parsed = URI(uri)
+        require(parsed.scheme == "androidoscopy" && parsed.host == "connect") {
+            "Not an Androidoscopy onboarding URI: $uri"
+        }
+
+        val params = parsed.rawQuery.orEmpty()
+            .split('&')
+            .filter { it.isNotEmpty() }
+            .associate {
+                val (key, value) = it.split('=', limit = 2).let { parts -> parts[0] to parts.getOrElse(1) { "" } }
+                URLDecoder.decode(key, "UTF-8") to URLDecoder.decode(value, "UTF-8")
+            }
+
+        params["host"]?.let { hostIp = it }
+        params["port"]?.toIntOrNull()?.let { port = it }
+        params["pin"]?.let { certificatePin = it }
+        params["token"]?.let { authToken = it }
+    }
+
     fun dashboard(block: DashboardBuilder.() -> Unit) {
         val builder = DashboardBuilder()
         builder.block()
@@ -6,6 +6,7 @@ import kotlinx.serialization.Serializable
 import kotlinx.serialization.json.Json
 import java.net.DatagramPacket
 import java.net.DatagramSocket
+import java.net.InetAddress
 import java.net.SocketTimeoutException
 
 @Serializable
@@ -14,6 +15,9 @@ data class ServiceInfo(
     val version: String,
     val websocket_port: Int,
     val http_port: Int,
+    val instance_id: String = "",
+    val hostname: String = "",
+    val certificate_fingerprint: String? = null,
     val host: String = ""
 )
 
@@ -21,13 +25,24 @@ class DiscoveryListener(
     private val port: Int = 9998,
     private val json: Json = Json { ignoreUnknownKeys = true }
 ) {
-    suspend fun discoverService(timeoutMs: Long = 10_000): ServiceInfo? = withContext(Dispatchers.IO) {
+    /**
+     * Waits for the service broadcast, asking [probeHosts] to answer directly
+     * in the meantime, for networks that don't deliver broadcasts to devices.
+     */
+    suspend fun discoverService(
+        timeoutMs: Long = 10_000,
+        probeHosts: List<String> = listOf(BROADCAST_ADDRESS)
+    ): ServiceInfo? = withContext(Dispatchers.IO) {
         var socket: DatagramSocket? = null
         try {
             socket = DatagramSocket(port)
             socket.soTimeout = timeoutMs.toInt()
             socket.broadcast = true
 
+            for (host in probeHosts) {
+                sendProbe(socket, host)
+            }
+
             val buffer = ByteArray(1024)
             val packet = DatagramPacket(buffer, buffer.size)
 
@@ -42,6 +57,15 @@ class DiscoveryListener(
         }
     }
 
+    private fun sendProbe(socket: DatagramSocket, host: String) {
+        try {
+            val address = InetAddress.getByName(host)
+            socket.send(DatagramPacket(PROBE, PROBE.size, address, port))
+        } catch (e: Exception) {
+            // The broadcast may still arrive
+        }
+    }
+
     private fun parseServiceInfo(packet: DatagramPacket): ServiceInfo? {
         return try {
             val data = String(packet.data, 0, packet.length)
@@ -51,4 +75,9 @@ class DiscoveryListener(
             null
         }
     }
+
+    companion object {
+        const val BROADCAST_ADDRESS = "255.255.255.255"
+        private val PROBE = "DISCOVER".toByteArray()
+    }
 }
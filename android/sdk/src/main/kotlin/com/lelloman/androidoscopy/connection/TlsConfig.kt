@@ -1,30 +1,81 @@
 package com.lelloman.androidoscopy.connection
 
+import android.util.Base64
 import okhttp3.OkHttpClient
+import java.security.KeyStore
+import java.security.MessageDigest
+import java.security.cert.CertificateException
 import java.security.cert.X509Certificate
+import javax.net.ssl.KeyManager
+import javax.net.ssl.KeyManagerFactory
 import javax.net.ssl.SSLContext
 import javax.net.ssl.X509TrustManager
 
 /**
- * TLS configuration that trusts any server certificate.
- *
- * This allows the SDK to connect to servers using self-signed certificates
- * without requiring the Android app to enable cleartext traffic.
+ * A client certificate issued by `androidoscopy enroll`, for servers that
+ * require mutual TLS.
  */
-internal object TlsConfig {
-    private val trustManager = object : X509TrustManager {
-        override fun checkClientTrusted(chain: Array<out X509Certificate>?, authType: String?) {}
-        override fun checkServerTrusted(chain: Array<out X509Certificate>?, authType: String?) {}
-        override fun getAcceptedIssuers(): Array<X509Certificate> = arrayOf()
+class ClientCertificate(
+    val pkcs12: ByteArray,
+    val password: String
+)
+
+/**
+ * Trusts server certificates whose chain contains a certificate with the
+ * given public key pin, or any certificate if there is no pin.
+ */
+internal class PinningTrustManager(private val pin: String?) : X509TrustManager {
+    override fun checkClientTrusted(chain: Array<out X509Certificate>?, authType: String?) {}
+
+    override fun checkServerTrusted(chain: Array<out X509Certificate>?, authType: String?) {
+        if (pin == null) return
+        if (chain.orEmpty().none { pinOf(it) == pin }) {
+            throw CertificateException("Server certificate doesn't match the pinned $pin")
+        }
     }
 
-    private val sslContext = SSLContext.getInstance("TLS").apply {
-        init(null, arrayOf(trustManager), java.security.SecureRandom())
+    override fun getAcceptedIssuers(): Array<X509Certificate> = arrayOf()
+
+    companion object {
+        fun pinOf(certificate: X509Certificate): String {
+            val digest = MessageDigest.getInstance("SHA-256").digest(certificate.publicKey.encoded)
+            return "sha256/" + Base64.encodeToString(digest, Base64.NO_WRAP)
+        }
     }
+}
 
-    fun configureClient(builder: OkHttpClient.Builder): OkHttpClient.Builder {
+/**
+ * TLS configuration for the server's own certificates.
+ *
+ * This allows the SDK to connect to servers using self-signed certificates
+ * without requiring the Android app to enable cleartext traffic. With a
+ * certificate pin, only the server whose certificate was scanned is trusted.
+ */
+internal object TlsConfig {
+    fun configureClient(
+        builder: OkHttpClient.Builder,
+        clientCertificate: ClientCertificate? = null,
+        certificatePin: String? = null
+    ): OkHttpClient.Builder {
+        val trustManager = PinningTrustManager(certificatePin)
+        val keyManagers = clientCertificate?.let(::createKeyManagers)
+        val context = SSLContext.getInstance("TLS").apply {
+            init(keyManagers, arrayOf(trustManager), java.security.SecureRandom())
+        }
         return builder
-            .sslSocketFactory(sslContext.socketFactory, trustManager)
+            .sslSocketFactory(context.socketFactory, trustManager)
             .hostnameVerifier { _, _ -> true }
     }
+
+    private fun createKeyManagers(certificate: ClientCertificate): Array<KeyManager> {
+        val password = certificate.password.toCharArray()
+        val keyStore = KeyStore.getInstance("PKCS12").apply {
+            load(certificate.pkcs12.inputStream(), password)
+        }
+        val keyManagerFactory = KeyManagerFactory.getInstance(KeyManagerFactory.getDefaultAlgorithm()).apply {
+            init(keyStore, password)
+        }
+
+        return keyManagerFactory.keyManagers
+    }
 }
@@ -12,15 +12,20 @@ import java.util.concurrent.TimeUnit
 
 class WebSocketClient(
     private val url: String,
-    private val okHttpClient: OkHttpClient = createOkHttpClient()
+    clientCertificate: ClientCertificate? = null,
+    certificatePin: String? = null,
+    private val okHttpClient: OkHttpClient = createOkHttpClient(clientCertificate, certificatePin)
 ) {
     companion object {
-        private fun createOkHttpClient(): OkHttpClient {
+        private fun createOkHttpClient(
+            clientCertificate: ClientCertificate?,
+            certificatePin: String?
+        ): OkHttpClient {
             val builder = OkHttpClient.Builder()
                 .readTimeout(0, TimeUnit.MILLISECONDS)
 
-            // Configure TLS to trust any certificate (for self-signed server certs)
-            TlsConfig.configureClient(builder)
+            // Configure TLS for the server's own certificates, pinned if a pin is set
+            TlsConfig.configureClient(builder, clientCertificate, certificatePin)
 
             return builder.build()
         }
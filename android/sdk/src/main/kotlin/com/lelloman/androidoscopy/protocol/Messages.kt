@@ -67,7 +67,9 @@ data class RegisterPayload(
     @SerialName("version_name")
     val versionName: String,
     val device: DeviceInfo,
-    val dashboard: JsonElement
+    val dashboard: JsonElement,
+    @SerialName("auth_token")
+    val authToken: String? = null
 )
 
 @Serializable
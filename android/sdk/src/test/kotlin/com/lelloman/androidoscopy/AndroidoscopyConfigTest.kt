@@ -0,0 +1,38 @@
+package com.lelloman.androidoscopy
+
+import org.junit.Assert.assertEquals
+import org.junit.Assert.assertNull
+import org.junit.Test
+
+class AndroidoscopyConfigTest {
+
+    @Test
+    fun `onboarding configures host, port, pin and token`() {
+        val config = AndroidoscopyConfig()
+
+        config.onboarding(
+            "androidoscopy://connect?host=192.168.1.20&port=9000&tls=true&pin=sha256%2FAbC%2B%2F%3D&token=secret"
+        )
+
+        assertEquals("192.168.1.20", config.hostIp)
+        assertEquals(9000, config.port)
+        assertEquals("sha256/AbC+/=", config.certificatePin)
+        assertEquals("secret", config.authToken)
+    }
+
+    @Test
+    fun `onboarding without pin and token keeps them unset`() {
+        val config = AndroidoscopyConfig()
+
+        config.onboarding("androidoscopy://connect?host=10.0.2.2&port=8889&tls=false")
+
+        assertEquals("10.0.2.2", config.hostIp)
+        assertNull(config.certificatePin)
+        assertNull(config.authToken)
+    }
+
+    @Test(expected = IllegalArgumentException::class)
+    fun `onboarding rejects other URIs`() {
+        AndroidoscopyConfig().onboarding("https://example.com/connect?host=1.2.3.4")
+    }
+}
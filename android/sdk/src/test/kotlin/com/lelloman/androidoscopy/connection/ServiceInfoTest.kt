@@ -46,6 +46,27 @@ class ServiceInfoTest {
         assertEquals(9999, info.websocket_port)
     }
 
+    @Test
+    fun `parses server identity`() {
+        val data = """
+            {
+                "service": "androidoscopy",
+                "version": "1.0",
+                "instance_id": "5f0c6d1e-8c5b-4a53-9d3e-1b2a3c4d5e6f",
+                "hostname": "workstation",
+                "websocket_port": 9999,
+                "http_port": 8080,
+                "certificate_fingerprint": "AB:CD"
+            }
+        """.trimIndent()
+
+        val info = json.decodeFromString<ServiceInfo>(data)
+
+        assertEquals("5f0c6d1e-8c5b-4a53-9d3e-1b2a3c4d5e6f", info.instance_id)
+        assertEquals("workstation", info.hostname)
+        assertEquals("AB:CD", info.certificate_fingerprint)
+    }
+
     @Test
     fun `host can be set via copy`() {
         val data = """
@@ -3,7 +3,8 @@ import type {
     Session,
     ServiceToDashboardMessage,
     LogEntry,
-    ActionMessage
+    ActionMessage,
+    AttachmentInfo
 } from '../types/protocol';
 
 // Connection state
@@ -46,7 +47,8 @@ export function connect(url?: string) {
     connecting.set(true);
     error.set(null);
 
-    const wsUrl = url || `ws://${location.host}/ws/dashboard`;
+    const scheme = location.protocol === 'https:' ? 'wss' : 'ws';
+    const wsUrl = url || `${scheme}://${location.host}/ws/dashboard`;
 
     try {
         ws = new WebSocket(wsUrl);
@@ -132,6 +134,9 @@ function handleMessage(message: ServiceToDashboardMessage) {
         case 'ACTION_RESULT':
             handleActionResult(message.payload);
             break;
+        case 'ATTACHMENT_ADDED':
+            handleAttachmentAdded(message.payload.session_id, message.payload.attachment);
+            break;
     }
 }
 
@@ -230,6 +235,17 @@ function handleSessionEnded(sessionId: string) {
     });
 }
 
+function handleAttachmentAdded(sessionId: string, attachment: AttachmentInfo) {
+    sessions.update(map => {
+        const session = map.get(sessionId);
+        if (session) {
+            session.attachments = [...(session.attachments ?? []), attachment];
+            map.set(sessionId, { ...session });
+        }
+        return new Map(map);
+    });
+}
+
 function handleActionResult(payload: {
     session_id: string;
     action_id: string;
@@ -20,6 +20,17 @@ export interface Session {
     ended_at?: string;
     latest_data?: Record<string, unknown>;
     recent_logs: LogEntry[];
+    attachments?: AttachmentInfo[];
+}
+
+// File uploaded by the app, downloadable over HTTP
+export interface AttachmentInfo {
+    id: string;
+    name: string;
+    mime_type: string;
+    size: number;
+    sha256: string;
+    created_at: string;
 }
 
 // Dashboard schema types
@@ -279,7 +290,8 @@ export type ServiceToDashboardMessage =
     | SessionDataMessage
     | SessionLogMessage
     | SessionEndedMessage
-    | ActionResultMessage;
+    | ActionResultMessage
+    | AttachmentAddedMessage;
 
 export interface SyncMessage {
     type: 'SYNC';
@@ -343,6 +355,15 @@ export interface ActionResultMessage {
     };
 }
 
+export interface AttachmentAddedMessage {
+    type: 'ATTACHMENT_ADDED';
+    timestamp: string;
+    payload: {
+        session_id: string;
+        attachment: AttachmentInfo;
+    };
+}
+
 // Messages from dashboard to service
 export interface ActionMessage {
     type: 'ACTION';
@@ -0,0 +1,448 @@
+//! HTTP endpoints served alongside the dashboard.
+//!
+//! # Endpoints
+//!
+//! - `GET /api/sessions` - active and recently ended sessions, oldest first
+//!   (handled by [`list_sessions`])
+//! - `GET /api/sessions/{session_id}` - one session with its latest data and
+//!   recent logs (handled by [`get_session`])
+//! - `GET /api/sessions/{session_id}/attachments` - list a session's
+//!   attachments (handled by [`list_attachments`])
+//! - `GET /api/sessions/{session_id}/attachments/{attachment_id}` - download
+//!   a file uploaded by an app (handled by [`download_attachment`])
+//! - `GET /api/certificate` - the certificate apps should trust, as PEM, with
+//!   its SHA-256 pin and fingerprint (handled by [`certificate_info`])
+//! - `GET /api/certificate.pem`, `GET /api/certificate.der` - the same
+//!   certificate as a file (handled by [`certificate_pem`] and [`certificate_der`])
+//! - `GET /api/onboarding` - what the onboarding QR code encodes (handled by
+//!   [`onboarding`])
+//! - `GET /api/onboarding/qr.svg` - the QR code itself (handled by [`onboarding_qr`])
+//! - `GET /healthz` - the server is up (handled by [`healthz`])
+//! - `GET /readyz` - apps can connect: 200 once the app listener is bound,
+//!   with TLS when it is enabled, 503 before (handled by [`readyz`])
+//! - `GET /version` - server and protocol versions (handled by [`version`])
+//!
+//! The certificate and health endpoints are public, so a device can fetch
+//! the certificate, and a supervisor check the server, without a dashboard
+//! login. The onboarding endpoints contain the pairing token, so read-only
+//! dashboard users can't see them, and without dashboard login the token is
+//! only handed out to clients on the same machine.
+
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, Path, State},
+    http::{header, Response, StatusCode},
+    response::IntoResponse,
+    Extension, Json,
+};
+use serde::Serialize;
+use std::net::SocketAddr;
+use tracing::error;
+
+use crate::config::Role;
+use crate::dashboard_auth::AuthenticatedUser;
+use crate::onboarding::Onboarding;
+use crate::protocol::{SessionSummary, SUPPORTED_PROTOCOL_VERSIONS};
+use crate::state::AppState;
+use crate::tls;
+use crate::wire::SUPPORTED_SUBPROTOCOLS;
+
+pub async fn list_sessions(State(state): State<AppState>) -> Json<Vec<SessionSummary>> {
+    let manager = state.session_manager.lock().await;
+
+    let mut sessions: Vec<SessionSummary> = manager
+        .get_all_sessions()
+        .iter()
+        .map(|session| session.to_session_summary())
+        .collect();
+    sessions.sort_by_key(|session| session.started_at);
+    Json(sessions)
+}
+
+pub async fn get_session(
+    Path(session_id): Path<String>,
+    State(state): State<AppState>,
+) -> Response<Body> {
+    let manager = state.session_manager.lock().await;
+
+    match manager.get_session(&session_id) {
+        Some(session) => Json(session.to_session_info()).into_response(),
+        None => not_found(),
+    }
+}
+
+pub async fn list_attachments(
+    Path(session_id): Path<String>,
+    State(state): State<AppState>,
+) -> Response<Body> {
+    let manager = state.session_manager.lock().await;
+
+    match manager.get_session(&session_id) {
+        Some(session) => Json(session.attachments()).into_response(),
+        None => not_found(),
+    }
+}
+
+pub async fn download_attachment(
+    Path((session_id, attachment_id)): Path<(String, String)>,
+    State(state): State<AppState>,
+) -> Response<Body> {
+    let attachment = {
+        let manager = state.session_manager.lock().await;
+        match manager
+            .get_session(&session_id)
+            .and_then(|session| session.get_attachment(&attachment_id))
+        {
+            Some(attachment) => attachment.clone(),
+            None => return not_found(),
+        }
+    };
+
+    let data = match state
+        .attachment_store
+        .read(&session_id, &attachment.id)
+        .await
+    {
+        Ok(data) => data,
+        Err(e) => {
+            error!("Failed to read attachment {}: {}", attachment.id, e);
+            return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("Failed to read attachment"))
+                .unwrap();
+        }
+    };
+
+    // The file comes from the app and is served on the dashboard's origin, so
+    // it must never be rendered as a page that could run script there
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(
+            header::CONTENT_TYPE,
+            safe_content_type(&attachment.mime_type),
+        )
+        .header(header::X_CONTENT_TYPE_OPTIONS, "nosniff")
+        .header(header::CONTENT_SECURITY_POLICY, "sandbox")
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"{}\"",
+                sanitize_filename(&attachment.name)
+            ),
+        )
+        .body(Body::from(data))
+        .unwrap()
+}
+
+#[derive(Debug, Serialize)]
+pub struct CertificateInfo {
+    pub pem: String,
+    /// `sha256/<base64>` hash of the public key, for certificate pinning.
+    pub pin: String,
+    pub sha256_fingerprint: String,
+}
+
+struct DistributedCertificate {
+    pem: String,
+    der: Vec<u8>,
+}
+
+/// Reads the distributed certificate, or `None` when TLS is disabled.
+fn read_certificate(
+    state: &AppState,
+) -> Result<Option<DistributedCertificate>, Box<dyn std::error::Error>> {
+    let config = &state.config().server.tls;
+    if !config.enabled {
+        return Ok(None);
+    }
+
+    let path = tls::distributed_certificate_path(config);
+    let pem = std::fs::read_to_string(path)?;
+    let der = tls::get_certificate_der(path)?;
+    Ok(Some(DistributedCertificate { pem, der }))
+}
+
+fn certificate_error(e: Box<dyn std::error::Error>) -> Response<Body> {
+    error!("Failed to read certificate: {}", e);
+    Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(Body::from("Failed to read certificate"))
+        .unwrap()
+}
+
+pub async fn certificate_info(State(state): State<AppState>) -> Response<Body> {
+    let certificate = match read_certificate(&state) {
+        Ok(Some(certificate)) => certificate,
+        Ok(None) => return not_found(),
+        Err(e) => return certificate_error(e),
+    };
+
+    match tls::certificate_pin(&certificate.der) {
+        Ok(pin) => Json(CertificateInfo {
+            sha256_fingerprint: tls::certificate_fingerprint(&certificate.der),
+            pem: certificate.pem,
+            pin,
+        })
+        .into_response(),
+        Err(e) => certificate_error(e),
+    }
+}
+
+pub async fn certificate_pem(State(state): State<AppState>) -> Response<Body> {
+    match read_certificate(&state) {
+        Ok(Some(certificate)) => certificate_file(
+            certificate.pem.into_bytes(),
+            "application/x-pem-file",
+            "androidoscopy.pem",
+        ),
+        Ok(None) => not_found(),
+        Err(e) => certificate_error(e),
+    }
+}
+
+pub async fn certificate_der(State(state): State<AppState>) -> Response<Body> {
+    match read_certificate(&state) {
+        // The MIME type Android offers to install as a CA certificate
+        Ok(Some(certificate)) => certificate_file(
+            certificate.der,
+            "application/x-x509-ca-cert",
+            "androidoscopy.crt",
+        ),
+        Ok(None) => not_found(),
+        Err(e) => certificate_error(e),
+    }
+}
+
+fn certificate_file(
+    content: Vec<u8>,
+    content_type: &'static str,
+    filename: &str,
+) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, content_type)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        )
+        .body(Body::from(content))
+        .unwrap()
+}
+
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub version: &'static str,
+    /// Versions apps may REGISTER with.
+    pub protocol_versions: &'static [&'static str],
+    /// WebSocket subprotocols for encodings other than plain JSON.
+    pub subprotocols: &'static [&'static str],
+}
+
+impl VersionInfo {
+    fn current() -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION"),
+            protocol_versions: SUPPORTED_PROTOCOL_VERSIONS,
+            subprotocols: &SUPPORTED_SUBPROTOCOLS,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Health {
+    pub status: &'static str,
+    pub uptime_seconds: i64,
+    #[serde(flatten)]
+    pub version: VersionInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    /// Whether the app listener is bound.
+    pub app_listener: bool,
+    /// Whether it serves TLS, or TLS is disabled.
+    pub tls: bool,
+    #[serde(flatten)]
+    pub version: VersionInfo,
+}
+
+pub async fn healthz(State(state): State<AppState>) -> Json<Health> {
+    Json(Health {
+        status: "ok",
+        uptime_seconds: (chrono::Utc::now() - state.stats.started_at).num_seconds(),
+        version: VersionInfo::current(),
+    })
+}
+
+pub async fn readyz(State(state): State<AppState>) -> Response<Body> {
+    let listener = state.stats.app_listener();
+    let app_listener = listener.is_some();
+    // Falling back to plain WS when the certificates fail doesn't count
+    let tls = !state.config().server.tls.enabled || listener.is_some_and(|listener| listener.tls);
+    let ready = app_listener && tls;
+
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let readiness = Readiness {
+        ready,
+        app_listener,
+        tls,
+        version: VersionInfo::current(),
+    };
+    (status, Json(readiness)).into_response()
+}
+
+pub async fn version() -> Json<VersionInfo> {
+    Json(VersionInfo::current())
+}
+
+/// Builds the onboarding details, unless the user is read-only.
+///
+/// Without a logged in user, i.e. with dashboard login disabled, the pairing
+/// token is left out unless the request comes from this machine.
+fn onboarding_for(
+    state: &AppState,
+    user: Option<Extension<AuthenticatedUser>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+) -> Result<Onboarding, StatusCode> {
+    let trusted = match user {
+        Some(Extension(user)) if user.role == Role::ReadOnly => return Err(StatusCode::FORBIDDEN),
+        Some(_) => true,
+        None => peer.is_some_and(|ConnectInfo(addr)| addr.ip().is_loopback()),
+    };
+
+    let mut onboarding = Onboarding::from_config(&state.config()).map_err(|e| {
+        error!("Failed to prepare onboarding details: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    if !trusted {
+        onboarding.token = None;
+    }
+    Ok(onboarding)
+}
+
+#[derive(Debug, Serialize)]
+pub struct OnboardingInfo {
+    #[serde(flatten)]
+    pub onboarding: Onboarding,
+    pub uri: String,
+}
+
+pub async fn onboarding(
+    user: Option<Extension<AuthenticatedUser>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    State(state): State<AppState>,
+) -> Response<Body> {
+    match onboarding_for(&state, user, peer) {
+        Ok(onboarding) => Json(OnboardingInfo {
+            uri: onboarding.uri(),
+            onboarding,
+        })
+        .into_response(),
+        Err(status) => status.into_response(),
+    }
+}
+
+pub async fn onboarding_qr(
+    user: Option<Extension<AuthenticatedUser>>,
+    peer: Option<ConnectInfo<SocketAddr>>,
+    State(state): State<AppState>,
+) -> Response<Body> {
+    let onboarding = match onboarding_for(&state, user, peer) {
+        Ok(onboarding) => onboarding,
+        Err(status) => return status.into_response(),
+    };
+
+    match onboarding.qr_svg() {
+        Ok(svg) => Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "image/svg+xml")
+            .header(header::CACHE_CONTROL, "no-store")
+            .body(Body::from(svg))
+            .unwrap(),
+        Err(e) => {
+            error!("Failed to render onboarding QR code: {}", e);
+            Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("Failed to render QR code"))
+                .unwrap()
+        }
+    }
+}
+
+fn not_found() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .body(Body::from("Not Found"))
+        .unwrap()
+}
+
+/// Attachment types served as they are; anything else, such as HTML or SVG,
+/// is served as `application/octet-stream`.
+const SAFE_ATTACHMENT_TYPES: &[&str] = &[
+    "application/gzip",
+    "application/json",
+    "application/zip",
+    "image/gif",
+    "image/jpeg",
+    "image/png",
+    "image/webp",
+    "text/plain",
+];
+
+/// The Content-Type for an app-provided mime type, without parameters.
+fn safe_content_type(mime_type: &str) -> &'static str {
+    let essence = mime_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    SAFE_ATTACHMENT_TYPES
+        .iter()
+        .find(|safe| **safe == essence)
+        .copied()
+        .unwrap_or("application/octet-stream")
+}
+
+/// Keeps app-provided names from breaking out of the Content-Disposition header.
+fn sanitize_filename(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '"' | '\\' | '/' => '_',
+            c if c.is_ascii_control() || !c.is_ascii() => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_filename() {
+        assert_eq!(sanitize_filename("heap dump.txt"), "heap dump.txt");
+        assert_eq!(sanitize_filename("../\"evil\"\r\n.png"), "..__evil___.png");
+        assert_eq!(sanitize_filename("schermata è.png"), "schermata _.png");
+    }
+
+    #[test]
+    fn test_safe_content_type() {
+        assert_eq!(safe_content_type("image/png"), "image/png");
+        assert_eq!(safe_content_type("Text/Plain; charset=utf-8"), "text/plain");
+        assert_eq!(safe_content_type("text/html"), "application/octet-stream");
+        assert_eq!(
+            safe_content_type("image/svg+xml"),
+            "application/octet-stream"
+        );
+        assert_eq!(
+            safe_content_type("not a type\r\n"),
+            "application/octet-stream"
+        );
+    }
+}
@@ -0,0 +1,714 @@
+//! Files uploaded by apps through the chunked BLOB protocol.
+//!
+//! An upload is announced with BLOB_BEGIN, streamed as ordered BLOB_CHUNK
+//! messages and completed with BLOB_END. [`BlobAssembler`] streams the partial
+//! uploads of one app connection to disk and turns a finished upload into an
+//! [`Attachment`] once its size and SHA-256 match what BLOB_BEGIN declared.
+//!
+//! Attachments are kept on disk by [`AttachmentStore`], one directory per
+//! session, while their metadata stays in the session. A
+//! session's directory is deleted when the session expires. Sessions only live
+//! in memory, so directories left over from a previous run are deleted when the
+//! server starts.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::protocol::{AttachmentInfo, BlobBeginPayload, BlobChunkPayload};
+
+/// Uploads that may be in flight at the same time on one connection.
+pub const MAX_PENDING_BLOBS: usize = 4;
+
+/// File marking a directory as created by [`AttachmentStore`].
+pub const STORE_MARKER: &str = ".androidoscopy-attachments";
+
+// === Attachment ===
+
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub id: String,
+    pub name: String,
+    pub mime_type: String,
+    pub size: u64,
+    pub sha256: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Attachment {
+    pub fn to_attachment_info(&self) -> AttachmentInfo {
+        AttachmentInfo {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            mime_type: self.mime_type.clone(),
+            size: self.size,
+            sha256: self.sha256.clone(),
+            created_at: self.created_at,
+        }
+    }
+}
+
+// === Attachment Store ===
+
+#[derive(Debug)]
+pub struct AttachmentStore {
+    root: PathBuf,
+    // Atomic so they can change with a config reload
+    max_attachments_per_session: AtomicUsize,
+    quota_bytes: AtomicU64,
+}
+
+impl AttachmentStore {
+    pub fn new(
+        root: impl AsRef<Path>,
+        max_attachments_per_session: usize,
+        quota_bytes: u64,
+    ) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            max_attachments_per_session: AtomicUsize::new(max_attachments_per_session),
+            quota_bytes: AtomicU64::new(quota_bytes),
+        }
+    }
+
+    /// Changes the limits checked for uploads from now on.
+    pub fn set_limits(&self, max_attachments_per_session: usize, quota_bytes: u64) {
+        self.max_attachments_per_session
+            .store(max_attachments_per_session, Ordering::Relaxed);
+        self.quota_bytes.store(quota_bytes, Ordering::Relaxed);
+    }
+
+    /// Checks whether a session that already holds `existing` can store `size` more bytes.
+    pub fn check_quota(&self, existing: &[AttachmentInfo], size: u64) -> Result<(), BlobError> {
+        let used: u64 = existing.iter().map(|a| a.size).sum();
+
+        if existing.len() >= self.max_attachments_per_session.load(Ordering::Relaxed)
+            || used + size > self.quota_bytes.load(Ordering::Relaxed)
+        {
+            return Err(BlobError::QuotaExceeded);
+        }
+        Ok(())
+    }
+
+    /// Creates the file an upload is streamed into, next to the session's attachments.
+    async fn create_part(&self, session_id: &str) -> io::Result<PartFile> {
+        let dir = self.root.join(session_id);
+        tokio::fs::create_dir_all(&dir).await?;
+        // Without truncate this leaves an existing marker untouched
+        tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(false)
+            .open(self.root.join(STORE_MARKER))
+            .await?;
+
+        let path = dir.join(format!(".{}.part", Uuid::new_v4()));
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+            .await?;
+        Ok(PartFile {
+            path,
+            file: Some(file),
+            committed: false,
+        })
+    }
+
+    /// Moves a completed upload into place as the attachment `attachment_id`.
+    async fn commit_part(
+        &self,
+        session_id: &str,
+        mut part: PartFile,
+        attachment_id: &str,
+    ) -> io::Result<()> {
+        if let Some(mut file) = part.file.take() {
+            file.flush().await?;
+        }
+        tokio::fs::rename(&part.path, self.root.join(session_id).join(attachment_id)).await?;
+        part.committed = true;
+        Ok(())
+    }
+
+    /// Reads an attachment's content. Callers must check that the id belongs to the session.
+    pub async fn read(&self, session_id: &str, attachment_id: &str) -> io::Result<Vec<u8>> {
+        tokio::fs::read(self.root.join(session_id).join(attachment_id)).await
+    }
+
+    /// Deletes a single attachment.
+    pub async fn remove(&self, session_id: &str, attachment_id: &str) -> io::Result<()> {
+        tokio::fs::remove_file(self.root.join(session_id).join(attachment_id)).await
+    }
+
+    /// Deletes the attachments of a session.
+    pub async fn remove_session(&self, session_id: &str) -> io::Result<()> {
+        match tokio::fs::remove_dir_all(self.root.join(session_id)).await {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    /// Deletes attachments left over from a previous run.
+    ///
+    /// The directory is configurable, so only session directories are deleted,
+    /// and only when the [`STORE_MARKER`] shows the store created the directory.
+    pub fn purge(&self) -> io::Result<()> {
+        let entries = match std::fs::read_dir(&self.root) {
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            result => result?,
+        };
+
+        if !self.root.join(STORE_MARKER).is_file() {
+            if std::fs::read_dir(&self.root)?.next().is_some() {
+                return Err(io::Error::other(format!(
+                    "{} was not created by androidoscopy, leaving it alone",
+                    self.root.display()
+                )));
+            }
+            return Ok(());
+        }
+
+        for entry in entries {
+            let entry = entry?;
+            let is_session = entry
+                .file_name()
+                .to_str()
+                .is_some_and(|name| Uuid::parse_str(name).is_ok());
+            if is_session && entry.file_type()?.is_dir() {
+                std::fs::remove_dir_all(entry.path())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// === Blob Assembler ===
+
+/// A partial upload on disk, deleted when dropped unless it was committed.
+#[derive(Debug)]
+struct PartFile {
+    path: PathBuf,
+    file: Option<tokio::fs::File>,
+    committed: bool,
+}
+
+impl Drop for PartFile {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct PendingBlob {
+    name: String,
+    mime_type: String,
+    size: u64,
+    sha256: String,
+    next_index: u32,
+    hasher: Sha256,
+    received: u64,
+    part: PartFile,
+}
+
+/// Partial uploads of one app connection.
+///
+/// Chunks are written straight to a file in the session's attachment
+/// directory, so an upload never has to fit in memory. The file becomes the
+/// attachment once BLOB_END confirms its size and checksum, and is deleted if
+/// the upload fails or the assembler is dropped first.
+#[derive(Debug)]
+pub struct BlobAssembler {
+    store: Arc<AttachmentStore>,
+    session_id: String,
+    pending: HashMap<String, PendingBlob>,
+}
+
+impl BlobAssembler {
+    pub fn new(store: Arc<AttachmentStore>, session_id: impl Into<String>) -> Self {
+        Self {
+            store,
+            session_id: session_id.into(),
+            pending: HashMap::new(),
+        }
+    }
+
+    pub async fn begin(&mut self, payload: BlobBeginPayload) -> Result<(), BlobError> {
+        if self.pending.contains_key(&payload.blob_id) {
+            return Err(BlobError::AlreadyStarted);
+        }
+        if self.pending.len() >= MAX_PENDING_BLOBS {
+            return Err(BlobError::TooManyPending);
+        }
+
+        let part = self
+            .store
+            .create_part(&self.session_id)
+            .await
+            .map_err(|_| BlobError::StorageFailed)?;
+
+        self.pending.insert(
+            payload.blob_id,
+            PendingBlob {
+                name: payload.name,
+                mime_type: payload.mime_type,
+                size: payload.size,
+                sha256: payload.sha256.to_ascii_lowercase(),
+                next_index: 0,
+                hasher: Sha256::new(),
+                received: 0,
+                part,
+            },
+        );
+        Ok(())
+    }
+
+    /// Appends a chunk. Any error aborts the upload.
+    pub async fn push_chunk(&mut self, payload: BlobChunkPayload) -> Result<(), BlobError> {
+        let result = self.append(&payload).await;
+        if result.is_err() {
+            self.pending.remove(&payload.blob_id);
+        }
+        result
+    }
+
+    async fn append(&mut self, payload: &BlobChunkPayload) -> Result<(), BlobError> {
+        let blob = self
+            .pending
+            .get_mut(&payload.blob_id)
+            .ok_or(BlobError::UnknownBlob)?;
+
+        if payload.index != blob.next_index {
+            return Err(BlobError::OutOfOrder {
+                expected: blob.next_index,
+                got: payload.index,
+            });
+        }
+
+        let bytes = BASE64
+            .decode(&payload.data)
+            .map_err(|_| BlobError::InvalidEncoding)?;
+
+        if blob.received + bytes.len() as u64 > blob.size {
+            return Err(BlobError::SizeMismatch);
+        }
+
+        let file = blob.part.file.as_mut().ok_or(BlobError::StorageFailed)?;
+        file.write_all(&bytes)
+            .await
+            .map_err(|_| BlobError::StorageFailed)?;
+
+        blob.hasher.update(&bytes);
+        blob.received += bytes.len() as u64;
+        blob.next_index += 1;
+        Ok(())
+    }
+
+    /// Completes an upload, verifying its size and checksum before storing it.
+    pub async fn finish(&mut self, blob_id: &str) -> Result<Attachment, BlobError> {
+        let blob = self.pending.remove(blob_id).ok_or(BlobError::UnknownBlob)?;
+
+        if blob.received != blob.size {
+            return Err(BlobError::SizeMismatch);
+        }
+
+        let digest = format!("{:x}", blob.hasher.finalize());
+        if digest != blob.sha256 {
+            return Err(BlobError::ChecksumMismatch);
+        }
+
+        let attachment = Attachment {
+            id: Uuid::new_v4().to_string(),
+            name: blob.name,
+            mime_type: blob.mime_type,
+            size: blob.size,
+            sha256: digest,
+            created_at: Utc::now(),
+        };
+        self.store
+            .commit_part(&self.session_id, blob.part, &attachment.id)
+            .await
+            .map_err(|_| BlobError::StorageFailed)?;
+        Ok(attachment)
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlobError {
+    AlreadyStarted,
+    TooManyPending,
+    UnknownBlob,
+    OutOfOrder { expected: u32, got: u32 },
+    InvalidEncoding,
+    SizeMismatch,
+    ChecksumMismatch,
+    QuotaExceeded,
+    StorageFailed,
+}
+
+impl BlobError {
+    /// Error code reported to the app in an ERROR message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            BlobError::AlreadyStarted => "BLOB_ALREADY_STARTED",
+            BlobError::TooManyPending => "BLOB_TOO_MANY_PENDING",
+            BlobError::UnknownBlob => "BLOB_UNKNOWN",
+            BlobError::OutOfOrder { .. } => "BLOB_OUT_OF_ORDER",
+            BlobError::InvalidEncoding => "BLOB_INVALID_ENCODING",
+            BlobError::SizeMismatch => "BLOB_SIZE_MISMATCH",
+            BlobError::ChecksumMismatch => "BLOB_CHECKSUM_MISMATCH",
+            BlobError::QuotaExceeded => "BLOB_QUOTA_EXCEEDED",
+            BlobError::StorageFailed => "BLOB_STORAGE_FAILED",
+        }
+    }
+}
+
+impl std::fmt::Display for BlobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlobError::AlreadyStarted => write!(f, "A blob with this id is already being uploaded"),
+            BlobError::TooManyPending => {
+                write!(
+                    f,
+                    "At most {} blobs can be uploaded at the same time",
+                    MAX_PENDING_BLOBS
+                )
+            }
+            BlobError::UnknownBlob => write!(f, "No upload in progress for this blob id"),
+            BlobError::OutOfOrder { expected, got } => {
+                write!(f, "Expected chunk {}, got chunk {}", expected, got)
+            }
+            BlobError::InvalidEncoding => write!(f, "Chunk data is not valid base64"),
+            BlobError::SizeMismatch => write!(f, "Received data does not match the declared size"),
+            BlobError::ChecksumMismatch => write!(f, "SHA-256 of received data does not match"),
+            BlobError::QuotaExceeded => write!(f, "Session attachment quota exceeded"),
+            BlobError::StorageFailed => write!(f, "Failed to store attachment"),
+        }
+    }
+}
+
+impl std::error::Error for BlobError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sha256_hex(data: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(data))
+    }
+
+    fn begin_payload(blob_id: &str, data: &[u8]) -> BlobBeginPayload {
+        BlobBeginPayload {
+            blob_id: blob_id.to_string(),
+            name: "dump.txt".to_string(),
+            mime_type: "text/plain".to_string(),
+            size: data.len() as u64,
+            sha256: sha256_hex(data),
+        }
+    }
+
+    fn test_store() -> (PathBuf, Arc<AttachmentStore>) {
+        let root = std::env::temp_dir().join(format!("androidoscopy-test-{}", Uuid::new_v4()));
+        let store = Arc::new(AttachmentStore::new(&root, 10, 1024));
+        (root, store)
+    }
+
+    fn chunk_payload(blob_id: &str, index: u32, data: &[u8]) -> BlobChunkPayload {
+        BlobChunkPayload {
+            blob_id: blob_id.to_string(),
+            index,
+            data: BASE64.encode(data),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_reassembles_chunks() {
+        let (root, store) = test_store();
+        let data = b"hello chunked world";
+        let mut assembler = BlobAssembler::new(store.clone(), "session-1");
+
+        assembler
+            .begin(begin_payload("blob-1", data))
+            .await
+            .unwrap();
+        assembler
+            .push_chunk(chunk_payload("blob-1", 0, &data[..5]))
+            .await
+            .unwrap();
+        assembler
+            .push_chunk(chunk_payload("blob-1", 1, &data[5..]))
+            .await
+            .unwrap();
+        let attachment = assembler.finish("blob-1").await.unwrap();
+
+        assert_eq!(store.read("session-1", &attachment.id).await.unwrap(), data);
+        assert_eq!(attachment.name, "dump.txt");
+        assert_eq!(attachment.size, data.len() as u64);
+        assert_eq!(assembler.pending_count(), 0);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_checksum_mismatch() {
+        let (root, store) = test_store();
+        let data = b"payload";
+        let mut assembler = BlobAssembler::new(store, "session-1");
+
+        let mut begin = begin_payload("blob-1", data);
+        begin.sha256 = sha256_hex(b"something else");
+        assembler.begin(begin).await.unwrap();
+        assembler
+            .push_chunk(chunk_payload("blob-1", 0, data))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            assembler.finish("blob-1").await.unwrap_err(),
+            BlobError::ChecksumMismatch
+        );
+        // The partial file is gone
+        assert_eq!(
+            std::fs::read_dir(root.join("session-1")).unwrap().count(),
+            0
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_out_of_order_chunk_and_aborts_upload() {
+        let (root, store) = test_store();
+        let data = b"payload";
+        let mut assembler = BlobAssembler::new(store, "session-1");
+
+        assembler
+            .begin(begin_payload("blob-1", data))
+            .await
+            .unwrap();
+        let err = assembler
+            .push_chunk(chunk_payload("blob-1", 1, data))
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            err,
+            BlobError::OutOfOrder {
+                expected: 0,
+                got: 1
+            }
+        );
+        assert_eq!(assembler.pending_count(), 0);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_data_beyond_declared_size() {
+        let (root, store) = test_store();
+        let mut assembler = BlobAssembler::new(store, "session-1");
+
+        assembler
+            .begin(begin_payload("blob-1", b"abc"))
+            .await
+            .unwrap();
+        let err = assembler
+            .push_chunk(chunk_payload("blob-1", 0, b"abcdef"))
+            .await
+            .unwrap_err();
+
+        assert_eq!(err, BlobError::SizeMismatch);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejects_incomplete_blob() {
+        let (root, store) = test_store();
+        let data = b"payload";
+        let mut assembler = BlobAssembler::new(store, "session-1");
+
+        assembler
+            .begin(begin_payload("blob-1", data))
+            .await
+            .unwrap();
+        assembler
+            .push_chunk(chunk_payload("blob-1", 0, &data[..3]))
+            .await
+            .unwrap();
+
+        assert_eq!(
+            assembler.finish("blob-1").await.unwrap_err(),
+            BlobError::SizeMismatch
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_dropping_assembler_deletes_partial_uploads() {
+        let (root, store) = test_store();
+        let mut assembler = BlobAssembler::new(store, "session-1");
+
+        assembler
+            .begin(begin_payload("blob-1", b"payload"))
+            .await
+            .unwrap();
+        assembler
+            .push_chunk(chunk_payload("blob-1", 0, b"pay"))
+            .await
+            .unwrap();
+        assert_eq!(
+            std::fs::read_dir(root.join("session-1")).unwrap().count(),
+            1
+        );
+
+        drop(assembler);
+        assert_eq!(
+            std::fs::read_dir(root.join("session-1")).unwrap().count(),
+            0
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    fn attachment_info(size: u64) -> AttachmentInfo {
+        AttachmentInfo {
+            id: Uuid::new_v4().to_string(),
+            name: "a.bin".to_string(),
+            mime_type: "application/octet-stream".to_string(),
+            size,
+            sha256: String::new(),
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test]
+    fn test_store_quota_bytes() {
+        let store = AttachmentStore::new("unused", 10, 100);
+        let existing = vec![attachment_info(60)];
+
+        assert!(store.check_quota(&existing, 40).is_ok());
+        assert_eq!(
+            store.check_quota(&existing, 41).unwrap_err(),
+            BlobError::QuotaExceeded
+        );
+    }
+
+    #[test]
+    fn test_store_quota_count() {
+        let store = AttachmentStore::new("unused", 2, 100);
+        let existing = vec![attachment_info(1), attachment_info(1)];
+
+        assert_eq!(
+            store.check_quota(&existing, 1).unwrap_err(),
+            BlobError::QuotaExceeded
+        );
+    }
+
+    async fn store_attachment(
+        store: &Arc<AttachmentStore>,
+        session_id: &str,
+        data: &[u8],
+    ) -> Attachment {
+        let mut assembler = BlobAssembler::new(store.clone(), session_id);
+        assembler
+            .begin(begin_payload("blob-1", data))
+            .await
+            .unwrap();
+        assembler
+            .push_chunk(chunk_payload("blob-1", 0, data))
+            .await
+            .unwrap();
+        assembler.finish("blob-1").await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_store_save_read_and_purge() {
+        let (root, store) = test_store();
+
+        // Purging a missing directory is fine
+        store.purge().unwrap();
+
+        let data = b"stored on disk";
+        let session_id = Uuid::new_v4().to_string();
+        let attachment = store_attachment(&store, &session_id, data).await;
+
+        assert_eq!(store.read(&session_id, &attachment.id).await.unwrap(), data);
+
+        // Only session directories are deleted
+        std::fs::write(root.join("notes.txt"), "keep").unwrap();
+        std::fs::create_dir(root.join("keep")).unwrap();
+        store.purge().unwrap();
+        assert!(store.read(&session_id, &attachment.id).await.is_err());
+        assert!(root.join("notes.txt").exists());
+        assert!(root.join("keep").exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_store_purge_leaves_foreign_directory_alone() {
+        let root = std::env::temp_dir().join(format!("androidoscopy-test-{}", Uuid::new_v4()));
+        let session_dir = root.join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&session_dir).unwrap();
+        let store = AttachmentStore::new(&root, 10, 1024);
+
+        assert!(store.purge().is_err());
+        assert!(session_dir.exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_store_remove_session() {
+        let (root, store) = test_store();
+        let first = store_attachment(&store, "session-1", b"removed").await;
+        let second = store_attachment(&store, "session-2", b"kept").await;
+
+        store.remove_session("session-1").await.unwrap();
+        assert!(store.read("session-1", &first.id).await.is_err());
+        assert!(store.read("session-2", &second.id).await.is_ok());
+
+        // Removing a session without attachments is fine
+        store.remove_session("session-3").await.unwrap();
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_limits_pending_uploads() {
+        let (root, store) = test_store();
+        let mut assembler = BlobAssembler::new(store, "session-1");
+
+        for i in 0..MAX_PENDING_BLOBS {
+            assembler
+                .begin(begin_payload(&format!("blob-{}", i), b"x"))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            assembler
+                .begin(begin_payload("one-more", b"x"))
+                .await
+                .unwrap_err(),
+            BlobError::TooManyPending
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,156 @@
+//! Pairing-token authentication for app connections.
+//!
+//! When `[server.auth]` is enabled, apps must present the server's pairing
+//! token before they can register a session. The token can be sent either
+//! as a header on the WebSocket upgrade request:
+//!
+//! - `X-Androidoscopy-Token: <token>`
+//! - `Authorization: Bearer <token>`
+//!
+//! or in the `auth_token` field of the REGISTER payload. Upgrades carrying a
+//! wrong token are rejected with `401 Unauthorized`; upgrades without one are
+//! accepted, but the connection is closed unless REGISTER carries the token.
+//!
+//! Unless a token is set in the config file, one is generated on first use
+//! and stored at `token_path`. `androidoscopy token` prints it.
+
+use axum::http::{header, HeaderMap};
+use std::fs;
+use std::io;
+use std::path::Path;
+use tracing::info;
+use uuid::Uuid;
+
+use crate::ca::write_file;
+use crate::config::AuthConfig;
+
+pub const TOKEN_HEADER: &str = "x-androidoscopy-token";
+
+/// Returns the configured token, or the one stored at `token_path`,
+/// generating and storing a new one if neither exists.
+pub fn load_or_create_token(config: &AuthConfig) -> io::Result<String> {
+    if let Some(token) = config.token.as_ref().filter(|t| !t.is_empty()) {
+        return Ok(token.clone());
+    }
+
+    let path = Path::new(&config.token_path);
+    match fs::read_to_string(path) {
+        Ok(token) if !token.trim().is_empty() => Ok(token.trim().to_string()),
+        Ok(_) => regenerate_token(config),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => regenerate_token(config),
+        Err(e) => Err(e),
+    }
+}
+
+/// Generates a new token and stores it at `token_path`, replacing any previous one.
+pub fn regenerate_token(config: &AuthConfig) -> io::Result<String> {
+    let token = Uuid::new_v4().simple().to_string();
+    // Anyone who can read the token can connect as an app
+    write_file(&config.token_path, &token, true)?;
+
+    info!("Generated pairing token at {}", config.token_path);
+    Ok(token)
+}
+
+/// Extracts a token from the upgrade request headers, if one was sent.
+pub fn token_from_headers(headers: &HeaderMap) -> Option<&str> {
+    if let Some(token) = headers.get(TOKEN_HEADER).and_then(|v| v.to_str().ok()) {
+        return Some(token.trim());
+    }
+
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+}
+
+/// Compares tokens in constant time, so the comparison doesn't leak how much of a guess was right.
+pub fn tokens_match(expected: &str, provided: &str) -> bool {
+    let expected = expected.as_bytes();
+    let provided = provided.as_bytes();
+
+    if expected.len() != provided.len() {
+        return false;
+    }
+
+    expected
+        .iter()
+        .zip(provided)
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn temp_config() -> AuthConfig {
+        AuthConfig {
+            enabled: true,
+            token: None,
+            token_path: std::env::temp_dir()
+                .join(format!("androidoscopy-token-{}", Uuid::new_v4()))
+                .to_string_lossy()
+                .to_string(),
+        }
+    }
+
+    #[test]
+    fn test_token_is_generated_once() {
+        let config = temp_config();
+
+        let token = load_or_create_token(&config).unwrap();
+        assert_eq!(token.len(), 32);
+        assert_eq!(load_or_create_token(&config).unwrap(), token);
+
+        let rotated = regenerate_token(&config).unwrap();
+        assert_ne!(rotated, token);
+        assert_eq!(load_or_create_token(&config).unwrap(), rotated);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&config.token_path)
+                .unwrap()
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::remove_file(&config.token_path).unwrap();
+    }
+
+    #[test]
+    fn test_configured_token_wins() {
+        let mut config = temp_config();
+        config.token = Some("from-config".to_string());
+
+        assert_eq!(load_or_create_token(&config).unwrap(), "from-config");
+        assert!(!Path::new(&config.token_path).exists());
+    }
+
+    #[test]
+    fn test_token_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(token_from_headers(&headers), None);
+
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer abc"),
+        );
+        assert_eq!(token_from_headers(&headers), Some("abc"));
+
+        headers.insert(TOKEN_HEADER, HeaderValue::from_static("xyz"));
+        assert_eq!(token_from_headers(&headers), Some("xyz"));
+    }
+
+    #[test]
+    fn test_tokens_match() {
+        assert!(tokens_match("secret", "secret"));
+        assert!(!tokens_match("secret", "secreT"));
+        assert!(!tokens_match("secret", "secret2"));
+        assert!(!tokens_match("secret", ""));
+    }
+}
@@ -0,0 +1,368 @@
+//! A small local certificate authority.
+//!
+//! The server keeps up to two of these:
+//!
+//! - a root CA issuing the short-lived server certificate, so clients can pin
+//!   or trust one long-lived certificate while the server certificate is
+//!   re-issued whenever the LAN IPs change (see [`crate::tls`]);
+//! - with `[server.tls.client_auth]` enabled, a device CA: only app
+//!   connections presenting a client certificate it issued are accepted.
+//!   `androidoscopy enroll <device>` issues one and exports it, together with
+//!   its private key and the CA certificate, as a PKCS#12 file for the app.
+
+use p12_keystore::{
+    Certificate as P12Certificate, EncryptionAlgorithm, KeyStore, KeyStoreEntry, MacAlgorithm,
+    PrivateKey as P12PrivateKey, PrivateKeyChain,
+};
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, DnType, ExtendedKeyUsagePurpose, IsCa,
+    KeyPair, KeyUsagePurpose, SerialNumber,
+};
+use rustls::pki_types::{pem::PemObject, CertificateDer};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use time::{Duration, OffsetDateTime};
+use tracing::info;
+use uuid::Uuid;
+
+/// Common name of the CA that issues the server certificate.
+pub const SERVER_CA_NAME: &str = "Androidoscopy Local CA";
+
+/// Common name of the CA that issues client certificates to devices.
+pub const DEVICE_CA_NAME: &str = "Androidoscopy Device CA";
+
+/// Validity of a newly created CA certificate.
+const CA_VALIDITY_DAYS: i64 = 10 * 365;
+
+pub struct LocalCa {
+    /// Re-signed from the stored parameters; only used as the issuer when signing.
+    issuer: Certificate,
+    key: KeyPair,
+    /// The certificate as stored on disk, which is what clients trust.
+    cert_der: CertificateDer<'static>,
+    cert_pem: String,
+}
+
+/// A certificate issued by [`LocalCa`], with its private key.
+pub struct IssuedCertificate {
+    pub cert_der: Vec<u8>,
+    pub cert_pem: String,
+    /// PKCS#8 DER.
+    pub key_der: Vec<u8>,
+    pub key_pem: String,
+}
+
+impl LocalCa {
+    /// Loads the CA from `cert_path` and `key_path`, creating a new one if neither exists.
+    pub fn load_or_create(
+        cert_path: &str,
+        key_path: &str,
+        common_name: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let cert_exists = Path::new(cert_path).exists();
+        let key_exists = Path::new(key_path).exists();
+
+        match (cert_exists, key_exists) {
+            (true, true) => Self::load(cert_path, key_path),
+            (false, false) => Self::create(cert_path, key_path, common_name),
+            _ => Err(format!(
+                "Only one of {} and {} exists; restore the missing file or remove both to create a new CA",
+                cert_path, key_path
+            )
+            .into()),
+        }
+    }
+
+    fn load(cert_path: &str, key_path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let cert_pem = fs::read_to_string(cert_path)
+            .map_err(|e| format!("Failed to read CA certificate {}: {}", cert_path, e))?;
+        let key_pem = fs::read_to_string(key_path)
+            .map_err(|e| format!("Failed to read CA key {}: {}", key_path, e))?;
+
+        let key = KeyPair::from_pem(&key_pem)
+            .map_err(|e| format!("Invalid CA key {}: {}", key_path, e))?;
+        let params = CertificateParams::from_ca_cert_pem(&cert_pem)
+            .map_err(|e| format!("Invalid CA certificate {}: {}", cert_path, e))?;
+        let cert_der = CertificateDer::from_pem_slice(cert_pem.as_bytes())
+            .map_err(|e| format!("Invalid CA certificate {}: {}", cert_path, e))?;
+
+        let issuer = params.self_signed(&key)?;
+
+        Ok(Self {
+            issuer,
+            key,
+            cert_der,
+            cert_pem,
+        })
+    }
+
+    fn create(
+        cert_path: &str,
+        key_path: &str,
+        common_name: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut params = CertificateParams::new(Vec::<String>::new())?;
+        params
+            .distinguished_name
+            .push(DnType::CommonName, common_name);
+        params.is_ca = IsCa::Ca(BasicConstraints::Constrained(0));
+        params.key_usages = vec![
+            KeyUsagePurpose::KeyCertSign,
+            KeyUsagePurpose::CrlSign,
+            KeyUsagePurpose::DigitalSignature,
+        ];
+        params.serial_number = Some(random_serial());
+        params.not_before = OffsetDateTime::now_utc() - Duration::hours(1);
+        params.not_after = OffsetDateTime::now_utc() + Duration::days(CA_VALIDITY_DAYS);
+
+        let key = KeyPair::generate()?;
+        let cert = params.self_signed(&key)?;
+
+        write_file(cert_path, cert.pem(), false)?;
+        write_file(key_path, key.serialize_pem(), true)?;
+        info!("Created {} at {}", common_name, cert_path);
+
+        Ok(Self {
+            cert_der: cert.der().clone(),
+            cert_pem: cert.pem(),
+            issuer: cert,
+            key,
+        })
+    }
+
+    pub fn cert_der(&self) -> &CertificateDer<'static> {
+        &self.cert_der
+    }
+
+    pub fn cert_pem(&self) -> &str {
+        &self.cert_pem
+    }
+
+    /// Issues a client certificate for a device, named after it.
+    pub fn issue_client_certificate(
+        &self,
+        device_name: &str,
+        validity_days: u32,
+    ) -> Result<IssuedCertificate, Box<dyn std::error::Error>> {
+        let mut params = CertificateParams::new(Vec::<String>::new())?;
+        params
+            .distinguished_name
+            .push(DnType::CommonName, device_name);
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        params.serial_number = Some(random_serial());
+        params.not_before = OffsetDateTime::now_utc() - Duration::hours(1);
+        params.not_after = OffsetDateTime::now_utc() + Duration::days(validity_days.into());
+
+        let key = KeyPair::generate()?;
+        let cert = params.signed_by(&key, &self.issuer, &self.key)?;
+
+        Ok(IssuedCertificate {
+            cert_der: cert.der().to_vec(),
+            cert_pem: cert.pem(),
+            key_der: key.serialize_der(),
+            key_pem: key.serialize_pem(),
+        })
+    }
+
+    /// Issues a server certificate valid for the given host names and IPs.
+    pub fn issue_server_certificate(
+        &self,
+        subject_alt_names: &[String],
+        validity_days: u32,
+    ) -> Result<IssuedCertificate, Box<dyn std::error::Error>> {
+        let mut params = CertificateParams::new(subject_alt_names.to_vec())?;
+        params
+            .distinguished_name
+            .push(DnType::CommonName, "Androidoscopy Server");
+        params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ServerAuth];
+        params.serial_number = Some(random_serial());
+        params.not_before = OffsetDateTime::now_utc() - Duration::hours(1);
+        params.not_after = OffsetDateTime::now_utc() + Duration::days(validity_days.into());
+
+        let key = KeyPair::generate()?;
+        let cert = params.signed_by(&key, &self.issuer, &self.key)?;
+
+        Ok(IssuedCertificate {
+            cert_der: cert.der().to_vec(),
+            cert_pem: cert.pem(),
+            key_der: key.serialize_der(),
+            key_pem: key.serialize_pem(),
+        })
+    }
+
+    /// Bundles an issued certificate, its key and the CA certificate into a
+    /// password-protected PKCS#12 file.
+    pub fn export_pkcs12(
+        &self,
+        issued: &IssuedCertificate,
+        alias: &str,
+        password: &str,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let key = P12PrivateKey::from_der(&issued.key_der)?;
+        let chain = PrivateKeyChain::new(
+            alias,
+            key,
+            [
+                P12Certificate::from_der(&issued.cert_der)?,
+                P12Certificate::from_der(self.cert_der.as_ref())?,
+            ],
+        );
+
+        let mut keystore = KeyStore::new();
+        keystore.add_entry(alias, KeyStoreEntry::PrivateKeyChain(chain));
+
+        // Legacy algorithms, since older Android versions can't read AES-encrypted PKCS#12 files
+        let bytes = keystore
+            .writer(password)
+            .encryption_algorithm(EncryptionAlgorithm::PbeWithShaAnd3KeyTripleDesCbc)
+            .mac_algorithm(MacAlgorithm::HmacSha1)
+            .write()?;
+
+        Ok(bytes)
+    }
+}
+
+fn random_serial() -> SerialNumber {
+    let mut bytes = Uuid::new_v4().as_bytes().to_vec();
+    // Keep the DER integer positive
+    bytes[0] &= 0x7f;
+    SerialNumber::from_slice(&bytes)
+}
+
+/// Writes a file, creating parent directories; `private` files are only readable by the owner.
+///
+/// The content goes to a temporary file next to `path` first, which then
+/// replaces `path`, so readers never see a partly written file.
+pub fn write_file(
+    path: impl AsRef<Path>,
+    content: impl AsRef<[u8]>,
+    private: bool,
+) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp_path = dir.join(format!(".{}.{}.tmp", name, Uuid::new_v4().simple()));
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        // Never readable by others, not even briefly
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+
+    let written = options.open(&temp_path).and_then(|mut file| {
+        file.write_all(content.as_ref())?;
+        file.sync_all()
+    });
+    match written.and_then(|_| fs::rename(&temp_path, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&temp_path);
+            Err(e)
+        }
+    }
+}
+
+/// Default file name for a device's PKCS#12 bundle, keeping only characters
+/// that can't leave the current directory.
+pub fn pkcs12_file_name(device: &str) -> String {
+    let name: String = device
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let name = name.trim_start_matches('.');
+    format!("{}.p12", if name.is_empty() { "device" } else { name })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use p12_keystore::Pkcs12ImportPolicy;
+
+    fn temp_paths() -> (String, String) {
+        let dir = std::env::temp_dir().join(format!("androidoscopy-ca-{}", Uuid::new_v4()));
+        (
+            dir.join("ca.pem").to_string_lossy().to_string(),
+            dir.join("ca-key.pem").to_string_lossy().to_string(),
+        )
+    }
+
+    #[test]
+    fn test_ca_is_persisted() {
+        let (cert_path, key_path) = temp_paths();
+
+        let created = LocalCa::load_or_create(&cert_path, &key_path, "Test CA").unwrap();
+        let loaded = LocalCa::load_or_create(&cert_path, &key_path, "Test CA").unwrap();
+        assert_eq!(created.cert_der(), loaded.cert_der());
+
+        fs::remove_file(&key_path).unwrap();
+        assert!(LocalCa::load_or_create(&cert_path, &key_path, "Test CA").is_err());
+
+        fs::remove_dir_all(Path::new(&cert_path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_issued_certificate_chains_to_ca() {
+        let (cert_path, key_path) = temp_paths();
+        let created = LocalCa::load_or_create(&cert_path, &key_path, "Test CA").unwrap();
+        drop(created);
+
+        // Issue from a reloaded CA, as `enroll` does
+        let ca = LocalCa::load_or_create(&cert_path, &key_path, "Test CA").unwrap();
+        let issued = ca.issue_client_certificate("pixel-5", 30).unwrap();
+
+        let (_, cert) = x509_parser::parse_x509_certificate(&issued.cert_der).unwrap();
+        let (_, ca_cert) = x509_parser::parse_x509_certificate(ca.cert_der()).unwrap();
+        assert_eq!(cert.issuer(), ca_cert.subject());
+        assert!(cert.subject().to_string().contains("pixel-5"));
+        cert.verify_signature(Some(ca_cert.public_key())).unwrap();
+
+        fs::remove_dir_all(Path::new(&cert_path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_export_pkcs12() {
+        let (cert_path, key_path) = temp_paths();
+        let ca = LocalCa::load_or_create(&cert_path, &key_path, "Test CA").unwrap();
+        let issued = ca.issue_client_certificate("pixel-5", 30).unwrap();
+
+        let bytes = ca.export_pkcs12(&issued, "pixel-5", "changeit").unwrap();
+        let keystore =
+            KeyStore::from_pkcs12(&bytes, "changeit", Pkcs12ImportPolicy::Relaxed).unwrap();
+        let (_, chain) = keystore.private_key_chain().unwrap();
+        assert_eq!(chain.key().as_der(), issued.key_der.as_slice());
+        assert_eq!(chain.certs().len(), 2);
+        assert_eq!(chain.certs()[0].as_der(), issued.cert_der.as_slice());
+
+        assert!(KeyStore::from_pkcs12(&bytes, "wrong", Pkcs12ImportPolicy::Relaxed).is_err());
+
+        fs::remove_dir_all(Path::new(&cert_path).parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_pkcs12_file_name() {
+        assert_eq!(pkcs12_file_name("pixel-8"), "pixel-8.p12");
+        assert_eq!(pkcs12_file_name("../x"), "_x.p12");
+        assert_eq!(pkcs12_file_name("a/b"), "a_b.p12");
+        assert_eq!(pkcs12_file_name(".."), "device.p12");
+        assert_eq!(pkcs12_file_name("Alice's phone"), "Alice_s_phone.p12");
+    }
+}
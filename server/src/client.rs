@@ -0,0 +1,764 @@
+//! A client for a running server, used by the `sessions`, `logs`, `action`,
+//! `status` and `control` commands.
+//!
+//! It talks to the server like the web dashboard does: the HTTP API for
+//! snapshots and `/ws/dashboard` for live messages. It prefers the control
+//! socket (see [`crate::control`]), which needs no login and isn't exposed
+//! to the network. Otherwise it uses the dashboard port: over TLS when
+//! `[dashboard.tls]` is enabled, trusting the certificate apps are given
+//! (see [`tls::distributed_certificate_path`]), and with HTTP Basic auth
+//! when `[dashboard.auth]` is enabled.
+
+use base64::Engine;
+use chrono::{DateTime, Local, Utc};
+use futures::{SinkExt, StreamExt};
+use http_body_util::{BodyExt, Empty};
+use hyper::body::Bytes;
+use hyper::{header, HeaderMap, Method, Request, StatusCode};
+use hyper_util::rt::TokioIo;
+use rustls::pki_types::ServerName;
+use rustls::RootCertStore;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use serde_json_path::JsonPath;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::WebSocketStream;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::dashboard_auth::SESSION_COOKIE;
+use crate::protocol::{
+    ActionResultToDashboardPayload, DashboardActionPayload, DashboardToServiceMessage,
+    ServiceToDashboardMessage, SessionInfo, SessionSummary,
+};
+use crate::{certs, tls};
+
+/// A dashboard user's login.
+#[derive(Debug, Clone)]
+pub struct Credentials {
+    pub user: String,
+    pub password: String,
+}
+
+#[derive(Debug)]
+pub enum ClientError {
+    Connect { addr: String, source: io::Error },
+    Tls(String),
+    Http(String),
+    Status { status: StatusCode, body: String },
+    Decode(String),
+    WebSocket(String),
+    Timeout(String),
+    SessionNotFound(String),
+    AmbiguousSession { query: String, matches: Vec<String> },
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Connect { addr, source } => {
+                write!(
+                    f,
+                    "failed to connect to the server at {}: {}; is it running?",
+                    addr, source
+                )
+            }
+            ClientError::Tls(message) => write!(f, "TLS error: {}", message),
+            ClientError::Http(message) => write!(f, "HTTP error: {}", message),
+            ClientError::Status { status, .. } if *status == StatusCode::UNAUTHORIZED => {
+                write!(
+                    f,
+                    "the server requires a dashboard login; pass --user and --password"
+                )
+            }
+            ClientError::Status { status, body } if body.trim().is_empty() => {
+                write!(f, "the server answered {}", status)
+            }
+            ClientError::Status { status, body } => {
+                write!(f, "the server answered {}: {}", status, body.trim())
+            }
+            ClientError::Decode(message) => {
+                write!(f, "unexpected response from the server: {}", message)
+            }
+            ClientError::WebSocket(message) => write!(f, "WebSocket error: {}", message),
+            ClientError::Timeout(message) => write!(f, "timed out {}", message),
+            ClientError::SessionNotFound(query) => write!(f, "no session matches `{}`", query),
+            ClientError::AmbiguousSession { query, matches } => {
+                write!(
+                    f,
+                    "`{}` matches several sessions: {}",
+                    query,
+                    matches.join(", ")
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ClientError {}
+
+trait Stream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Stream for T {}
+
+enum Endpoint {
+    Tcp {
+        addr: SocketAddr,
+        tls: Option<(TlsConnector, ServerName<'static>)>,
+    },
+    #[cfg(unix)]
+    Unix(PathBuf),
+}
+
+pub struct Client {
+    endpoint: Endpoint,
+    credentials: Option<Credentials>,
+    /// Login cookie set by the server, sent instead of checking the password again.
+    cookie: Mutex<Option<String>>,
+}
+
+impl Client {
+    /// A client for the server running with `config` on this machine, over
+    /// its control socket if it is listening on one.
+    pub fn local(config: &Config, credentials: Option<Credentials>) -> Result<Self, ClientError> {
+        #[cfg(unix)]
+        if !config.server.control_socket.is_empty() {
+            let path = Path::new(&config.server.control_socket);
+            if std::os::unix::net::UnixStream::connect(path).is_ok() {
+                return Ok(Self::control(path));
+            }
+        }
+        Self::new(config, credentials)
+    }
+
+    /// A client for the control socket at `path`.
+    #[cfg(unix)]
+    pub fn control(path: impl AsRef<Path>) -> Self {
+        Self {
+            endpoint: Endpoint::Unix(path.as_ref().to_path_buf()),
+            credentials: None,
+            cookie: Mutex::new(None),
+        }
+    }
+
+    /// A client for the dashboard port of the server running with `config`,
+    /// on this machine.
+    pub fn new(config: &Config, credentials: Option<Credentials>) -> Result<Self, ClientError> {
+        let ip = match config.server.bind_address.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+            Ok(IpAddr::V6(ip)) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            Ok(ip) => ip,
+            Err(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        };
+        let addr = SocketAddr::new(ip, config.server.http_port);
+
+        let tls = if config.dashboard.tls.enabled {
+            Some((tls_connector(config)?, ServerName::IpAddress(ip.into())))
+        } else {
+            None
+        };
+
+        Ok(Self {
+            endpoint: Endpoint::Tcp { addr, tls },
+            credentials,
+            cookie: Mutex::new(None),
+        })
+    }
+
+    async fn connect(&self) -> Result<Box<dyn Stream>, ClientError> {
+        let (addr, tls) = match &self.endpoint {
+            Endpoint::Tcp { addr, tls } => (addr, tls),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                let stream = tokio::net::UnixStream::connect(path)
+                    .await
+                    .map_err(|source| ClientError::Connect {
+                        addr: path.display().to_string(),
+                        source,
+                    })?;
+                return Ok(Box::new(stream));
+            }
+        };
+        let stream = TcpStream::connect(addr)
+            .await
+            .map_err(|source| ClientError::Connect {
+                addr: addr.to_string(),
+                source,
+            })?;
+
+        match tls {
+            Some((connector, server_name)) => {
+                let stream = connector
+                    .connect(server_name.clone(), stream)
+                    .await
+                    .map_err(|e| ClientError::Tls(e.to_string()))?;
+                Ok(Box::new(stream))
+            }
+            None => Ok(Box::new(stream)),
+        }
+    }
+
+    fn authorization(&self) -> Option<String> {
+        self.credentials.as_ref().map(|credentials| {
+            let encoded = base64::engine::general_purpose::STANDARD
+                .encode(format!("{}:{}", credentials.user, credentials.password));
+            format!("Basic {}", encoded)
+        })
+    }
+
+    fn cookie(&self) -> Option<String> {
+        self.cookie.lock().unwrap().clone()
+    }
+
+    fn remember_cookie(&self, headers: &HeaderMap) {
+        let login = headers
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .filter_map(|value| value.split(';').next())
+            .find(|cookie| cookie.starts_with(&format!("{}=", SESSION_COOKIE)));
+        if let Some(login) = login {
+            *self.cookie.lock().unwrap() = Some(login.to_string());
+        }
+    }
+
+    /// The authority in URLs and `Host` headers.
+    fn host(&self) -> String {
+        match &self.endpoint {
+            Endpoint::Tcp { addr, .. } => addr.to_string(),
+            #[cfg(unix)]
+            Endpoint::Unix(_) => "localhost".to_string(),
+        }
+    }
+
+    /// GETs `path` from the HTTP API.
+    pub async fn get<T: DeserializeOwned>(&self, path: &str) -> Result<T, ClientError> {
+        self.request(Method::GET, path).await
+    }
+
+    /// POSTs to `path`, without a body.
+    pub async fn post<T: DeserializeOwned>(&self, path: &str) -> Result<T, ClientError> {
+        self.request(Method::POST, path).await
+    }
+
+    async fn request<T: DeserializeOwned>(
+        &self,
+        method: Method,
+        path: &str,
+    ) -> Result<T, ClientError> {
+        let stream = self.connect().await?;
+        let (mut sender, connection) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .map_err(|e| ClientError::Http(e.to_string()))?;
+        tokio::spawn(async move {
+            let _ = connection.await;
+        });
+
+        let mut request = Request::builder()
+            .method(method)
+            .uri(path)
+            .header(header::HOST, self.host());
+        if let Some(authorization) = self.authorization() {
+            request = request.header(header::AUTHORIZATION, authorization);
+        }
+        if let Some(cookie) = self.cookie() {
+            request = request.header(header::COOKIE, cookie);
+        }
+        let request = request
+            .body(Empty::<Bytes>::new())
+            .map_err(|e| ClientError::Http(e.to_string()))?;
+
+        let response = sender
+            .send_request(request)
+            .await
+            .map_err(|e| ClientError::Http(e.to_string()))?;
+        self.remember_cookie(response.headers());
+        let status = response.status();
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .map_err(|e| ClientError::Http(e.to_string()))?
+            .to_bytes();
+
+        if !status.is_success() {
+            return Err(ClientError::Status {
+                status,
+                body: String::from_utf8_lossy(&body).into_owned(),
+            });
+        }
+        serde_json::from_slice(&body).map_err(|e| ClientError::Decode(e.to_string()))
+    }
+
+    /// Active and recently ended sessions, oldest first.
+    pub async fn sessions(&self) -> Result<Vec<SessionSummary>, ClientError> {
+        self.get("/api/sessions").await
+    }
+
+    pub async fn session(&self, session_id: &str) -> Result<SessionInfo, ClientError> {
+        self.get(&format!("/api/sessions/{}", session_id)).await
+    }
+
+    /// Looks up the session `query` refers to, see [`find_session`].
+    pub async fn find_session(&self, query: &str) -> Result<SessionSummary, ClientError> {
+        let sessions = self.sessions().await?;
+        find_session(&sessions, query).cloned()
+    }
+
+    /// Connects to `/ws/dashboard`, which starts with a SYNC of the active
+    /// sessions.
+    pub async fn dashboard(&self) -> Result<DashboardConnection, ClientError> {
+        let stream = self.connect().await?;
+
+        let scheme = match &self.endpoint {
+            Endpoint::Tcp { tls: Some(_), .. } => "wss",
+            _ => "ws",
+        };
+        let mut request = format!("{}://{}/ws/dashboard", scheme, self.host())
+            .into_client_request()
+            .map_err(|e| ClientError::WebSocket(e.to_string()))?;
+        if let Some(authorization) = self.authorization() {
+            let value = authorization
+                .parse()
+                .map_err(|_| ClientError::WebSocket("invalid credentials".to_string()))?;
+            request.headers_mut().insert(header::AUTHORIZATION, value);
+        }
+        if let Some(cookie) = self.cookie().and_then(|cookie| cookie.parse().ok()) {
+            request.headers_mut().insert(header::COOKIE, cookie);
+        }
+
+        match tokio_tungstenite::client_async(request, stream).await {
+            Ok((socket, _)) => Ok(DashboardConnection { socket }),
+            Err(tungstenite::Error::Http(response)) => Err(ClientError::Status {
+                status: response.status(),
+                body: String::from_utf8_lossy(response.body().as_deref().unwrap_or_default())
+                    .into_owned(),
+            }),
+            Err(e) => Err(ClientError::WebSocket(e.to_string())),
+        }
+    }
+}
+
+/// A dashboard WebSocket connection, speaking JSON.
+pub struct DashboardConnection {
+    socket: WebSocketStream<Box<dyn Stream>>,
+}
+
+impl DashboardConnection {
+    /// The next message from the server, or `None` once it closes the
+    /// connection.
+    pub async fn next(&mut self) -> Option<Result<ServiceToDashboardMessage, ClientError>> {
+        loop {
+            let text = match self.socket.next().await? {
+                Ok(Message::Text(text)) => text,
+                Ok(Message::Close(_)) => return None,
+                Ok(_) => continue,
+                Err(e) => return Some(Err(ClientError::WebSocket(e.to_string()))),
+            };
+            return Some(
+                serde_json::from_str(&text).map_err(|e| ClientError::Decode(e.to_string())),
+            );
+        }
+    }
+
+    pub async fn send(&mut self, message: &DashboardToServiceMessage) -> Result<(), ClientError> {
+        let text =
+            serde_json::to_string(message).map_err(|e| ClientError::Decode(e.to_string()))?;
+        self.socket
+            .send(Message::Text(text))
+            .await
+            .map_err(|e| ClientError::WebSocket(e.to_string()))
+    }
+
+    /// Sends an ACTION to a session and waits up to `timeout` for its
+    /// ACTION_RESULT, which may report a failure.
+    pub async fn run_action(
+        &mut self,
+        session_id: &str,
+        action: &str,
+        args: Option<Value>,
+        timeout: Duration,
+    ) -> Result<ActionResultToDashboardPayload, ClientError> {
+        let action_id = Uuid::new_v4().to_string();
+        self.send(&DashboardToServiceMessage::Action {
+            payload: DashboardActionPayload {
+                session_id: session_id.to_string(),
+                action_id: action_id.clone(),
+                action: action.to_string(),
+                args,
+            },
+        })
+        .await?;
+
+        let result = tokio::time::timeout(timeout, async {
+            while let Some(message) = self.next().await {
+                match message? {
+                    ServiceToDashboardMessage::ActionResult { payload, .. }
+                        if payload.action_id == action_id =>
+                    {
+                        return Ok(payload);
+                    }
+                    ServiceToDashboardMessage::SessionEnded { payload, .. }
+                        if payload.session_id == session_id =>
+                    {
+                        return Err(ClientError::WebSocket(
+                            "the session ended before answering".to_string(),
+                        ));
+                    }
+                    _ => {}
+                }
+            }
+            Err(ClientError::WebSocket(
+                "the server closed the connection".to_string(),
+            ))
+        })
+        .await;
+
+        result.unwrap_or_else(|_| {
+            Err(ClientError::Timeout(format!(
+                "after {}s waiting for the result of `{}`",
+                timeout.as_secs_f64(),
+                action
+            )))
+        })
+    }
+}
+
+/// Whether `query` selects the session: its id, a prefix of the id, or the
+/// package name of its app.
+pub fn session_matches(session_id: &str, package_name: &str, query: &str) -> bool {
+    session_id.starts_with(query) || package_name == query
+}
+
+fn tls_connector(config: &Config) -> Result<TlsConnector, ClientError> {
+    let tls_error = |e: &dyn fmt::Display| ClientError::Tls(e.to_string());
+
+    let mut roots = RootCertStore::empty();
+    let path = tls::distributed_certificate_path(&config.server.tls);
+    for cert in certs::load_certificates(path).map_err(|e| tls_error(&e))? {
+        roots.add(cert).map_err(|e| tls_error(&e))?;
+    }
+
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let mut client_config = rustls::ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| tls_error(&e))?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    client_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(TlsConnector::from(Arc::new(client_config)))
+}
+
+/// Finds the session `query` refers to: its id, a prefix of the id, or the
+/// package name of its app. When several match, an active session wins over
+/// ended ones.
+pub fn find_session<'a>(
+    sessions: &'a [SessionSummary],
+    query: &str,
+) -> Result<&'a SessionSummary, ClientError> {
+    if let Some(session) = sessions.iter().find(|session| session.session_id == query) {
+        return Ok(session);
+    }
+
+    let matches: Vec<&SessionSummary> = sessions
+        .iter()
+        .filter(|session| session_matches(&session.session_id, &session.package_name, query))
+        .collect();
+    let active: Vec<&SessionSummary> = matches
+        .iter()
+        .copied()
+        .filter(|session| session.ended_at.is_none())
+        .collect();
+
+    match (matches.as_slice(), active.as_slice()) {
+        ([], _) => Err(ClientError::SessionNotFound(query.to_string())),
+        ([session], _) | (_, [session]) => Ok(session),
+        _ => Err(ClientError::AmbiguousSession {
+            query: query.to_string(),
+            matches: matches
+                .iter()
+                .map(|session| short_id(&session.session_id).to_string())
+                .collect(),
+        }),
+    }
+}
+
+/// Picks the active session an ACTION goes to: the only one matching
+/// `query` (see [`session_matches`]) and `device`, a device id or model.
+/// Without either, there must be a single active session.
+pub fn select_active_session<'a>(
+    sessions: &'a [SessionInfo],
+    query: Option<&str>,
+    device: Option<&str>,
+) -> Result<&'a SessionInfo, ClientError> {
+    let matches: Vec<&SessionInfo> = sessions
+        .iter()
+        .filter(|session| session.ended_at.is_none())
+        .filter(|session| {
+            query.is_none_or(|query| {
+                session_matches(&session.session_id, &session.package_name, query)
+            })
+        })
+        .filter(|session| {
+            device.is_none_or(|device| {
+                session.device.device_id == device
+                    || session.device.model.eq_ignore_ascii_case(device)
+            })
+        })
+        .collect();
+
+    let selection = || match (query, device) {
+        (Some(query), Some(device)) => format!("{} on {}", query, device),
+        (Some(selection), None) | (None, Some(selection)) => selection.to_string(),
+        (None, None) => "any active session".to_string(),
+    };
+    match matches.as_slice() {
+        [] => Err(ClientError::SessionNotFound(selection())),
+        [session] => Ok(session),
+        _ => Err(ClientError::AmbiguousSession {
+            query: selection(),
+            matches: matches
+                .iter()
+                .map(|session| {
+                    format!(
+                        "{} ({} on {})",
+                        short_id(&session.session_id),
+                        session.package_name,
+                        session.device.model
+                    )
+                })
+                .collect(),
+        }),
+    }
+}
+
+/// The start of a session id, enough to tell sessions apart.
+pub fn short_id(session_id: &str) -> &str {
+    session_id.get(..8).unwrap_or(session_id)
+}
+
+/// Renders sessions as a table with a header row.
+pub fn sessions_table(sessions: &[SessionSummary]) -> String {
+    let time = |time: DateTime<Utc>| {
+        time.with_timezone(&Local)
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()
+    };
+
+    let header = [
+        "SESSION", "APP", "PACKAGE", "VERSION", "DEVICE", "STARTED", "ENDED",
+    ]
+    .map(String::from);
+    let rows: Vec<[String; 7]> = sessions
+        .iter()
+        .map(|session| {
+            let device = &session.device;
+            let mut device_name = format!("{} {}", device.manufacturer, device.model);
+            if device.is_emulator {
+                device_name.push_str(" (emulator)");
+            }
+            [
+                short_id(&session.session_id).to_string(),
+                session.app_name.clone(),
+                session.package_name.clone(),
+                session.version_name.clone(),
+                device_name,
+                time(session.started_at),
+                session
+                    .ended_at
+                    .map(time)
+                    .unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+
+    let mut widths = header.clone().map(|column| column.chars().count());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let mut table = String::new();
+    for row in std::iter::once(&header).chain(&rows) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        table.push_str(line.join("  ").trim_end());
+        table.push('\n');
+    }
+    table
+}
+
+/// Selects part of `data` with a JSONPath expression: the single node it
+/// matches, or an array when it matches several.
+pub fn select(data: &Value, path: &str) -> Result<Value, String> {
+    let path = JsonPath::parse(path).map_err(|e| format!("invalid JSONPath `{}`: {}", path, e))?;
+    let nodes = path.query(data).all();
+
+    match nodes.as_slice() {
+        [] => Err(format!("`{}` matches nothing", path)),
+        [node] => Ok((*node).clone()),
+        nodes => Ok(Value::Array(
+            nodes.iter().map(|node| (*node).clone()).collect(),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::DeviceInfo;
+    use serde_json::json;
+
+    fn session(session_id: &str, package_name: &str, ended: bool) -> SessionSummary {
+        SessionSummary {
+            session_id: session_id.to_string(),
+            app_name: "Sample".to_string(),
+            package_name: package_name.to_string(),
+            version_name: "1.0".to_string(),
+            device: DeviceInfo {
+                device_id: "device-1".to_string(),
+                manufacturer: "Google".to_string(),
+                model: "Pixel 8".to_string(),
+                android_version: "14".to_string(),
+                api_level: 34,
+                is_emulator: true,
+            },
+            started_at: Utc::now(),
+            ended_at: ended.then(Utc::now),
+        }
+    }
+
+    #[test]
+    fn test_find_session() {
+        let sessions = vec![
+            session("aaaa1111-0000", "com.example.app", true),
+            session("aaaa2222-0000", "com.example.app", false),
+            session("bbbb3333-0000", "com.example.other", false),
+            session("bbbb4444-0000", "com.example.other", false),
+        ];
+
+        let found =
+            |query| find_session(&sessions, query).map(|session| session.session_id.as_str());
+        assert_eq!(found("aaaa1111-0000").unwrap(), "aaaa1111-0000");
+        assert_eq!(found("bbbb3").unwrap(), "bbbb3333-0000");
+        // The ended session of the same app is ignored
+        assert_eq!(found("com.example.app").unwrap(), "aaaa2222-0000");
+        assert!(matches!(
+            found("com.example.other"),
+            Err(ClientError::AmbiguousSession { .. })
+        ));
+        assert!(matches!(
+            found("cccc"),
+            Err(ClientError::SessionNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn test_select_active_session() {
+        let info = |session_id: &str, package_name: &str, model: &str, ended: bool| {
+            let summary = session(session_id, package_name, ended);
+            let mut device = summary.device;
+            device.model = model.to_string();
+            device.device_id = format!("{}-id", model);
+            SessionInfo {
+                session_id: summary.session_id,
+                app_name: summary.app_name,
+                package_name: summary.package_name,
+                version_name: summary.version_name,
+                device,
+                dashboard: json!({}),
+                started_at: summary.started_at,
+                ended_at: summary.ended_at,
+                latest_data: None,
+                recent_logs: Vec::new(),
+                attachments: Vec::new(),
+            }
+        };
+        let sessions = vec![
+            info("aaaa1111", "com.example.app", "Pixel 8", false),
+            info("bbbb2222", "com.example.app", "Galaxy S24", false),
+            info("cccc3333", "com.example.other", "Pixel 8", true),
+        ];
+
+        let selected = |query, device| {
+            select_active_session(&sessions, query, device)
+                .map(|session| session.session_id.as_str())
+        };
+        assert_eq!(
+            selected(Some("com.example.app"), Some("pixel 8")).unwrap(),
+            "aaaa1111"
+        );
+        assert_eq!(selected(None, Some("Galaxy S24-id")).unwrap(), "bbbb2222");
+        assert_eq!(selected(Some("bbbb"), None).unwrap(), "bbbb2222");
+        match selected(Some("com.example.app"), None) {
+            Err(ClientError::AmbiguousSession { matches, .. }) => {
+                assert_eq!(
+                    matches,
+                    vec![
+                        "aaaa1111 (com.example.app on Pixel 8)",
+                        "bbbb2222 (com.example.app on Galaxy S24)"
+                    ]
+                );
+            }
+            other => panic!("Expected an ambiguous selection, got {:?}", other),
+        }
+        // Ended sessions can't run actions
+        assert!(matches!(
+            selected(Some("com.example.other"), None),
+            Err(ClientError::SessionNotFound(_))
+        ));
+        assert!(matches!(
+            selected(None, None),
+            Err(ClientError::AmbiguousSession { .. })
+        ));
+    }
+
+    #[test]
+    fn test_sessions_table() {
+        let table = sessions_table(&[
+            session("aaaa1111-0000", "com.example.app", false),
+            session("bbbb2222-0000", "com.example.other", true),
+        ]);
+        let lines: Vec<&str> = table.lines().collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("SESSION   APP     PACKAGE "));
+        assert!(lines[1].starts_with("aaaa1111  Sample  com.example.app "));
+        assert!(lines[1].contains("Google Pixel 8 (emulator)"));
+        assert!(lines[1].ends_with(" -"));
+        assert!(!lines[2].ends_with(" -"));
+    }
+
+    #[test]
+    fn test_select() {
+        let data =
+            json!({"memory": {"heap_used": 12}, "threads": [{"name": "main"}, {"name": "io"}]});
+
+        assert_eq!(select(&data, "$.memory.heap_used").unwrap(), json!(12));
+        assert_eq!(
+            select(&data, "$.threads[*].name").unwrap(),
+            json!(["main", "io"])
+        );
+        assert!(select(&data, "$.missing")
+            .unwrap_err()
+            .contains("matches nothing"));
+        assert!(select(&data, "memory")
+            .unwrap_err()
+            .contains("invalid JSONPath"));
+    }
+}
@@ -1,8 +1,15 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use tracing_subscriber::filter::LevelFilter;
 
-#[derive(Debug, Clone, Deserialize)]
+use crate::discovery::DISCOVERY_PORT;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     #[serde(default)]
     pub server: ServerConfig,
@@ -10,9 +17,14 @@ pub struct Config {
     pub session: SessionConfig,
     #[serde(default)]
     pub logging: LoggingConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub dashboard: DashboardConfig,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct ServerConfig {
     #[serde(default = "default_websocket_port")]
     pub websocket_port: u16,
@@ -24,11 +36,52 @@ pub struct ServerConfig {
     pub max_connections: usize,
     #[serde(default = "default_udp_discovery")]
     pub udp_discovery_enabled: bool,
+    /// Advertise the server as `_androidoscopy._tcp` over mDNS/DNS-SD.
+    #[serde(default = "default_mdns")]
+    pub mdns_enabled: bool,
+    /// Unix socket for the command line, see [`crate::control`]; empty
+    /// disables it.
+    #[serde(default = "default_control_socket")]
+    pub control_socket: String,
+    #[serde(default)]
+    pub discovery: DiscoveryConfig,
     #[serde(default)]
     pub tls: TlsConfig,
+    #[serde(default)]
+    pub auth: AuthConfig,
+}
+
+/// Which interfaces UDP discovery and mDNS announce the server on.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DiscoveryConfig {
+    /// Interface names to announce on; empty means all of them.
+    #[serde(default)]
+    pub interfaces: Vec<String>,
+    /// Interface names never to announce on. A trailing `*` matches any
+    /// suffix; by default container and VM bridges are skipped.
+    #[serde(default = "default_exclude_interfaces")]
+    pub exclude_interfaces: Vec<String>,
+    /// Also announce over IPv6 multicast.
+    #[serde(default = "default_discovery_ipv6")]
+    pub ipv6_enabled: bool,
+}
+
+/// Pairing-token authentication for app connections, see [`crate::auth`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Fixed token; when unset, a generated one is stored at `token_path`.
+    #[serde(default)]
+    pub token: Option<String>,
+    #[serde(default = "default_token_path")]
+    pub token_path: String,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct TlsConfig {
     #[serde(default = "default_tls_enabled")]
     pub enabled: bool,
@@ -36,11 +89,46 @@ pub struct TlsConfig {
     pub cert_path: String,
     #[serde(default = "default_key_path")]
     pub key_path: String,
+    /// Password of an encrypted (`ENCRYPTED PRIVATE KEY`) key at `key_path`.
+    #[serde(default)]
+    pub key_password: Option<String>,
+    /// Issue the server certificate from a local root CA, re-issuing it when
+    /// the local IPs change or it nears expiry. Disable to use your own files.
     #[serde(default = "default_auto_generate")]
     pub auto_generate: bool,
+    #[serde(default = "default_server_ca_cert_path")]
+    pub ca_cert_path: String,
+    #[serde(default = "default_server_ca_key_path")]
+    pub ca_key_path: String,
+    /// Validity of the server certificates issued by the local root CA.
+    #[serde(default = "default_leaf_validity_days")]
+    pub leaf_validity_days: u32,
+    /// Host names the issued server certificate covers on top of localhost
+    /// and the local IPs, e.g. the name the dashboard is opened with.
+    #[serde(default)]
+    pub extra_hostnames: Vec<String>,
+    #[serde(default)]
+    pub client_auth: ClientAuthConfig,
+}
+
+/// Mutual TLS for app connections: only devices holding a client certificate
+/// issued by the server's device CA can connect. See [`crate::ca`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClientAuthConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_device_ca_cert_path")]
+    pub ca_cert_path: String,
+    #[serde(default = "default_device_ca_key_path")]
+    pub ca_key_path: String,
+    /// Validity of client certificates issued by `androidoscopy enroll`.
+    #[serde(default = "default_client_cert_days")]
+    pub cert_validity_days: u32,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct SessionConfig {
     #[serde(default = "default_ended_session_ttl")]
     pub ended_session_ttl_seconds: u64,
@@ -48,12 +136,122 @@ pub struct SessionConfig {
     pub data_buffer_size: usize,
     #[serde(default = "default_log_buffer_size")]
     pub log_buffer_size: usize,
+    /// Directory where files uploaded by apps are stored, one subdirectory per session.
+    #[serde(default = "default_attachments_dir")]
+    pub attachments_dir: String,
+    #[serde(default = "default_max_attachments_per_session")]
+    pub max_attachments_per_session: usize,
+    #[serde(default = "default_attachment_quota_bytes")]
+    pub attachment_quota_bytes: u64,
+}
+
+/// Size limits enforced on messages from apps. Sizes are in bytes.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Largest WebSocket message accepted, after decompression for zstd frames.
+    #[serde(default = "default_max_message_size")]
+    pub max_message_size: usize,
+    #[serde(default = "default_max_log_message_size")]
+    pub max_log_message_size: usize,
+    #[serde(default = "default_max_log_throwable_size")]
+    pub max_log_throwable_size: usize,
+    /// Largest DATA payload, measured as serialized JSON.
+    #[serde(default = "default_max_data_size")]
+    pub max_data_size: usize,
+    /// Deepest nesting of objects and arrays allowed in a DATA payload.
+    #[serde(default = "default_max_data_depth")]
+    pub max_data_depth: usize,
+    /// Largest ACTION_RESULT `data`, measured as serialized JSON.
+    #[serde(default = "default_max_action_result_size")]
+    pub max_action_result_size: usize,
+    /// Largest base64 `data` field of a BLOB_CHUNK.
+    #[serde(default = "default_max_blob_chunk_size")]
+    pub max_blob_chunk_size: usize,
+    #[serde(default = "default_max_blob_size")]
+    pub max_blob_size: u64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DashboardConfig {
+    #[serde(default)]
+    pub auth: DashboardAuthConfig,
+    #[serde(default)]
+    pub tls: DashboardTlsConfig,
+}
+
+/// Serves the dashboard over HTTPS on `http_port`, with the certificate from
+/// `[server.tls]`.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DashboardTlsConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Port of a plain HTTP listener redirecting to the HTTPS dashboard.
+    #[serde(default)]
+    pub redirect_port: Option<u16>,
+}
+
+/// Login for the dashboard, see [`crate::dashboard_auth`].
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct DashboardAuthConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// How long a login stays valid.
+    #[serde(default = "default_login_ttl_hours")]
+    pub login_ttl_hours: u64,
+    #[serde(default)]
+    pub users: Vec<UserConfig>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    pub name: String,
+    /// An Argon2 hash from `androidoscopy hash-password`, or the password
+    /// itself.
+    pub password: String,
+    #[serde(default)]
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    /// Can watch sessions but not send ACTIONs to apps.
+    #[default]
+    ReadOnly,
+    ReadWrite,
+}
+
+/// Server logs, see [`crate::logging`].
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct LoggingConfig {
+    /// trace, debug, info, warn, error or off; `RUST_LOG` overrides it.
     #[serde(default = "default_log_level")]
     pub level: String,
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Also write logs to this file, rotated by size.
+    #[serde(default)]
+    pub file: Option<String>,
+    #[serde(default = "default_log_max_file_size")]
+    pub max_file_size: u64,
+    /// How many rotated files to keep besides the current one.
+    #[serde(default = "default_log_max_files")]
+    pub max_files: usize,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    /// One JSON object per line.
+    Json,
 }
 
 fn default_websocket_port() -> u16 {
@@ -76,6 +274,28 @@ fn default_udp_discovery() -> bool {
     true
 }
 
+fn default_mdns() -> bool {
+    true
+}
+
+fn default_control_socket() -> String {
+    let base = dirs::runtime_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("androidoscopy");
+    base.join("control.sock").to_string_lossy().to_string()
+}
+
+fn default_exclude_interfaces() -> Vec<String> {
+    ["docker*", "br-*", "veth*", "virbr*"]
+        .iter()
+        .map(|name| name.to_string())
+        .collect()
+}
+
+fn default_discovery_ipv6() -> bool {
+    true
+}
+
 fn default_tls_enabled() -> bool {
     true
 }
@@ -94,10 +314,53 @@ fn default_key_path() -> String {
     base.join("key.pem").to_string_lossy().to_string()
 }
 
+fn default_server_ca_cert_path() -> String {
+    let base = dirs::data_local_dir()
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+        .join("androidoscopy");
+    base.join("ca.pem").to_string_lossy().to_string()
+}
+
+fn default_server_ca_key_path() -> String {
+    let base = dirs::data_local_dir()
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+        .join("androidoscopy");
+    base.join("ca-key.pem").to_string_lossy().to_string()
+}
+
+fn default_leaf_validity_days() -> u32 {
+    30
+}
+
+fn default_device_ca_cert_path() -> String {
+    let base = dirs::data_local_dir()
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+        .join("androidoscopy");
+    base.join("device-ca.pem").to_string_lossy().to_string()
+}
+
+fn default_device_ca_key_path() -> String {
+    let base = dirs::data_local_dir()
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+        .join("androidoscopy");
+    base.join("device-ca-key.pem").to_string_lossy().to_string()
+}
+
+fn default_client_cert_days() -> u32 {
+    365
+}
+
 fn default_auto_generate() -> bool {
     true
 }
 
+fn default_token_path() -> String {
+    let base = dirs::data_local_dir()
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+        .join("androidoscopy");
+    base.join("pairing_token").to_string_lossy().to_string()
+}
+
 fn default_ended_session_ttl() -> u64 {
     3600
 }
@@ -110,18 +373,67 @@ fn default_log_buffer_size() -> usize {
     50000
 }
 
+fn default_attachments_dir() -> String {
+    let base = dirs::data_local_dir()
+        .unwrap_or_else(|| std::path::PathBuf::from("."))
+        .join("androidoscopy");
+    base.join("attachments").to_string_lossy().to_string()
+}
+
+fn default_max_attachments_per_session() -> usize {
+    100
+}
+
+fn default_attachment_quota_bytes() -> u64 {
+    256 * 1024 * 1024
+}
+
+fn default_max_message_size() -> usize {
+    1024 * 1024
+}
+
+fn default_max_log_message_size() -> usize {
+    64 * 1024
+}
+
+fn default_max_log_throwable_size() -> usize {
+    256 * 1024
+}
+
+fn default_max_data_size() -> usize {
+    512 * 1024
+}
+
+fn default_max_data_depth() -> usize {
+    32
+}
+
+fn default_max_action_result_size() -> usize {
+    512 * 1024
+}
+
+fn default_max_blob_chunk_size() -> usize {
+    512 * 1024
+}
+
+fn default_max_blob_size() -> u64 {
+    64 * 1024 * 1024
+}
+
+fn default_login_ttl_hours() -> u64 {
+    24 * 7
+}
+
 fn default_log_level() -> String {
     "info".to_string()
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            server: ServerConfig::default(),
-            session: SessionConfig::default(),
-            logging: LoggingConfig::default(),
-        }
-    }
+fn default_log_max_file_size() -> u64 {
+    10 * 1024 * 1024
+}
+
+fn default_log_max_files() -> usize {
+    5
 }
 
 impl Default for ServerConfig {
@@ -132,7 +444,21 @@ impl Default for ServerConfig {
             bind_address: default_bind_address(),
             max_connections: default_max_connections(),
             udp_discovery_enabled: default_udp_discovery(),
+            mdns_enabled: default_mdns(),
+            control_socket: default_control_socket(),
+            discovery: DiscoveryConfig::default(),
             tls: TlsConfig::default(),
+            auth: AuthConfig::default(),
+        }
+    }
+}
+
+impl Default for DiscoveryConfig {
+    fn default() -> Self {
+        Self {
+            interfaces: Vec::new(),
+            exclude_interfaces: default_exclude_interfaces(),
+            ipv6_enabled: default_discovery_ipv6(),
         }
     }
 }
@@ -143,7 +469,34 @@ impl Default for TlsConfig {
             enabled: default_tls_enabled(),
             cert_path: default_cert_path(),
             key_path: default_key_path(),
+            key_password: None,
             auto_generate: default_auto_generate(),
+            ca_cert_path: default_server_ca_cert_path(),
+            ca_key_path: default_server_ca_key_path(),
+            leaf_validity_days: default_leaf_validity_days(),
+            extra_hostnames: Vec::new(),
+            client_auth: ClientAuthConfig::default(),
+        }
+    }
+}
+
+impl Default for ClientAuthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            ca_cert_path: default_device_ca_cert_path(),
+            ca_key_path: default_device_ca_key_path(),
+            cert_validity_days: default_client_cert_days(),
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            token: None,
+            token_path: default_token_path(),
         }
     }
 }
@@ -154,6 +507,34 @@ impl Default for SessionConfig {
             ended_session_ttl_seconds: default_ended_session_ttl(),
             data_buffer_size: default_data_buffer_size(),
             log_buffer_size: default_log_buffer_size(),
+            attachments_dir: default_attachments_dir(),
+            max_attachments_per_session: default_max_attachments_per_session(),
+            attachment_quota_bytes: default_attachment_quota_bytes(),
+        }
+    }
+}
+
+impl Default for DashboardAuthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            login_ttl_hours: default_login_ttl_hours(),
+            users: Vec::new(),
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_message_size: default_max_message_size(),
+            max_log_message_size: default_max_log_message_size(),
+            max_log_throwable_size: default_max_log_throwable_size(),
+            max_data_size: default_max_data_size(),
+            max_data_depth: default_max_data_depth(),
+            max_action_result_size: default_max_action_result_size(),
+            max_blob_chunk_size: default_max_blob_chunk_size(),
+            max_blob_size: default_max_blob_size(),
         }
     }
 }
@@ -162,24 +543,310 @@ impl Default for LoggingConfig {
     fn default() -> Self {
         Self {
             level: default_log_level(),
+            format: LogFormat::default(),
+            file: None,
+            max_file_size: default_log_max_file_size(),
+            max_files: default_log_max_files(),
+        }
+    }
+}
+
+/// The config file written by `androidoscopy config init`: every setting with
+/// its default value and a short explanation.
+pub const DEFAULT_CONFIG: &str = include_str!("default_config.toml");
+
+/// Prefix of environment variables overriding config keys, with `__`
+/// separating tables: `ANDROIDOSCOPY_SERVER__HTTP_PORT=9000` sets
+/// `server.http_port`.
+pub const ENV_PREFIX: &str = "ANDROIDOSCOPY_";
+
+/// A config key set from the command line or the environment, layered over
+/// the config file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigOverride {
+    /// Dotted key, e.g. `server.tls.enabled`.
+    pub key: String,
+    /// The value in TOML syntax; anything that isn't valid TOML is taken as
+    /// a plain string.
+    pub value: String,
+    /// Where the override comes from, for error messages.
+    pub origin: String,
+}
+
+impl ConfigOverride {
+    pub fn new(
+        key: impl Into<String>,
+        value: impl Into<String>,
+        origin: impl Into<String>,
+    ) -> Self {
+        Self {
+            key: key.into(),
+            value: value.into(),
+            origin: origin.into(),
+        }
+    }
+
+    /// Parses a `key=value` assignment.
+    pub fn parse(assignment: &str, origin: impl Into<String>) -> Result<Self, String> {
+        match assignment.split_once('=') {
+            Some((key, value)) if !key.trim().is_empty() => {
+                Ok(Self::new(key.trim(), value.trim(), origin))
+            }
+            _ => Err(format!("expected KEY=VALUE, got `{}`", assignment)),
+        }
+    }
+
+    /// Overrides from `ANDROIDOSCOPY_<TABLE>__<KEY>` variables. Variables
+    /// without a `__`, like `ANDROIDOSCOPY_CONFIG`, aren't config keys.
+    pub fn from_env(vars: impl IntoIterator<Item = (String, String)>) -> Vec<Self> {
+        let mut overrides: Vec<Self> = vars
+            .into_iter()
+            .filter_map(|(name, value)| {
+                let key = name.strip_prefix(ENV_PREFIX)?;
+                if !key.contains("__") {
+                    return None;
+                }
+                Some(Self::new(
+                    key.to_lowercase().replace("__", "."),
+                    value,
+                    name,
+                ))
+            })
+            .collect();
+        // The environment has no order of its own
+        overrides.sort_by(|a, b| a.origin.cmp(&b.origin));
+        overrides
+    }
+
+    /// The value to set: typed if it parses as TOML and is accepted for the
+    /// key, otherwise a string, so `token=123` still sets a string.
+    fn resolve(&self) -> Result<toml::Value, ConfigError> {
+        let path: Vec<&str> = self.key.split('.').collect();
+        if path.iter().any(|part| part.is_empty()) {
+            return Err(self.error(format!("invalid key `{}`", self.key)));
+        }
+
+        let typed = toml::from_str::<toml::Table>(&format!("value = {}", self.value))
+            .ok()
+            .and_then(|mut table| table.remove("value"));
+        let string = toml::Value::String(self.value.clone());
+
+        let mut first_error = None;
+        for value in typed.into_iter().chain([string]) {
+            // Checked on its own, so the error can only be about this key
+            let mut table = toml::Table::new();
+            insert(&mut table, &path, value.clone()).map_err(|message| self.error(message))?;
+            match toml::Value::Table(table).try_into::<Config>() {
+                Ok(_) => return Ok(value),
+                Err(e) => {
+                    first_error.get_or_insert(e.message().to_string());
+                }
+            }
+        }
+
+        Err(self.error(first_error.unwrap_or_default()))
+    }
+
+    fn error(&self, message: String) -> ConfigError {
+        ConfigError::Override {
+            origin: self.origin.clone(),
+            key: self.key.clone(),
+            message,
+        }
+    }
+}
+
+/// Sets `path` in `table`, creating the tables on the way.
+pub(crate) fn insert(
+    table: &mut toml::Table,
+    path: &[&str],
+    value: toml::Value,
+) -> Result<(), String> {
+    let (last, parents) = path.split_last().ok_or("empty key")?;
+    let mut current = table;
+    for (i, part) in parents.iter().enumerate() {
+        let entry = current
+            .entry(part.to_string())
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+        current = match entry {
+            toml::Value::Table(child) => child,
+            _ => return Err(format!("`{}` is not a table", parents[..=i].join("."))),
+        };
+    }
+    current.insert(last.to_string(), value);
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    NotFound {
+        path: PathBuf,
+    },
+    Read {
+        path: PathBuf,
+        source: io::Error,
+    },
+    /// Invalid TOML, an unknown key or a value of the wrong type in the file.
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    Override {
+        origin: String,
+        key: String,
+        message: String,
+    },
+    /// The file and the overrides are fine separately but not together.
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NotFound { path } => write!(f, "config file {} not found", path.display()),
+            ConfigError::Read { path, source } => {
+                write!(f, "failed to read {}: {}", path.display(), source)
+            }
+            // The TOML error already shows the line and the key at fault
+            ConfigError::Parse { path, source } => {
+                write!(f, "invalid config file {}:\n{}", path.display(), source)
+            }
+            ConfigError::Override {
+                origin,
+                key,
+                message,
+            } => {
+                write!(
+                    f,
+                    "invalid value for `{}` from {}: {}",
+                    key, origin, message
+                )
+            }
+            ConfigError::Invalid(message) => write!(f, "invalid config: {}", message),
         }
     }
 }
 
+impl std::error::Error for ConfigError {}
+
 impl Config {
-    pub fn load() -> Result<Self, Box<dyn std::error::Error>> {
-        let config_path = Self::config_path();
+    /// Loads the default config file, if there is one.
+    pub fn load() -> Result<Self, ConfigError> {
+        Self::load_with(None, &[])
+    }
+
+    /// Loads `path`, which has to exist, or else the default config file if
+    /// there is one, then applies `overrides` in order.
+    pub fn load_with(
+        path: Option<&Path>,
+        overrides: &[ConfigOverride],
+    ) -> Result<Self, ConfigError> {
+        let default_path = Self::config_path();
+        let path = path.unwrap_or(&default_path);
+
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && path == default_path => String::new(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                return Err(ConfigError::NotFound {
+                    path: path.to_path_buf(),
+                })
+            }
+            Err(source) => {
+                return Err(ConfigError::Read {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        };
+
+        Self::parse(&content, path, overrides)
+    }
+
+    /// Parses the `content` of the config file at `path`.
+    fn parse(
+        content: &str,
+        path: &Path,
+        overrides: &[ConfigOverride],
+    ) -> Result<Self, ConfigError> {
+        let parse_error = |source| ConfigError::Parse {
+            path: path.to_path_buf(),
+            source,
+        };
+
+        // Deserializing the text directly keeps line numbers in the errors
+        let config: Config = toml::from_str(content).map_err(parse_error)?;
+        if overrides.is_empty() {
+            config.validate()?;
+            return Ok(config);
+        }
+
+        let mut table: toml::Table = toml::from_str(content).map_err(parse_error)?;
+        for config_override in overrides {
+            let value = config_override.resolve()?;
+            let key: Vec<&str> = config_override.key.split('.').collect();
+            insert(&mut table, &key, value).map_err(|message| config_override.error(message))?;
+        }
+
+        let config: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|e: toml::de::Error| ConfigError::Invalid(e.message().to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks the settings that can't be wrong on their own, only in
+    /// combination, reporting all problems at once.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        // Port 0 picks a free port, so it can't collide
+        let mut ports = vec![
+            ("server.websocket_port", self.server.websocket_port),
+            ("server.http_port", self.server.http_port),
+        ];
+        if let Some(port) = self.dashboard.tls.redirect_port {
+            ports.push(("dashboard.tls.redirect_port", port));
+        }
+        ports.retain(|(_, port)| *port != 0);
 
-        if config_path.exists() {
-            let content = fs::read_to_string(&config_path)?;
-            let config: Config = toml::from_str(&content)?;
-            Ok(config)
+        for (i, (key, port)) in ports.iter().enumerate() {
+            if let Some((other, _)) = ports[..i].iter().find(|(_, other_port)| other_port == port) {
+                problems.push(format!("`{}` and `{}` are both {}", other, key, port));
+            }
+            if *port == DISCOVERY_PORT && self.server.udp_discovery_enabled {
+                problems.push(format!("`{}` is {}, the UDP discovery port", key, port));
+            }
+        }
+
+        if self.server.bind_address.parse::<IpAddr>().is_err() {
+            problems.push(format!(
+                "`server.bind_address` `{}` is not an IP address",
+                self.server.bind_address
+            ));
+        }
+        if self.logging.level.parse::<LevelFilter>().is_err() {
+            problems.push(format!(
+                "`logging.level` `{}` is not one of trace, debug, info, warn, error or off",
+                self.logging.level
+            ));
+        }
+        if self.dashboard.tls.enabled && !self.server.tls.enabled {
+            problems.push("[dashboard.tls] requires [server.tls] to be enabled".to_string());
+        }
+        if self.server.tls.client_auth.enabled && !self.server.tls.enabled {
+            problems
+                .push("[server.tls.client_auth] requires [server.tls] to be enabled".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
         } else {
-            Ok(Config::default())
+            Err(ConfigError::Invalid(problems.join("; ")))
         }
     }
 
-    fn config_path() -> PathBuf {
+    pub fn config_path() -> PathBuf {
         dirs::home_dir()
             .unwrap_or_else(|| PathBuf::from("."))
             .join(".androidoscopy")
@@ -197,6 +864,8 @@ mod tests {
         assert_eq!(config.server.http_port, 8880);
         assert_eq!(config.server.websocket_port, 8889);
         assert_eq!(config.server.bind_address, "0.0.0.0");
+        assert!(config.server.udp_discovery_enabled);
+        assert!(config.server.mdns_enabled);
     }
 
     #[test]
@@ -215,4 +884,275 @@ mod tests {
         assert_eq!(config.server.websocket_port, 9998);
         assert_eq!(config.session.data_buffer_size, 500);
     }
+
+    #[test]
+    fn test_parse_discovery() {
+        let config: Config = toml::from_str("").unwrap();
+        assert!(config.server.discovery.interfaces.is_empty());
+        assert!(config
+            .server
+            .discovery
+            .exclude_interfaces
+            .contains(&"docker*".to_string()));
+        assert!(config.server.discovery.ipv6_enabled);
+
+        let toml_content = r#"
+            [server.discovery]
+            interfaces = ["wlan0", "eth*"]
+            exclude_interfaces = []
+            ipv6_enabled = false
+        "#;
+
+        let config: Config = toml::from_str(toml_content).unwrap();
+        assert_eq!(config.server.discovery.interfaces, vec!["wlan0", "eth*"]);
+        assert!(config.server.discovery.exclude_interfaces.is_empty());
+        assert!(!config.server.discovery.ipv6_enabled);
+    }
+
+    #[test]
+    fn test_default_config_file() {
+        let config: Config = toml::from_str(DEFAULT_CONFIG).unwrap();
+        assert_eq!(
+            toml::Value::try_from(&config).unwrap(),
+            toml::Value::try_from(Config::default()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Config::default().validate().is_ok());
+
+        let mut config = Config::default();
+        config.server.http_port = 9000;
+        config.server.websocket_port = 9000;
+        config.dashboard.tls.redirect_port = Some(DISCOVERY_PORT);
+        config.server.bind_address = "localhost".to_string();
+        let message = config.validate().unwrap_err().to_string();
+        assert!(message.contains("`server.websocket_port` and `server.http_port` are both 9000"));
+        assert!(message.contains("`dashboard.tls.redirect_port` is 9998, the UDP discovery port"));
+        assert!(message.contains("`server.bind_address`"));
+
+        // Free ports, and the discovery port once discovery is off, are fine
+        let mut config = Config::default();
+        config.server.http_port = 0;
+        config.server.websocket_port = 0;
+        assert!(config.validate().is_ok());
+        config.server.websocket_port = DISCOVERY_PORT;
+        config.server.udp_discovery_enabled = false;
+        assert!(config.validate().is_ok());
+
+        let mut config = Config::default();
+        config.server.tls.enabled = false;
+        config.dashboard.tls.enabled = true;
+        assert!(config
+            .validate()
+            .unwrap_err()
+            .to_string()
+            .contains("[dashboard.tls]"));
+    }
+
+    #[test]
+    fn test_parse_logging() {
+        let config: Config = toml::from_str("").unwrap();
+        assert_eq!(config.logging.format, LogFormat::Text);
+        assert_eq!(config.logging.file, None);
+
+        let toml_content = r#"
+            [logging]
+            level = "debug"
+            format = "json"
+            file = "/var/log/androidoscopy/server.log"
+            max_file_size = 1048576
+            max_files = 3
+        "#;
+
+        let config: Config = toml::from_str(toml_content).unwrap();
+        assert_eq!(config.logging.level, "debug");
+        assert_eq!(config.logging.format, LogFormat::Json);
+        assert_eq!(
+            config.logging.file.as_deref(),
+            Some("/var/log/androidoscopy/server.log")
+        );
+        assert_eq!(config.logging.max_file_size, 1048576);
+        assert_eq!(config.logging.max_files, 3);
+
+        assert!(toml::from_str::<Config>("[logging]\nformat = \"xml\"").is_err());
+    }
+
+    #[test]
+    fn test_parse_limits() {
+        let toml_content = r#"
+            [limits]
+            max_message_size = 2097152
+            max_data_depth = 8
+        "#;
+
+        let config: Config = toml::from_str(toml_content).unwrap();
+        assert_eq!(config.limits.max_message_size, 2 * 1024 * 1024);
+        assert_eq!(config.limits.max_data_depth, 8);
+        assert_eq!(config.limits.max_log_message_size, 64 * 1024);
+    }
+
+    #[test]
+    fn test_parse_auth() {
+        let toml_content = r#"
+            [server.auth]
+            enabled = true
+            token = "s3cret"
+        "#;
+
+        let config: Config = toml::from_str(toml_content).unwrap();
+        assert!(config.server.auth.enabled);
+        assert_eq!(config.server.auth.token.as_deref(), Some("s3cret"));
+        assert!(config.server.auth.token_path.ends_with("pairing_token"));
+    }
+
+    #[test]
+    fn test_parse_dashboard_users() {
+        let toml_content = r#"
+            [dashboard.auth]
+            enabled = true
+
+            [[dashboard.auth.users]]
+            name = "alice"
+            password = "hunter2"
+            role = "read-write"
+
+            [[dashboard.auth.users]]
+            name = "bob"
+            password = "letmein"
+        "#;
+
+        let config: Config = toml::from_str(toml_content).unwrap();
+        let users = &config.dashboard.auth.users;
+        assert!(config.dashboard.auth.enabled);
+        assert_eq!(users.len(), 2);
+        assert_eq!(users[0].role, Role::ReadWrite);
+        assert_eq!(users[1].role, Role::ReadOnly);
+    }
+
+    #[test]
+    fn test_parse_dashboard_tls() {
+        let toml_content = r#"
+            [server.tls]
+            extra_hostnames = ["devbox.local"]
+
+            [dashboard.tls]
+            enabled = true
+            redirect_port = 8881
+        "#;
+
+        let config: Config = toml::from_str(toml_content).unwrap();
+        assert_eq!(config.server.tls.extra_hostnames, vec!["devbox.local"]);
+        assert!(config.dashboard.tls.enabled);
+        assert_eq!(config.dashboard.tls.redirect_port, Some(8881));
+
+        let config = Config::default();
+        assert!(!config.dashboard.tls.enabled);
+        assert_eq!(config.dashboard.tls.redirect_port, None);
+    }
+
+    #[test]
+    fn test_unknown_key_is_an_error() {
+        let content = "[server]\nhtp_port = 9000\n";
+        let error = Config::parse(content, Path::new("config.toml"), &[]).unwrap_err();
+        let message = error.to_string();
+        assert!(message.contains("config.toml"), "{}", message);
+        assert!(message.contains("line 2"), "{}", message);
+        assert!(message.contains("htp_port"), "{}", message);
+
+        let error = Config::parse(
+            "[server]\nhttp_port = \"x\"\n",
+            Path::new("config.toml"),
+            &[],
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("http_port"), "{}", error);
+    }
+
+    #[test]
+    fn test_overrides() {
+        let content = "[server]\nhttp_port = 9000\nwebsocket_port = 9001\n";
+        let overrides = [
+            ConfigOverride::new("server.http_port", "9100", "--http-port"),
+            ConfigOverride::new("server.bind_address", "127.0.0.1", "--bind-address"),
+            ConfigOverride::new("server.tls.enabled", "false", "--set"),
+            ConfigOverride::new("server.auth.token", "123", "--set"),
+            ConfigOverride::new("server.discovery.interfaces", r#"["wlan0"]"#, "--set"),
+        ];
+
+        let config = Config::parse(content, Path::new("config.toml"), &overrides).unwrap();
+        assert_eq!(config.server.http_port, 9100);
+        assert_eq!(config.server.websocket_port, 9001);
+        assert_eq!(config.server.bind_address, "127.0.0.1");
+        assert!(!config.server.tls.enabled);
+        assert_eq!(config.server.auth.token.as_deref(), Some("123"));
+        assert_eq!(config.server.discovery.interfaces, vec!["wlan0"]);
+    }
+
+    #[test]
+    fn test_invalid_override() {
+        let invalid = |key: &str, value: &str| {
+            let overrides = [ConfigOverride::new(key, value, "ANDROIDOSCOPY_TEST")];
+            Config::parse("", Path::new("config.toml"), &overrides)
+                .unwrap_err()
+                .to_string()
+        };
+
+        let message = invalid("server.http_port", "eighty");
+        assert!(message.contains("server.http_port"), "{}", message);
+        assert!(message.contains("ANDROIDOSCOPY_TEST"), "{}", message);
+
+        assert!(invalid("server.htp_port", "9000").contains("htp_port"));
+        assert!(invalid("server.http_port.value", "1").contains("server.http_port.value"));
+        assert!(invalid("server..http_port", "1").contains("invalid key"));
+    }
+
+    #[test]
+    fn test_overrides_from_env() {
+        let vars = [
+            ("ANDROIDOSCOPY_SERVER__TLS__ENABLED", "false"),
+            ("ANDROIDOSCOPY_CONFIG", "/etc/androidoscopy.toml"),
+            ("ANDROIDOSCOPY_SERVER__HTTP_PORT", "9000"),
+            ("HOME", "/root"),
+        ]
+        .map(|(name, value)| (name.to_string(), value.to_string()));
+
+        assert_eq!(
+            ConfigOverride::from_env(vars),
+            vec![
+                ConfigOverride::new(
+                    "server.http_port",
+                    "9000",
+                    "ANDROIDOSCOPY_SERVER__HTTP_PORT"
+                ),
+                ConfigOverride::new(
+                    "server.tls.enabled",
+                    "false",
+                    "ANDROIDOSCOPY_SERVER__TLS__ENABLED"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_override_assignment() {
+        let parsed = ConfigOverride::parse("server.http_port = 9000", "--set").unwrap();
+        assert_eq!(
+            parsed,
+            ConfigOverride::new("server.http_port", "9000", "--set")
+        );
+        assert!(ConfigOverride::parse("server.http_port", "--set").is_err());
+        assert!(ConfigOverride::parse("=9000", "--set").is_err());
+    }
+
+    #[test]
+    fn test_explicit_config_file_must_exist() {
+        let path = std::env::temp_dir().join(format!(
+            "androidoscopy-missing-{}.toml",
+            uuid::Uuid::new_v4()
+        ));
+        let error = Config::load_with(Some(&path), &[]).unwrap_err();
+        assert!(matches!(error, ConfigError::NotFound { .. }));
+    }
 }
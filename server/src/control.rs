@@ -0,0 +1,588 @@
+//! The control socket: a Unix socket for the command line on this machine.
+//!
+//! It serves `/ws/dashboard` and the session endpoints of [`crate::api`]
+//! like the dashboard port, but without a login: only the user running the
+//! server can open the socket, and they act with the `read-write` role.
+//! On top of that it serves admin endpoints:
+//!
+//! - `GET /control/status` - health and statistics (handled by [`status`])
+//! - `GET /control/state` - the running config and the sessions (handled by
+//!   [`state`])
+//! - `POST /control/reload` - reload the config, answering with the
+//!   [`crate::reload::ConfigChanges`] (handled by [`reload`])
+//! - `POST /control/shutdown` - stop the server (handled by [`shutdown`])
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{Response, StatusCode},
+    response::IntoResponse,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, Notify};
+use tracing::{info, warn};
+
+use crate::config::{Config, Role};
+use crate::dashboard_auth::AuthenticatedUser;
+use crate::protocol::SessionSummary;
+use crate::reload::ReloadRequest;
+use crate::state::AppState;
+use crate::stats::{Listener, MessageRate};
+use crate::{api, handlers, netif, tls};
+
+/// What the admin endpoints act on.
+#[derive(Clone)]
+pub struct Controls {
+    pub reload: mpsc::Sender<ReloadRequest>,
+    /// Notified once to stop the server.
+    pub shutdown: Arc<Notify>,
+}
+
+/// The answer to `GET /control/state`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerState {
+    pub version: String,
+    pub pid: u32,
+    pub config: Config,
+    /// Active and recently ended sessions, oldest first.
+    pub sessions: Vec<SessionSummary>,
+}
+
+/// The answer to `GET /control/status`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerStatus {
+    pub version: String,
+    pub pid: u32,
+    pub started_at: DateTime<Utc>,
+    pub listeners: Vec<Listener>,
+    /// The certificate apps are served, when the app port uses TLS.
+    pub tls: Option<TlsStatus>,
+    pub discovery: DiscoveryStatus,
+    pub active_sessions: usize,
+    pub ended_sessions: usize,
+    pub dashboards: usize,
+    pub app_messages: MessageRate,
+    pub dashboard_messages: MessageRate,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsStatus {
+    /// SHA-256 fingerprint of the certificate apps pin, the one discovery
+    /// and onboarding publish, or `None` if it can't be read.
+    pub pinned_fingerprint: Option<String>,
+    /// Expiry of the certificate the app listener serves, which is re-issued
+    /// before then when auto-generated.
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Whether apps need a certificate from `androidoscopy enroll`.
+    pub client_auth: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiscoveryStatus {
+    pub udp_enabled: bool,
+    pub mdns_enabled: bool,
+    /// Interfaces announced on, when either is enabled.
+    pub interfaces: Vec<String>,
+}
+
+pub fn routes(controls: Controls) -> Router<AppState> {
+    let user = AuthenticatedUser {
+        name: "control".to_string(),
+        role: Role::ReadWrite,
+    };
+
+    Router::new()
+        .route("/ws/dashboard", get(handlers::handle_dashboard_ws))
+        .route("/api/sessions", get(api::list_sessions))
+        .route("/api/sessions/:session_id", get(api::get_session))
+        .route(
+            "/api/sessions/:session_id/attachments",
+            get(api::list_attachments),
+        )
+        .route(
+            "/api/sessions/:session_id/attachments/:attachment_id",
+            get(api::download_attachment),
+        )
+        .route("/control/status", get(status))
+        .route("/control/state", get(state))
+        .route("/control/reload", post(reload))
+        .route("/control/shutdown", post(shutdown))
+        .layer(Extension(user))
+        .layer(Extension(controls))
+}
+
+pub async fn status(State(state): State<AppState>) -> Json<ServerStatus> {
+    let config = state.config();
+    let listeners = state.stats.listeners();
+
+    // The certificate may have fallen back to plain WS, the listener tells
+    let app_tls = state
+        .stats
+        .app_listener()
+        .is_some_and(|listener| listener.tls);
+    let tls = if app_tls {
+        let tls_config = config.server.tls.clone();
+        let (pinned, served) = tokio::task::spawn_blocking(move || {
+            (
+                tls::get_certificate_der(tls::distributed_certificate_path(&tls_config)).ok(),
+                tls::get_certificate_der(&tls_config.cert_path).ok(),
+            )
+        })
+        .await
+        .unwrap_or_default();
+        Some(TlsStatus {
+            pinned_fingerprint: pinned.as_deref().map(tls::certificate_fingerprint),
+            expires_at: served
+                .as_deref()
+                .and_then(|der| tls::certificate_expiry(der).ok()),
+            client_auth: config.server.tls.client_auth.enabled,
+        })
+    } else {
+        None
+    };
+
+    let discovery = &config.server.discovery;
+    let mut interfaces = Vec::new();
+    if config.server.udp_discovery_enabled || config.server.mdns_enabled {
+        interfaces = netif::list()
+            .into_iter()
+            .map(|interface| interface.name)
+            .filter(|name| {
+                netif::is_selected(name, &discovery.interfaces, &discovery.exclude_interfaces)
+            })
+            .collect();
+        interfaces.sort();
+        interfaces.dedup();
+    }
+
+    let (active_sessions, ended_sessions, dashboards) = {
+        let manager = state.session_manager.lock().await;
+        let active = manager.get_active_sessions().len();
+        (
+            active,
+            manager.get_all_sessions().len() - active,
+            manager.get_dashboard_senders().len(),
+        )
+    };
+
+    Json(ServerStatus {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        pid: std::process::id(),
+        started_at: state.stats.started_at,
+        listeners,
+        tls,
+        discovery: DiscoveryStatus {
+            udp_enabled: config.server.udp_discovery_enabled,
+            mdns_enabled: config.server.mdns_enabled,
+            interfaces,
+        },
+        active_sessions,
+        ended_sessions,
+        dashboards,
+        app_messages: state.stats.app_messages.rate(),
+        dashboard_messages: state.stats.dashboard_messages.rate(),
+    })
+}
+
+pub async fn state(State(state): State<AppState>) -> Json<ServerState> {
+    let Json(sessions) = api::list_sessions(State(state.clone())).await;
+
+    Json(ServerState {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        pid: std::process::id(),
+        config: (*state.config()).clone(),
+        sessions,
+    })
+}
+
+pub async fn reload(Extension(controls): Extension<Controls>) -> Response<Body> {
+    let (reply, changes) = oneshot::channel();
+    let request = ReloadRequest {
+        reason: "requested over the control socket".to_string(),
+        reply: Some(reply),
+    };
+    if controls.reload.send(request).await.is_err() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Config reloading is not running",
+        )
+            .into_response();
+    }
+
+    match changes.await {
+        Ok(Ok(changes)) => Json(changes).into_response(),
+        Ok(Err(e)) => (StatusCode::UNPROCESSABLE_ENTITY, e).into_response(),
+        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "The reload was dropped").into_response(),
+    }
+}
+
+pub async fn shutdown(Extension(controls): Extension<Controls>) -> Json<bool> {
+    info!("Shutdown requested over the control socket");
+    // Leave time to answer before the runtime goes away
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        controls.shutdown.notify_one();
+    });
+    Json(true)
+}
+
+/// Serves [`routes`] on a Unix socket at `path` until the process exits.
+///
+/// The socket's directory must belong to this user and be closed to others,
+/// see [`check_socket_dir`]. A socket left behind by a server that is gone is
+/// replaced; if another server is listening on it, this fails.
+#[cfg(unix)]
+pub fn spawn(path: &Path, state: AppState, controls: Controls) -> io::Result<()> {
+    use hyper_util::rt::TokioIo;
+    use hyper_util::service::TowerToHyperService;
+    use std::os::unix::fs::PermissionsExt;
+    use tokio::net::UnixListener;
+
+    let dir = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    check_socket_dir(dir)?;
+    if path.exists() {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                "another server is listening on it",
+            ));
+        }
+        std::fs::remove_file(path)?;
+    }
+
+    let listener = UnixListener::bind(path)?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    info!("Control socket: {}", path.display());
+    state
+        .stats
+        .add_listener("Control socket", path.display().to_string(), false);
+
+    let app = routes(controls).with_state(state);
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("Failed to accept a control connection: {}", e);
+                    continue;
+                }
+            };
+            let service = TowerToHyperService::new(app.clone());
+            tokio::spawn(async move {
+                // Upgrades carry /ws/dashboard
+                let _ = hyper::server::conn::http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .with_upgrades()
+                    .await;
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Creates `dir` only accessible to this user if it is missing, or checks
+/// that an existing one is.
+///
+/// Existing directories are never changed: a shared one like `/tmp`, or one
+/// another user created in advance, is rejected instead.
+#[cfg(unix)]
+pub fn check_socket_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{DirBuilderExt, MetadataExt};
+
+    if !dir.exists() {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+    }
+
+    // Not following symlinks, and checked after creating in case someone was faster
+    let metadata = std::fs::symlink_metadata(dir)?;
+    // SAFETY: geteuid has no preconditions and can't fail
+    let uid = unsafe { libc::geteuid() };
+    let problem = if !metadata.is_dir() {
+        Some("is not a directory")
+    } else if metadata.uid() != uid {
+        Some("belongs to another user")
+    } else if metadata.mode() & 0o077 != 0 {
+        Some("is accessible to other users")
+    } else {
+        None
+    };
+    match problem {
+        Some(problem) => Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "{} {}; use a directory only you can access",
+                dir.display(),
+                problem
+            ),
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Removes the socket when the server stops.
+pub fn remove(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        if e.kind() != io::ErrorKind::NotFound {
+            warn!(
+                "Failed to remove the control socket {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// `status` for people, as of `now`.
+pub fn status_report(status: &ServerStatus, now: DateTime<Utc>) -> String {
+    let mut report = String::new();
+    let _ = writeln!(
+        report,
+        "androidoscopy {} is running (pid {}), up {}",
+        status.version,
+        status.pid,
+        duration(now - status.started_at)
+    );
+
+    let _ = writeln!(report, "Listening on:");
+    let width = status
+        .listeners
+        .iter()
+        .map(|listener| listener.name.len())
+        .max()
+        .unwrap_or(0);
+    for listener in &status.listeners {
+        let _ = writeln!(
+            report,
+            "  {:width$}  {}",
+            listener.name,
+            listener.url,
+            width = width
+        );
+    }
+
+    match &status.tls {
+        None => {
+            let _ = writeln!(report, "TLS:          off");
+        }
+        Some(TlsStatus {
+            pinned_fingerprint: Some(fingerprint),
+            expires_at: Some(expires_at),
+            client_auth,
+        }) => {
+            let _ = writeln!(report, "TLS:          pinned SHA-256 {}", fingerprint);
+            let expiry = expires_at.format("%Y-%m-%d %H:%M UTC");
+            if *expires_at > now {
+                let _ = writeln!(
+                    report,
+                    "              served certificate expires {} (in {})",
+                    expiry,
+                    duration(*expires_at - now)
+                );
+            } else {
+                let _ = writeln!(
+                    report,
+                    "              served certificate EXPIRED {}",
+                    expiry
+                );
+            }
+            if *client_auth {
+                let _ = writeln!(report, "              client certificates required");
+            }
+        }
+        Some(_) => {
+            let _ = writeln!(
+                report,
+                "TLS:          on, but the certificate can't be read"
+            );
+        }
+    }
+
+    let mechanisms: Vec<&str> = [
+        (status.discovery.udp_enabled, "UDP broadcast"),
+        (status.discovery.mdns_enabled, "mDNS"),
+    ]
+    .into_iter()
+    .filter_map(|(enabled, name)| enabled.then_some(name))
+    .collect();
+    let discovery = match (
+        mechanisms.as_slice(),
+        status.discovery.interfaces.as_slice(),
+    ) {
+        ([], _) => "off".to_string(),
+        (_, []) => format!(
+            "{}, but no interface to announce on",
+            mechanisms.join(" and ")
+        ),
+        (_, interfaces) => format!("{} on {}", mechanisms.join(" and "), interfaces.join(", ")),
+    };
+    let _ = writeln!(report, "Discovery:    {}", discovery);
+
+    let _ = writeln!(
+        report,
+        "Sessions:     {} active, {} ended",
+        status.active_sessions, status.ended_sessions
+    );
+    let _ = writeln!(report, "Dashboards:   {} connected", status.dashboards);
+    let _ = writeln!(
+        report,
+        "Messages:     {:.1}/s from apps ({} total), {:.1}/s to dashboards ({} total)",
+        status.app_messages.per_second,
+        status.app_messages.total,
+        status.dashboard_messages.per_second,
+        status.dashboard_messages.total
+    );
+    report
+}
+
+/// A duration like `3d 4h`, `2h 5m` or `42s`, to the two largest units.
+fn duration(duration: chrono::Duration) -> String {
+    let seconds = duration.num_seconds().max(0);
+    let units = [
+        (seconds / 86400, "d"),
+        (seconds / 3600 % 24, "h"),
+        (seconds / 60 % 60, "m"),
+        (seconds % 60, "s"),
+    ];
+
+    let parts: Vec<String> = units
+        .iter()
+        .skip_while(|(value, _)| *value == 0)
+        .take(2)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect();
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_status_report() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let mut status = ServerStatus {
+            version: "1.2.3".to_string(),
+            pid: 42,
+            started_at: now - chrono::Duration::seconds(2 * 3600 + 5 * 60 + 9),
+            listeners: vec![
+                Listener {
+                    name: "Dashboard".to_string(),
+                    url: "http://0.0.0.0:8880".to_string(),
+                    tls: false,
+                },
+                Listener {
+                    name: "Android app".to_string(),
+                    url: "wss://0.0.0.0:8889/ws/app".to_string(),
+                    tls: true,
+                },
+            ],
+            tls: Some(TlsStatus {
+                pinned_fingerprint: Some("AB:CD".to_string()),
+                expires_at: Some(now + chrono::Duration::days(29)),
+                client_auth: true,
+            }),
+            discovery: DiscoveryStatus {
+                udp_enabled: true,
+                mdns_enabled: true,
+                interfaces: vec!["eth0".to_string(), "wlan0".to_string()],
+            },
+            active_sessions: 2,
+            ended_sessions: 1,
+            dashboards: 1,
+            app_messages: MessageRate {
+                total: 1200,
+                per_second: 12.5,
+            },
+            dashboard_messages: MessageRate {
+                total: 900,
+                per_second: 9.0,
+            },
+        };
+
+        let report = status_report(&status, now);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(
+            lines[0],
+            "androidoscopy 1.2.3 is running (pid 42), up 2h 5m"
+        );
+        assert_eq!(lines[2], "  Dashboard    http://0.0.0.0:8880");
+        assert_eq!(lines[3], "  Android app  wss://0.0.0.0:8889/ws/app");
+        assert_eq!(lines[4], "TLS:          pinned SHA-256 AB:CD");
+        assert_eq!(
+            lines[5],
+            "              served certificate expires 2024-03-30 12:00 UTC (in 29d 0h)"
+        );
+        assert_eq!(lines[6], "              client certificates required");
+        assert_eq!(
+            lines[7],
+            "Discovery:    UDP broadcast and mDNS on eth0, wlan0"
+        );
+        assert_eq!(lines[8], "Sessions:     2 active, 1 ended");
+        assert_eq!(lines[9], "Dashboards:   1 connected");
+        assert_eq!(
+            lines[10],
+            "Messages:     12.5/s from apps (1200 total), 9.0/s to dashboards (900 total)"
+        );
+
+        status.tls = None;
+        status.discovery.udp_enabled = false;
+        status.discovery.mdns_enabled = false;
+        let report = status_report(&status, now);
+        assert!(report.contains("TLS:          off\n"));
+        assert!(report.contains("Discovery:    off\n"));
+    }
+
+    #[test]
+    fn test_duration() {
+        assert_eq!(duration(chrono::Duration::seconds(0)), "0s");
+        assert_eq!(duration(chrono::Duration::seconds(42)), "42s");
+        assert_eq!(
+            duration(chrono::Duration::seconds(3 * 86400 + 4 * 3600 + 59)),
+            "3d 4h"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_socket_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root =
+            std::env::temp_dir().join(format!("androidoscopy-control-{}", uuid::Uuid::new_v4()));
+        let dir = root.join("androidoscopy");
+
+        check_socket_dir(&dir).unwrap();
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+        // Existing and private is fine
+        check_socket_dir(&dir).unwrap();
+
+        // Open to others, which is left as it is
+        std::fs::set_permissions(&dir, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let error = check_socket_dir(&dir).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        let mode = std::fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,509 @@
+//! Login for the dashboard.
+//!
+//! When `[dashboard.auth]` is enabled, every route on the HTTP port except
+//! `/login` and `/api/certificate*` requires a logged-in user: the dashboard
+//! assets, `/ws/dashboard` and the rest of `/api/*`. Users are listed in the config file with a password and a
+//! [`Role`]; read-only users can watch sessions but can't send ACTIONs.
+//! Passwords are stored as Argon2 hashes made by [`hash_password`]
+//! (`androidoscopy hash-password`); plain text ones still work, with a warning.
+//!
+//! # Endpoints
+//!
+//! - `GET /login` - login form (handled by [`login_page`])
+//! - `POST /login` - checks the credentials and sets the session cookie
+//!   (handled by [`login`])
+//! - `GET /logout` - forgets the login (handled by [`logout`])
+//!
+//! Browsers authenticate with the `androidoscopy_session` cookie set by
+//! `/login`. Other clients can send `Authorization: Basic` instead; the
+//! response sets the same cookie, which saves checking the password again.
+//! A login follows the config: a user removed by a reload is logged out, and
+//! a changed role applies right away.
+
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, Method, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Redirect, Response},
+    Form,
+};
+use base64::Engine;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::sync::{Mutex, Semaphore};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::auth::tokens_match;
+use crate::config::{Config, DashboardAuthConfig, Role};
+use crate::state::AppState;
+
+pub const SESSION_COOKIE: &str = "androidoscopy_session";
+
+/// How the hashes made by [`hash_password`] start.
+pub const PASSWORD_HASH_PREFIX: &str = "$argon2";
+
+/// The user behind a request, added as a request extension by [`require_login`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticatedUser {
+    pub name: String,
+    pub role: Role,
+}
+
+/// Password checks that may run at the same time; each takes a while and
+/// some memory, so requests beyond this wait.
+const MAX_CONCURRENT_PASSWORD_CHECKS: usize = 4;
+
+#[derive(Debug)]
+struct Login {
+    name: String,
+    expires_at: Instant,
+}
+
+/// Logins issued by `/login`, or by a successful Basic auth check, keyed by the
+/// cookie value. Kept in memory, so a restart logs everyone out.
+#[derive(Debug)]
+pub struct LoginStore {
+    logins: Mutex<HashMap<String, Login>>,
+    ttl: Duration,
+    password_checks: Semaphore,
+}
+
+impl LoginStore {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            logins: Mutex::new(HashMap::new()),
+            ttl,
+            password_checks: Semaphore::new(MAX_CONCURRENT_PASSWORD_CHECKS),
+        }
+    }
+
+    /// Returns the cookie value for a new login.
+    pub async fn create(&self, user: &AuthenticatedUser) -> String {
+        let token = Uuid::new_v4().simple().to_string();
+        let now = Instant::now();
+
+        let mut logins = self.logins.lock().await;
+        logins.retain(|_, login| login.expires_at > now);
+        logins.insert(
+            token.clone(),
+            Login {
+                name: user.name.clone(),
+                expires_at: now + self.ttl,
+            },
+        );
+
+        token
+    }
+
+    /// The user logged in with `token`, with their role in `config`. Logins of
+    /// users no longer in `config` are dropped.
+    pub async fn get(
+        &self,
+        token: &str,
+        config: &DashboardAuthConfig,
+    ) -> Option<AuthenticatedUser> {
+        let mut logins = self.logins.lock().await;
+        let login = logins
+            .get(token)
+            .filter(|login| login.expires_at > Instant::now())?;
+
+        match config.users.iter().find(|user| user.name == login.name) {
+            Some(user) => Some(AuthenticatedUser {
+                name: user.name.clone(),
+                role: user.role,
+            }),
+            None => {
+                logins.remove(token);
+                None
+            }
+        }
+    }
+
+    pub async fn remove(&self, token: &str) {
+        self.logins.lock().await.remove(token);
+    }
+
+    /// [`check_credentials`] on the blocking thread pool, at most
+    /// [`MAX_CONCURRENT_PASSWORD_CHECKS`] at a time.
+    pub async fn check(
+        &self,
+        config: &DashboardAuthConfig,
+        name: &str,
+        password: &str,
+    ) -> Option<AuthenticatedUser> {
+        let _permit = self.password_checks.acquire().await.ok()?;
+        let (config, name, password) = (config.clone(), name.to_string(), password.to_string());
+        tokio::task::spawn_blocking(move || check_credentials(&config, &name, &password))
+            .await
+            .ok()
+            .flatten()
+    }
+}
+
+/// Hashes a password for `[[dashboard.auth.users]]`, as a PHC string.
+pub fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::encode_b64(Uuid::new_v4().as_bytes())?;
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+pub fn is_password_hash(password: &str) -> bool {
+    password.starts_with(PASSWORD_HASH_PREFIX)
+}
+
+/// Checks `password` against a configured one, either a hash or plain text.
+fn password_matches(configured: &str, password: &str) -> bool {
+    if !is_password_hash(configured) {
+        return tokens_match(configured, password);
+    }
+    match PasswordHash::new(configured) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(e) => {
+            warn!("Invalid dashboard password hash: {}", e);
+            false
+        }
+    }
+}
+
+/// Checked instead of a real hash for unknown names, so they take as long.
+fn unknown_user_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+    HASH.get_or_init(|| hash_password("").unwrap_or_default())
+}
+
+/// Returns the user with these credentials, if any.
+///
+/// Whether the name exists doesn't change how long this takes.
+pub fn check_credentials(
+    config: &DashboardAuthConfig,
+    name: &str,
+    password: &str,
+) -> Option<AuthenticatedUser> {
+    // Every name is compared, not stopping at the first match
+    let user = config.users.iter().fold(None, |found, user| {
+        found.or(tokens_match(&user.name, name).then_some(user))
+    });
+
+    let configured = match user {
+        Some(user) => user.password.as_str(),
+        None => unknown_user_hash(),
+    };
+    let matches = password_matches(configured, password);
+
+    user.filter(|_| matches).map(|user| AuthenticatedUser {
+        name: user.name.clone(),
+        role: user.role,
+    })
+}
+
+fn session_cookie(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let encoded = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = base64::engine::general_purpose::STANDARD
+        .decode(encoded.trim())
+        .ok()?;
+    let decoded = String::from_utf8(decoded).ok()?;
+    let (name, password) = decoded.split_once(':')?;
+    Some((name.to_string(), password.to_string()))
+}
+
+/// Middleware rejecting requests without a valid login when dashboard auth is enabled.
+///
+/// A request that passes with Basic auth gets a login cookie, so clients that
+/// keep it don't have their password checked again.
+pub async fn require_login(
+    State(state): State<AppState>,
+    mut req: Request,
+    next: Next,
+) -> Response {
+    let config = state.config();
+    let auth = &config.dashboard.auth;
+    if !auth.enabled {
+        return next.run(req).await;
+    }
+
+    let mut user = match session_cookie(req.headers()) {
+        Some(token) => state.logins.get(token, auth).await,
+        None => None,
+    };
+    let mut new_login = None;
+    if user.is_none() {
+        if let Some((name, password)) = basic_credentials(req.headers()) {
+            user = state.logins.check(auth, &name, &password).await;
+            if let Some(user) = &user {
+                new_login = Some(state.logins.create(user).await);
+            }
+        }
+    }
+
+    match user {
+        Some(user) => {
+            req.extensions_mut().insert(user);
+            let mut response = next.run(req).await;
+            if let Some(token) = new_login {
+                if let Ok(cookie) = login_cookie(&config, &token).parse() {
+                    response.headers_mut().append(header::SET_COOKIE, cookie);
+                }
+            }
+            response
+        }
+        None => {
+            let path = req.uri().path();
+            let is_page = req.method() == Method::GET
+                && !path.starts_with("/ws/")
+                && !path.starts_with("/api/");
+            if is_page {
+                Redirect::to("/login").into_response()
+            } else {
+                StatusCode::UNAUTHORIZED.into_response()
+            }
+        }
+    }
+}
+
+fn login_cookie(config: &Config, token: &str) -> String {
+    let mut cookie = format!(
+        "{}={}; HttpOnly; SameSite=Strict; Path=/; Max-Age={}",
+        SESSION_COOKIE,
+        token,
+        config.dashboard.auth.login_ttl_hours * 3600
+    );
+    if config.dashboard.tls.enabled {
+        cookie.push_str("; Secure");
+    }
+    cookie
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LoginForm {
+    pub name: String,
+    pub password: String,
+}
+
+pub async fn login_page(State(state): State<AppState>) -> Response {
+    if !state.config().dashboard.auth.enabled {
+        return Redirect::to("/").into_response();
+    }
+    Html(render_login_page(None)).into_response()
+}
+
+pub async fn login(State(state): State<AppState>, Form(form): Form<LoginForm>) -> Response {
+    let config = state.config();
+    if !config.dashboard.auth.enabled {
+        return Redirect::to("/").into_response();
+    }
+
+    let Some(user) = state
+        .logins
+        .check(&config.dashboard.auth, &form.name, &form.password)
+        .await
+    else {
+        warn!("Failed dashboard login for {}", form.name);
+        return (
+            StatusCode::UNAUTHORIZED,
+            Html(render_login_page(Some("Wrong name or password"))),
+        )
+            .into_response();
+    };
+
+    info!("Dashboard login for {} ({:?})", user.name, user.role);
+    let token = state.logins.create(&user).await;
+    let cookie = login_cookie(&config, &token);
+
+    ([(header::SET_COOKIE, cookie)], Redirect::to("/")).into_response()
+}
+
+pub async fn logout(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(token) = session_cookie(&headers) {
+        state.logins.remove(token).await;
+    }
+
+    let cookie = format!(
+        "{}=; HttpOnly; SameSite=Strict; Path=/; Max-Age=0",
+        SESSION_COOKIE
+    );
+    ([(header::SET_COOKIE, cookie)], Redirect::to("/login")).into_response()
+}
+
+fn render_login_page(error: Option<&str>) -> String {
+    let error = error
+        .map(|message| format!("<p class=\"error\">{}</p>", message))
+        .unwrap_or_default();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Androidoscopy - Login</title>
+<style>
+  body {{ font-family: system-ui, sans-serif; background: #1a1a2e; color: #eee; display: flex; justify-content: center; align-items: center; height: 100vh; margin: 0; }}
+  form {{ background: #16213e; padding: 2rem; border-radius: 8px; display: flex; flex-direction: column; gap: 0.75rem; min-width: 260px; }}
+  input {{ padding: 0.5rem; border-radius: 4px; border: 1px solid #444; background: #0f3460; color: #eee; }}
+  button {{ padding: 0.5rem; border-radius: 4px; border: none; background: #e94560; color: #fff; cursor: pointer; }}
+  .error {{ color: #e94560; margin: 0; }}
+</style>
+</head>
+<body>
+<form method="post" action="/login">
+  <h1>Androidoscopy</h1>
+  {}
+  <input name="name" placeholder="Name" autocomplete="username" required autofocus>
+  <input name="password" type="password" placeholder="Password" autocomplete="current-password" required>
+  <button type="submit">Log in</button>
+</form>
+</body>
+</html>"#,
+        error
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::UserConfig;
+    use axum::http::HeaderValue;
+
+    fn auth_config() -> DashboardAuthConfig {
+        DashboardAuthConfig {
+            enabled: true,
+            login_ttl_hours: 1,
+            users: vec![
+                UserConfig {
+                    name: "alice".to_string(),
+                    password: "hunter2".to_string(),
+                    role: Role::ReadWrite,
+                },
+                UserConfig {
+                    name: "bob".to_string(),
+                    password: "letmein".to_string(),
+                    role: Role::ReadOnly,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_check_credentials() {
+        let config = auth_config();
+
+        assert_eq!(
+            check_credentials(&config, "alice", "hunter2").unwrap().role,
+            Role::ReadWrite
+        );
+        assert_eq!(
+            check_credentials(&config, "bob", "letmein").unwrap().role,
+            Role::ReadOnly
+        );
+        assert_eq!(check_credentials(&config, "alice", "letmein"), None);
+        assert_eq!(check_credentials(&config, "mallory", "hunter2"), None);
+        assert_eq!(check_credentials(&config, "mallory", ""), None);
+    }
+
+    #[test]
+    fn test_hashed_password() {
+        let hash = hash_password("hunter2").unwrap();
+        assert!(is_password_hash(&hash));
+        assert_ne!(hash, hash_password("hunter2").unwrap());
+
+        let mut config = auth_config();
+        config.users[0].password = hash;
+        assert_eq!(
+            check_credentials(&config, "alice", "hunter2").unwrap().role,
+            Role::ReadWrite
+        );
+        assert_eq!(check_credentials(&config, "alice", "hunter3"), None);
+        // Not the hash itself either
+        let hash = config.users[0].password.clone();
+        assert_eq!(check_credentials(&config, "alice", &hash), None);
+    }
+
+    #[test]
+    fn test_session_cookie() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(session_cookie(&headers), None);
+
+        headers.insert(
+            header::COOKIE,
+            HeaderValue::from_static("theme=dark; androidoscopy_session=abc123"),
+        );
+        assert_eq!(session_cookie(&headers), Some("abc123"));
+    }
+
+    #[test]
+    fn test_basic_credentials() {
+        let mut headers = HeaderMap::new();
+        // "alice:hunter2"
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Basic YWxpY2U6aHVudGVyMg=="),
+        );
+
+        assert_eq!(
+            basic_credentials(&headers),
+            Some(("alice".to_string(), "hunter2".to_string()))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_login_store_expiry() {
+        let config = auth_config();
+        let user = AuthenticatedUser {
+            name: "alice".to_string(),
+            role: Role::ReadWrite,
+        };
+
+        let store = LoginStore::new(Duration::from_secs(60));
+        let token = store.create(&user).await;
+        assert_eq!(store.get(&token, &config).await, Some(user.clone()));
+        store.remove(&token).await;
+        assert_eq!(store.get(&token, &config).await, None);
+
+        let expired = LoginStore::new(Duration::ZERO);
+        let token = expired.create(&user).await;
+        assert_eq!(expired.get(&token, &config).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_login_store_follows_config() {
+        let mut config = auth_config();
+        let store = LoginStore::new(Duration::from_secs(60));
+        let user = store.check(&config, "alice", "hunter2").await.unwrap();
+        let token = store.create(&user).await;
+
+        // Demoted
+        config.users[0].role = Role::ReadOnly;
+        assert_eq!(
+            store.get(&token, &config).await.unwrap().role,
+            Role::ReadOnly
+        );
+
+        // Removed, which also ends the login
+        config.users.remove(0);
+        assert_eq!(store.get(&token, &config).await, None);
+        assert_eq!(store.get(&token, &auth_config()).await, None);
+    }
+}
@@ -1,42 +1,382 @@
-use serde::Serialize;
-use std::net::SocketAddr;
-use std::time::Duration;
+//! Letting apps find the server on the LAN.
+//!
+//! Two mechanisms, enabled separately in `[server]`:
+//!
+//! - `udp_discovery_enabled`: a JSON [`DiscoveryMessage`] sent to UDP port
+//!   9998 every 5 seconds, as a directed broadcast on each IPv4 subnet and to
+//!   the all-nodes multicast group `ff02::1` on each IPv6 interface. The same
+//!   message answers `DISCOVER` probes apps on those subnets send to UDP port
+//!   9998, for networks that don't deliver broadcasts to devices;
+//! - `mdns_enabled`: a DNS-SD `_androidoscopy._tcp` service advertised over
+//!   mDNS, which also works on networks dropping broadcasts. Its TXT record
+//!   carries `version`, `websocket_port`, `http_port`, `tls` and, with TLS,
+//!   the certificate `pin`.
+//!
+//! `[server.discovery]` picks the interfaces both announce on, and probes are
+//! only answered on.
+
+use futures::future::join_all;
+use mdns_sd::{IfKind, ServiceDaemon, ServiceInfo};
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashSet;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant};
 use tokio::net::UdpSocket;
-use tracing::{error, info};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+use uuid::Uuid;
+
+use crate::config::{Config, DiscoveryConfig};
+use crate::netif::{self, InterfaceAddr};
 
-const DISCOVERY_PORT: u16 = 9998;
+/// UDP port apps listen on for announcements and send probes to.
+pub const DISCOVERY_PORT: u16 = 9998;
 const BROADCAST_INTERVAL: Duration = Duration::from_secs(5);
 
-#[derive(Debug, Serialize)]
-struct DiscoveryMessage {
-    service: &'static str,
-    version: &'static str,
+/// Version of the app protocol, as advertised to apps.
+pub const PROTOCOL_VERSION: &str = "1.0";
+
+pub const MDNS_SERVICE_TYPE: &str = "_androidoscopy._tcp.local.";
+
+/// What apps send, to `DISCOVERY_PORT`, to ask the server to introduce itself.
+pub const DISCOVER_PROBE: &[u8] = b"DISCOVER";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DiscoveryMessage {
+    pub service: String,
+    pub version: String,
+    /// Random id of this server run, telling apart replies from different
+    /// servers and the same server reached over different interfaces.
+    pub instance_id: String,
+    pub hostname: String,
+    pub websocket_port: u16,
+    pub http_port: u16,
+    /// SHA-256 fingerprint of the certificate apps should trust, with TLS.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub certificate_fingerprint: Option<String>,
+}
+
+impl DiscoveryMessage {
+    pub fn new(
+        websocket_port: u16,
+        http_port: u16,
+        certificate_fingerprint: Option<String>,
+    ) -> Self {
+        Self {
+            service: "androidoscopy".to_string(),
+            version: PROTOCOL_VERSION.to_string(),
+            instance_id: instance_id().to_string(),
+            hostname: local_hostname(),
+            websocket_port,
+            http_port,
+            certificate_fingerprint,
+        }
+    }
+}
+
+/// Id of this server process, generated on first use.
+pub fn instance_id() -> &'static str {
+    static INSTANCE_ID: OnceLock<String> = OnceLock::new();
+    INSTANCE_ID.get_or_init(|| Uuid::new_v4().to_string())
+}
+
+/// What discovery announces besides the ports, fixed for the server's lifetime.
+#[derive(Debug, Clone, Default)]
+pub struct Announcement {
+    pub tls: bool,
+    pub tls_pin: Option<String>,
+    pub certificate_fingerprint: Option<String>,
+}
+
+/// The settings discovery is started with.
+#[derive(Debug, Clone, PartialEq)]
+struct DiscoverySettings {
     websocket_port: u16,
     http_port: u16,
+    udp_enabled: bool,
+    mdns_enabled: bool,
+    discovery: DiscoveryConfig,
+}
+
+impl DiscoverySettings {
+    fn from_config(config: &Config) -> Self {
+        Self {
+            websocket_port: config.server.websocket_port,
+            http_port: config.server.http_port,
+            udp_enabled: config.server.udp_discovery_enabled,
+            mdns_enabled: config.server.mdns_enabled,
+            discovery: config.server.discovery.clone(),
+        }
+    }
+}
+
+/// The discovery mechanisms currently running.
+#[derive(Default)]
+struct RunningDiscovery {
+    tasks: Vec<JoinHandle<()>>,
+    mdns: Option<ServiceDaemon>,
+}
+
+impl RunningDiscovery {
+    fn start(settings: &DiscoverySettings, announcement: &Announcement) -> Self {
+        let mut running = Self::default();
+
+        if settings.udp_enabled {
+            let message = DiscoveryMessage::new(
+                settings.websocket_port,
+                settings.http_port,
+                announcement.certificate_fingerprint.clone(),
+            );
+            running.tasks.push(tokio::spawn(broadcast_presence(
+                message.clone(),
+                settings.discovery.clone(),
+            )));
+            running.tasks.push(tokio::spawn(answer_probes(
+                message,
+                settings.discovery.clone(),
+            )));
+        }
+
+        if settings.mdns_enabled {
+            match advertise_mdns(
+                settings.websocket_port,
+                settings.http_port,
+                announcement.tls,
+                announcement.tls_pin.clone(),
+                &settings.discovery,
+            ) {
+                Ok(daemon) => {
+                    running.tasks.push(tokio::spawn(follow_mdns_interfaces(
+                        daemon.clone(),
+                        settings.discovery.clone(),
+                    )));
+                    running.mdns = Some(daemon);
+                }
+                // UDP discovery or a configured host IP still work
+                Err(e) => warn!("Failed to start mDNS advertisement: {}", e),
+            }
+        }
+
+        running
+    }
+
+    fn stop(self) {
+        for task in self.tasks {
+            task.abort();
+        }
+        if let Some(daemon) = self.mdns {
+            // Unregisters the service, telling browsers it is gone
+            let _ = daemon.shutdown();
+        }
+    }
+}
+
+/// Runs the discovery mechanisms enabled in the config, restarting them
+/// whenever a reload changes the discovery settings.
+pub async fn supervise(mut updates: watch::Receiver<Arc<Config>>, announcement: Announcement) {
+    let mut current: Option<(DiscoverySettings, RunningDiscovery)> = None;
+
+    loop {
+        let settings = DiscoverySettings::from_config(&updates.borrow_and_update());
+        if current
+            .as_ref()
+            .map(|(running_settings, _)| running_settings)
+            != Some(&settings)
+        {
+            if let Some((_, running)) = current.take() {
+                info!("Discovery settings changed, restarting discovery");
+                running.stop();
+            }
+            let running = RunningDiscovery::start(&settings, &announcement);
+            current = Some((settings, running));
+        }
+
+        if updates.changed().await.is_err() {
+            break;
+        }
+    }
+
+    if let Some((_, running)) = current {
+        running.stop();
+    }
 }
 
-pub async fn broadcast_presence(websocket_port: u16, http_port: u16) {
-    let socket = match UdpSocket::bind("0.0.0.0:0").await {
-        Ok(s) => s,
+/// Where one round of the presence broadcast is sent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BroadcastTarget {
+    /// The directed broadcast address of a subnet, sent from the interface's
+    /// own address so it leaves through that interface.
+    Subnet {
+        source: Ipv4Addr,
+        broadcast: Ipv4Addr,
+    },
+    /// Link-local all-nodes multicast on the interface with this index.
+    Multicast { interface: String, index: u32 },
+}
+
+/// Broadcasts `message` every `BROADCAST_INTERVAL` on each interface
+/// selected by `config`, re-reading the interfaces every time so networks
+/// joined later are covered.
+pub async fn broadcast_presence(message: DiscoveryMessage, config: DiscoveryConfig) {
+    let json = match serde_json::to_vec(&message) {
+        Ok(j) => j,
         Err(e) => {
-            error!("Failed to bind UDP socket for discovery: {}", e);
+            error!("Failed to serialize discovery message: {}", e);
             return;
         }
     };
 
-    if let Err(e) = socket.set_broadcast(true) {
-        error!("Failed to enable broadcast on UDP socket: {}", e);
-        return;
+    info!(
+        "Starting UDP discovery broadcast on port {}",
+        DISCOVERY_PORT
+    );
+
+    let mut last_targets = Vec::new();
+    loop {
+        let targets = broadcast_targets(&netif::list(), &config);
+        if targets != last_targets {
+            if targets.is_empty() {
+                info!("No interfaces selected for discovery, broadcasting to 255.255.255.255");
+            }
+            for target in &targets {
+                info!("Broadcasting discovery to {}", target_addr(target));
+            }
+            last_targets = targets.clone();
+        }
+
+        if targets.is_empty() {
+            let limited = BroadcastTarget::Subnet {
+                source: Ipv4Addr::UNSPECIFIED,
+                broadcast: Ipv4Addr::BROADCAST,
+            };
+            if let Err(e) = send_broadcast(&limited, &json).await {
+                debug!("Failed to send discovery broadcast: {}", e);
+            }
+        }
+        for target in &targets {
+            // Some networks don't allow broadcast, so this would repeat every round
+            if let Err(e) = send_broadcast(target, &json).await {
+                debug!(
+                    "Failed to send discovery broadcast to {}: {}",
+                    target_addr(target),
+                    e
+                );
+            }
+        }
+
+        tokio::time::sleep(BROADCAST_INTERVAL).await;
     }
+}
 
-    let message = DiscoveryMessage {
-        service: "androidoscopy",
-        version: "1.0",
-        websocket_port,
-        http_port,
+fn broadcast_targets(
+    interfaces: &[InterfaceAddr],
+    config: &DiscoveryConfig,
+) -> Vec<BroadcastTarget> {
+    let mut targets = Vec::new();
+
+    for interface in interfaces {
+        if !netif::is_selected(
+            &interface.name,
+            &config.interfaces,
+            &config.exclude_interfaces,
+        ) {
+            continue;
+        }
+
+        let target = match (interface.ip, interface.broadcast, interface.index) {
+            (IpAddr::V4(source), Some(broadcast), _) => {
+                BroadcastTarget::Subnet { source, broadcast }
+            }
+            (IpAddr::V6(_), _, Some(index)) if config.ipv6_enabled => BroadcastTarget::Multicast {
+                interface: interface.name.clone(),
+                index,
+            },
+            _ => continue,
+        };
+        // Interfaces have several IPv6 addresses, one multicast per interface is enough
+        if !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+
+    targets
+}
+
+fn target_addr(target: &BroadcastTarget) -> SocketAddr {
+    match target {
+        BroadcastTarget::Subnet { broadcast, .. } => SocketAddr::from((*broadcast, DISCOVERY_PORT)),
+        BroadcastTarget::Multicast { index, .. } => SocketAddr::V6(SocketAddrV6::new(
+            Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1),
+            DISCOVERY_PORT,
+            0,
+            *index,
+        )),
+    }
+}
+
+async fn send_broadcast(target: &BroadcastTarget, json: &[u8]) -> std::io::Result<()> {
+    let socket = match target {
+        BroadcastTarget::Subnet { source, .. } => {
+            let socket = UdpSocket::bind((*source, 0)).await?;
+            socket.set_broadcast(true)?;
+            socket
+        }
+        BroadcastTarget::Multicast { index, .. } => {
+            let socket = Socket::new(Domain::IPV6, Type::DGRAM, Some(Protocol::UDP))?;
+            socket.set_multicast_if_v6(*index)?;
+            socket.set_nonblocking(true)?;
+            socket.bind(&SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)).into())?;
+            UdpSocket::from_std(socket.into())?
+        }
     };
 
-    let json = match serde_json::to_string(&message) {
+    socket.send_to(json, target_addr(target)).await?;
+    Ok(())
+}
+
+/// Answers `DISCOVER` probes sent to `DISCOVERY_PORT`, for networks where
+/// apps can't receive the broadcast.
+pub async fn answer_probes(message: DiscoveryMessage, config: DiscoveryConfig) {
+    let mut addrs = vec![SocketAddr::from((Ipv4Addr::UNSPECIFIED, DISCOVERY_PORT))];
+    if config.ipv6_enabled {
+        addrs.push(SocketAddr::from((Ipv6Addr::UNSPECIFIED, DISCOVERY_PORT)));
+    }
+
+    let mut responders = Vec::new();
+    for addr in addrs {
+        match bind_probe_socket(addr) {
+            Ok(socket) => {
+                info!("Answering discovery probes on UDP {}", addr);
+                responders.push(serve_probes(socket, message.clone(), config.clone()));
+            }
+            // Broadcast and mDNS keep working without it
+            Err(e) => warn!("Failed to bind UDP {} for discovery probes: {}", addr, e),
+        }
+    }
+
+    // Not spawned, so stopping this future stops the responders too
+    join_all(responders).await;
+}
+
+fn bind_probe_socket(addr: SocketAddr) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(addr), Type::DGRAM, Some(Protocol::UDP))?;
+    if addr.is_ipv6() {
+        // Leaves IPv4 to the other socket, which dual-stack would conflict with
+        socket.set_only_v6(true)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+/// Replies to each probe received on `socket`, to the address it came from.
+/// Anything else, such as this server's own broadcasts, is ignored, as are
+/// probes from outside the networks discovery is enabled on.
+pub async fn serve_probes(socket: UdpSocket, message: DiscoveryMessage, config: DiscoveryConfig) {
+    let json = match serde_json::to_vec(&message) {
         Ok(j) => j,
         Err(e) => {
             error!("Failed to serialize discovery message: {}", e);
@@ -44,46 +384,318 @@ pub async fn broadcast_presence(websocket_port: u16, http_port: u16) {
         }
     };
 
-    let broadcast_addr: SocketAddr = format!("255.255.255.255:{}", DISCOVERY_PORT)
-        .parse()
-        .unwrap();
+    let mut interfaces = netif::list();
+    let mut listed_at = Instant::now();
+    let mut buf = [0u8; 512];
+    loop {
+        let (len, src) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                // e.g. ICMP port unreachable from an earlier reply, on Windows
+                debug!("Failed to receive discovery probe: {}", e);
+                continue;
+            }
+        };
+
+        if buf[..len].trim_ascii() != DISCOVER_PROBE {
+            continue;
+        }
+
+        // Re-read like the broadcast does, so networks joined later are covered
+        if listed_at.elapsed() >= BROADCAST_INTERVAL {
+            interfaces = netif::list();
+            listed_at = Instant::now();
+        }
+        if !probe_allowed(src, &interfaces, &config) {
+            debug!(
+                "Ignoring discovery probe from {}, not on a discovery network",
+                src
+            );
+            continue;
+        }
+
+        debug!("Discovery probe from {}", src);
+        if let Err(e) = socket.send_to(&json, src).await {
+            warn!("Failed to answer discovery probe from {}: {}", src, e);
+        }
+    }
+}
+
+/// Whether a probe from `src` gets a reply. Only this machine and the subnets
+/// of the selected interfaces are answered, so the server can't be used to
+/// reflect replies at hosts elsewhere, which would also tell them about it.
+fn probe_allowed(src: SocketAddr, interfaces: &[InterfaceAddr], config: &DiscoveryConfig) -> bool {
+    if src.ip().is_loopback() {
+        return true;
+    }
+
+    interfaces
+        .iter()
+        .filter(|interface| {
+            netif::is_selected(
+                &interface.name,
+                &config.interfaces,
+                &config.exclude_interfaces,
+            )
+        })
+        .any(|interface| match src {
+            // Every link uses fe80::/64, the scope tells which one the probe came in on
+            SocketAddr::V6(src) if src.ip().segments()[0] & 0xffc0 == 0xfe80 => {
+                interface.index == Some(src.scope_id())
+            }
+            _ => interface.contains(src.ip()),
+        })
+}
+
+/// Advertises the server over mDNS. The daemon answers queries from its own
+/// thread until it is shut down.
+pub fn advertise_mdns(
+    websocket_port: u16,
+    http_port: u16,
+    tls: bool,
+    tls_pin: Option<String>,
+    config: &DiscoveryConfig,
+) -> Result<ServiceDaemon, mdns_sd::Error> {
+    let hostname = local_hostname();
+    let instance_name = format!("Androidoscopy on {}", hostname);
+    let properties = mdns_properties(websocket_port, http_port, tls, tls_pin.as_deref());
+
+    // The addresses are filled in, and kept up to date, by the daemon
+    let service = ServiceInfo::new(
+        MDNS_SERVICE_TYPE,
+        &instance_name,
+        &format!("{}.local.", hostname),
+        "",
+        websocket_port,
+        &properties[..],
+    )?
+    .enable_addr_auto();
+
+    let daemon = ServiceDaemon::new()?;
+    // Interfaces are only used once enable_mdns_interfaces selects them
+    daemon.disable_interface(IfKind::All)?;
+    enable_mdns_interfaces(&daemon, config, &mut HashSet::new())?;
+    daemon.register(service)?;
 
     info!(
-        "Starting UDP discovery broadcast on port {} (broadcasting to {})",
-        DISCOVERY_PORT, broadcast_addr
+        "Advertising {} as \"{}\" over mDNS",
+        MDNS_SERVICE_TYPE, instance_name
     );
+    Ok(daemon)
+}
 
+/// Keeps enabling the interfaces selected by `config` on the mDNS daemon,
+/// re-reading them like the broadcast does so networks joined later are
+/// covered, and others are not.
+async fn follow_mdns_interfaces(daemon: ServiceDaemon, config: DiscoveryConfig) {
+    let mut enabled = HashSet::new();
     loop {
-        match socket.send_to(json.as_bytes(), broadcast_addr).await {
-            Ok(_) => {
-                // Successfully sent broadcast
-            }
-            Err(e) => {
-                // Some networks don't allow broadcast, just log and continue
-                error!("Failed to send discovery broadcast: {}", e);
-            }
+        if let Err(e) = enable_mdns_interfaces(&daemon, &config, &mut enabled) {
+            warn!("Failed to update mDNS interfaces: {}", e);
         }
-
         tokio::time::sleep(BROADCAST_INTERVAL).await;
     }
 }
 
+/// Enables the selected interfaces not in `enabled` yet, adding them to it.
+fn enable_mdns_interfaces(
+    daemon: &ServiceDaemon,
+    config: &DiscoveryConfig,
+    enabled: &mut HashSet<String>,
+) -> Result<(), mdns_sd::Error> {
+    let new: HashSet<String> = netif::list()
+        .into_iter()
+        .map(|interface| interface.name)
+        .filter(|name| !enabled.contains(name))
+        .filter(|name| netif::is_selected(name, &config.interfaces, &config.exclude_interfaces))
+        .collect();
+    if new.is_empty() {
+        return Ok(());
+    }
+
+    for name in &new {
+        debug!("Advertising over mDNS on {}", name);
+    }
+    daemon.enable_interface(new.iter().cloned().map(IfKind::Name).collect::<Vec<_>>())?;
+    // Later selections win, so this has to follow every enable
+    if !config.ipv6_enabled {
+        daemon.disable_interface(IfKind::IPv6)?;
+    }
+    enabled.extend(new);
+    Ok(())
+}
+
+fn mdns_properties(
+    websocket_port: u16,
+    http_port: u16,
+    tls: bool,
+    tls_pin: Option<&str>,
+) -> Vec<(&'static str, String)> {
+    let mut properties = vec![
+        ("version", PROTOCOL_VERSION.to_string()),
+        ("websocket_port", websocket_port.to_string()),
+        ("http_port", http_port.to_string()),
+        ("tls", tls.to_string()),
+    ];
+    if let Some(pin) = tls_pin {
+        properties.push(("pin", pin.to_string()));
+    }
+    properties
+}
+
+/// The machine's host name, reduced to what is valid as a single DNS label.
+fn local_hostname() -> String {
+    let hostname = gethostname::gethostname().to_string_lossy().to_string();
+    let label: String = hostname
+        .split('.')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+
+    if label.is_empty() {
+        "androidoscopy".to_string()
+    } else {
+        label
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn test_discovery_message_serialization() {
-        let message = DiscoveryMessage {
-            service: "androidoscopy",
-            version: "1.0",
-            websocket_port: 9999,
-            http_port: 8080,
-        };
+        let message = DiscoveryMessage::new(9999, 8080, Some("AB:CD".to_string()));
 
         let json = serde_json::to_string(&message).unwrap();
         assert!(json.contains("androidoscopy"));
         assert!(json.contains("9999"));
         assert!(json.contains("8080"));
+        assert!(json.contains(instance_id()));
+        assert!(json.contains("\"certificate_fingerprint\":\"AB:CD\""));
+
+        let json = serde_json::to_string(&DiscoveryMessage::new(9999, 8080, None)).unwrap();
+        assert!(!json.contains("certificate_fingerprint"));
+    }
+
+    #[test]
+    fn test_broadcast_targets() {
+        let interface = |name: &str, ip: &str, broadcast: Option<&str>, index: u32| InterfaceAddr {
+            name: name.to_string(),
+            ip: ip.parse().unwrap(),
+            broadcast: broadcast.map(|b| b.parse().unwrap()),
+            prefix_len: 24,
+            index: Some(index),
+        };
+        let interfaces = vec![
+            interface("eth0", "192.168.1.20", Some("192.168.1.255"), 2),
+            interface("eth0", "fe80::1", None, 2),
+            interface("eth0", "2001:db8::1", None, 2),
+            interface("wlan0", "10.0.0.5", Some("10.0.0.255"), 3),
+            interface("tun0", "10.8.0.2", None, 4),
+            interface("docker0", "172.17.0.1", Some("172.17.255.255"), 5),
+        ];
+
+        let targets = broadcast_targets(&interfaces, &DiscoveryConfig::default());
+        assert_eq!(
+            targets,
+            vec![
+                BroadcastTarget::Subnet {
+                    source: "192.168.1.20".parse().unwrap(),
+                    broadcast: "192.168.1.255".parse().unwrap(),
+                },
+                BroadcastTarget::Multicast {
+                    interface: "eth0".to_string(),
+                    index: 2,
+                },
+                BroadcastTarget::Subnet {
+                    source: "10.0.0.5".parse().unwrap(),
+                    broadcast: "10.0.0.255".parse().unwrap(),
+                },
+            ]
+        );
+        assert_eq!(target_addr(&targets[1]).to_string(), "[ff02::1%2]:9998");
+
+        let config = DiscoveryConfig {
+            interfaces: vec!["eth*".to_string()],
+            exclude_interfaces: Vec::new(),
+            ipv6_enabled: false,
+        };
+        let targets = broadcast_targets(&interfaces, &config);
+        assert_eq!(
+            targets,
+            vec![BroadcastTarget::Subnet {
+                source: "192.168.1.20".parse().unwrap(),
+                broadcast: "192.168.1.255".parse().unwrap(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_probe_allowed() {
+        let interface = |name: &str, ip: &str, prefix_len: u8, index: u32| InterfaceAddr {
+            name: name.to_string(),
+            ip: ip.parse().unwrap(),
+            broadcast: None,
+            prefix_len,
+            index: Some(index),
+        };
+        let interfaces = vec![
+            interface("eth0", "192.168.1.20", 24, 2),
+            interface("eth0", "fe80::1", 64, 2),
+            interface("docker0", "172.17.0.1", 16, 5),
+            interface("docker0", "fe80::2", 64, 5),
+        ];
+        let config = DiscoveryConfig {
+            interfaces: Vec::new(),
+            exclude_interfaces: vec!["docker*".to_string()],
+            ipv6_enabled: true,
+        };
+        let allowed = |src: &str| probe_allowed(src.parse().unwrap(), &interfaces, &config);
+
+        assert!(allowed("192.168.1.99:40000"));
+        assert!(allowed("127.0.0.1:40000"));
+        assert!(allowed("[fe80::1234%2]:40000"));
+        // Excluded interfaces and other networks go unanswered
+        assert!(!allowed("[fe80::1234%5]:40000"));
+        assert!(!allowed("169.254.10.1:40000"));
+        assert!(!allowed("172.17.0.2:40000"));
+        assert!(!allowed("203.0.113.7:40000"));
+        assert!(!allowed("[2001:db8::1]:40000"));
+    }
+
+    #[test]
+    fn test_mdns_properties() {
+        let properties = mdns_properties(8889, 8880, true, Some("sha256/abc="));
+        assert_eq!(
+            properties,
+            vec![
+                ("version", "1.0".to_string()),
+                ("websocket_port", "8889".to_string()),
+                ("http_port", "8880".to_string()),
+                ("tls", "true".to_string()),
+                ("pin", "sha256/abc=".to_string()),
+            ]
+        );
+
+        let properties = mdns_properties(8889, 8880, false, None);
+        assert!(!properties.iter().any(|(key, _)| *key == "pin"));
+    }
+
+    #[test]
+    fn test_local_hostname_is_a_dns_label() {
+        let hostname = local_hostname();
+        assert!(!hostname.is_empty());
+        assert!(hostname
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-'));
     }
 }
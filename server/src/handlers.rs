@@ -8,8 +8,8 @@
 //! # Connection Lifecycle
 //!
 //! ## App Connection (`/ws/app`)
-//! 1. Client connects
-//! 2. Client sends REGISTER message
+//! 1. Client connects, with a pairing token header if auth is enabled
+//! 2. Client sends REGISTER message (carrying the token if the upgrade didn't)
 //! 3. Server responds with REGISTERED (includes session_id)
 //! 4. Server notifies dashboards with SESSION_STARTED
 //! 5. Client can now send DATA/LOG messages
@@ -19,44 +19,111 @@
 //! 1. Client connects
 //! 2. Server immediately sends SYNC with all active sessions
 //! 3. Server forwards SESSION_DATA/SESSION_LOG from apps
-//! 4. Client can send ACTION messages to specific sessions
+//! 4. Client can send ACTION messages to specific sessions, unless its user is read-only
+//!
+//! Both endpoints negotiate a [`WireFormat`] from the `Sec-WebSocket-Protocol`
+//! header; see [`crate::wire`].
 
 use axum::{
     extract::{
         ws::{Message, WebSocket, WebSocketUpgrade},
         State,
     },
-    response::IntoResponse,
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    Extension,
 };
 use chrono::Utc;
 use futures::{SinkExt, StreamExt};
 use tokio::sync::mpsc;
-use tracing::{error, info, warn};
+use tracing::{error, field, info, info_span, warn, Instrument, Span};
 
+use crate::attachments::{BlobAssembler, BlobError};
+use crate::auth;
+use crate::config::Role;
+use crate::dashboard_auth::AuthenticatedUser;
 use crate::protocol::{
-    ActionResultToDashboardPayload, AppMessage, DashboardToServiceMessage, LogEntry,
-    RegisteredPayload, ServiceToAppMessage, ServiceToDashboardMessage, SessionDataPayload,
-    SessionEndedPayload, SessionLogPayload, SessionStartedPayload, SyncPayload,
+    ActionResultToDashboardPayload, AppMessage, AttachmentAddedPayload, BlobStoredPayload,
+    DashboardActionPayload, DashboardToServiceMessage, ErrorPayload, LogEntry, RegisteredPayload,
+    ServiceToAppMessage, ServiceToDashboardMessage, SessionDataPayload, SessionEndedPayload,
+    SessionLogPayload, SessionStartedPayload, SyncPayload,
 };
 use crate::state::AppState;
+use crate::wire::{WireFormat, SUPPORTED_SUBPROTOCOLS};
 
 /// Handles WebSocket upgrade for app connections.
+///
+/// With `[server.auth]` enabled, a pairing token in the upgrade headers is
+/// checked here; without one, the app must send it in REGISTER instead.
 pub async fn handle_app_ws(
     ws: WebSocketUpgrade,
+    headers: HeaderMap,
     State(state): State<AppState>,
-) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_app_connection(socket, state))
+) -> Response {
+    let mut needs_token = state.config().server.auth.enabled;
+    if needs_token {
+        if let Some(token) = auth::token_from_headers(&headers) {
+            if !app_token_matches(&state, token) {
+                warn!("Rejected app connection with an invalid pairing token");
+                return StatusCode::UNAUTHORIZED.into_response();
+            }
+            needs_token = false;
+        }
+    }
+
+    let max_size = state.config().limits.max_message_size;
+
+    ws.protocols(SUPPORTED_SUBPROTOCOLS)
+        .max_message_size(max_size)
+        .max_frame_size(max_size)
+        .on_upgrade(move |socket| {
+            // Filled in on REGISTER, then on every line logged for the connection
+            let span = info_span!("app", session_id = field::Empty, device_id = field::Empty);
+            handle_app_connection(socket, state, needs_token).instrument(span)
+        })
 }
 
+fn app_token_matches(state: &AppState, provided: &str) -> bool {
+    state
+        .config()
+        .server
+        .auth
+        .token
+        .as_deref()
+        .is_some_and(|expected| auth::tokens_match(expected, provided))
+}
+
+/// Handles WebSocket upgrade for dashboard connections.
+///
+/// The user comes from [`crate::dashboard_auth::require_login`]; without one the
+/// connection is only accepted when dashboard auth is disabled.
 pub async fn handle_dashboard_ws(
     ws: WebSocketUpgrade,
+    user: Option<Extension<AuthenticatedUser>>,
     State(state): State<AppState>,
-) -> impl IntoResponse {
-    ws.on_upgrade(|socket| handle_dashboard_connection(socket, state))
+) -> Response {
+    let role = match user {
+        Some(Extension(user)) => user.role,
+        None if !state.config().dashboard.auth.enabled => Role::ReadWrite,
+        None => return StatusCode::UNAUTHORIZED.into_response(),
+    };
+
+    let max_size = state.config().limits.max_message_size;
+
+    ws.protocols(SUPPORTED_SUBPROTOCOLS)
+        .max_message_size(max_size)
+        .max_frame_size(max_size)
+        .on_upgrade(move |socket| handle_dashboard_connection(socket, state, role))
+}
+
+/// Returns the wire format negotiated during the WebSocket upgrade.
+fn negotiated_format(socket: &WebSocket) -> WireFormat {
+    WireFormat::from_subprotocol(socket.protocol().and_then(|p| p.to_str().ok()))
 }
 
-async fn handle_app_connection(socket: WebSocket, state: AppState) {
-    info!("App WebSocket connection established");
+async fn handle_app_connection(socket: WebSocket, state: AppState, needs_token: bool) {
+    let format = negotiated_format(&socket);
+    info!("App WebSocket connection established ({:?})", format);
 
     let (mut sender, mut receiver) = socket.split();
 
@@ -66,12 +133,34 @@ async fn handle_app_connection(socket: WebSocket, state: AppState) {
     // Wait for REGISTER message
     let session_id = loop {
         match receiver.next().await {
-            Some(Ok(Message::Text(text))) => {
-                match serde_json::from_str::<AppMessage>(&text) {
+            Some(Ok(frame @ (Message::Text(_) | Message::Binary(_)))) => {
+                match format.decode::<AppMessage>(&frame, state.config().limits.max_message_size) {
                     Ok(AppMessage::Register { payload, .. }) => {
-                        // Validate the message
+                        Span::current().record("device_id", payload.device.device_id.as_str());
+                        let authenticated = !needs_token
+                            || payload
+                                .auth_token
+                                .as_deref()
+                                .is_some_and(|token| app_token_matches(&state, token));
+                        if !authenticated {
+                            warn!("App {} sent no valid pairing token", payload.package_name);
+                            let error = ServiceToAppMessage::Error {
+                                timestamp: Utc::now(),
+                                payload: ErrorPayload {
+                                    code: "UNAUTHORIZED".to_string(),
+                                    message: "Missing or invalid pairing token".to_string(),
+                                },
+                            };
+                            if let Ok(frame) = format.encode(&error) {
+                                sender.send(frame).await.ok();
+                            }
+                            sender.send(Message::Close(None)).await.ok();
+                            return;
+                        }
+
                         let mut manager = state.session_manager.lock().await;
                         let (session_id, resumed) = manager.create_session(payload, tx.clone());
+                        Span::current().record("session_id", session_id.as_str());
 
                         // Send REGISTERED response
                         let response = ServiceToAppMessage::Registered {
@@ -81,8 +170,8 @@ async fn handle_app_connection(socket: WebSocket, state: AppState) {
                             },
                         };
 
-                        if let Ok(json) = serde_json::to_string(&response) {
-                            if sender.send(Message::Text(json.into())).await.is_err() {
+                        if let Ok(frame) = format.encode(&response) {
+                            if sender.send(frame).await.is_err() {
                                 error!("Failed to send REGISTERED response");
                                 return;
                             }
@@ -126,7 +215,7 @@ async fn handle_app_connection(socket: WebSocket, state: AppState) {
                 return;
             }
             Some(Ok(_)) => {
-                // Ignore binary, ping, pong
+                // Ignore ping, pong
             }
             Some(Err(e)) => {
                 error!("WebSocket error: {}", e);
@@ -139,23 +228,41 @@ async fn handle_app_connection(socket: WebSocket, state: AppState) {
     let session_id_clone = session_id.clone();
     let forward_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
-            if let Ok(json) = serde_json::to_string(&msg) {
-                if sender.send(Message::Text(json.into())).await.is_err() {
+            if let Ok(frame) = format.encode(&msg) {
+                if sender.send(frame).await.is_err() {
                     break;
                 }
             }
         }
     });
 
+    // Partial BLOB uploads, deleted if the app disconnects mid-transfer
+    let mut blobs = BlobAssembler::new(state.attachment_store.clone(), session_id.clone());
+
     // Main message loop
     while let Some(result) = receiver.next().await {
         match result {
-            Ok(Message::Text(text)) => {
-                match serde_json::from_str::<AppMessage>(&text) {
+            Ok(frame @ (Message::Text(_) | Message::Binary(_))) => {
+                // Read per message, so reloaded limits apply to open connections too
+                let config = state.config();
+                let limits = &config.limits;
+                match format.decode::<AppMessage>(&frame, limits.max_message_size) {
                     Ok(msg) => {
+                        state.stats.app_messages.record();
+
                         // Validate message
-                        if let Err(e) = msg.validate() {
+                        if let Err(e) = msg.validate(limits) {
                             warn!("Message validation failed: {}", e);
+                            let error = ServiceToAppMessage::Error {
+                                timestamp: Utc::now(),
+                                payload: ErrorPayload {
+                                    code: e.code().to_string(),
+                                    message: e.to_string(),
+                                },
+                            };
+                            if tx.send(error).await.is_err() {
+                                warn!("Failed to send ERROR to app");
+                            }
                             continue;
                         }
 
@@ -238,6 +345,123 @@ async fn handle_app_connection(socket: WebSocket, state: AppState) {
                                 };
                                 broadcast_to_dashboards(&manager, msg).await;
                             }
+                            AppMessage::BlobBegin {
+                                session_id: msg_session_id,
+                                payload,
+                                ..
+                            } => {
+                                if msg_session_id != session_id_clone {
+                                    warn!("Session ID mismatch in BLOB_BEGIN message");
+                                    continue;
+                                }
+
+                                let blob_id = payload.blob_id.clone();
+                                let quota = {
+                                    let manager = state.session_manager.lock().await;
+                                    match manager.get_session(&session_id_clone) {
+                                        Some(session) => state
+                                            .attachment_store
+                                            .check_quota(session.attachments(), payload.size),
+                                        None => Ok(()),
+                                    }
+                                };
+                                let result = match quota {
+                                    Ok(()) => blobs.begin(payload).await,
+                                    Err(e) => Err(e),
+                                };
+                                if let Err(e) = result {
+                                    send_blob_error(&tx, &blob_id, e).await;
+                                }
+                            }
+                            AppMessage::BlobChunk {
+                                session_id: msg_session_id,
+                                payload,
+                                ..
+                            } => {
+                                if msg_session_id != session_id_clone {
+                                    warn!("Session ID mismatch in BLOB_CHUNK message");
+                                    continue;
+                                }
+
+                                let blob_id = payload.blob_id.clone();
+                                if let Err(e) = blobs.push_chunk(payload).await {
+                                    send_blob_error(&tx, &blob_id, e).await;
+                                }
+                            }
+                            AppMessage::BlobEnd {
+                                session_id: msg_session_id,
+                                payload,
+                                ..
+                            } => {
+                                if msg_session_id != session_id_clone {
+                                    warn!("Session ID mismatch in BLOB_END message");
+                                    continue;
+                                }
+
+                                let attachment = match blobs.finish(&payload.blob_id).await {
+                                    Ok(attachment) => attachment,
+                                    Err(e) => {
+                                        send_blob_error(&tx, &payload.blob_id, e).await;
+                                        continue;
+                                    }
+                                };
+
+                                // Concurrent uploads may have used up the quota since BLOB_BEGIN
+                                let quota = {
+                                    let manager = state.session_manager.lock().await;
+                                    match manager.get_session(&session_id_clone) {
+                                        Some(session) => state
+                                            .attachment_store
+                                            .check_quota(session.attachments(), attachment.size),
+                                        None => Ok(()),
+                                    }
+                                };
+                                if let Err(e) = quota {
+                                    let removed = state
+                                        .attachment_store
+                                        .remove(&session_id_clone, &attachment.id)
+                                        .await;
+                                    if let Err(err) = removed {
+                                        error!(
+                                            "Failed to remove attachment {}: {}",
+                                            attachment.id, err
+                                        );
+                                    }
+                                    send_blob_error(&tx, &payload.blob_id, e).await;
+                                    continue;
+                                }
+
+                                let info = attachment.to_attachment_info();
+                                let mut manager = state.session_manager.lock().await;
+                                manager.add_attachment(&session_id_clone, info.clone());
+                                info!(
+                                    "Stored attachment {} ({} bytes) for session {}",
+                                    info.name, info.size, session_id_clone
+                                );
+
+                                // Notify dashboards
+                                let msg = ServiceToDashboardMessage::AttachmentAdded {
+                                    timestamp: Utc::now(),
+                                    payload: AttachmentAddedPayload {
+                                        session_id: session_id_clone.clone(),
+                                        attachment: info.clone(),
+                                    },
+                                };
+                                broadcast_to_dashboards(&manager, msg).await;
+
+                                drop(manager);
+
+                                let response = ServiceToAppMessage::BlobStored {
+                                    timestamp: Utc::now(),
+                                    payload: BlobStoredPayload {
+                                        blob_id: payload.blob_id,
+                                        attachment: info,
+                                    },
+                                };
+                                if tx.send(response).await.is_err() {
+                                    warn!("Failed to send BLOB_STORED to app");
+                                }
+                            }
                             AppMessage::Register { .. } => {
                                 warn!("Received duplicate REGISTER message");
                             }
@@ -253,7 +477,7 @@ async fn handle_app_connection(socket: WebSocket, state: AppState) {
                 break;
             }
             Ok(_) => {
-                // Ignore binary, ping, pong
+                // Ignore ping, pong
             }
             Err(e) => {
                 error!("WebSocket error: {}", e);
@@ -283,8 +507,9 @@ async fn handle_app_connection(socket: WebSocket, state: AppState) {
     info!("App disconnected, session {} ended", session_id);
 }
 
-async fn handle_dashboard_connection(socket: WebSocket, state: AppState) {
-    info!("Dashboard WebSocket connection established");
+async fn handle_dashboard_connection(socket: WebSocket, state: AppState, role: Role) {
+    let format = negotiated_format(&socket);
+    info!("Dashboard WebSocket connection established ({:?})", format);
 
     let (mut sender, mut receiver) = socket.split();
 
@@ -297,13 +522,17 @@ async fn handle_dashboard_connection(socket: WebSocket, state: AppState) {
         manager.add_dashboard_sender(tx.clone());
 
         // Send SYNC with all active sessions
-        let sessions: Vec<_> = manager.get_active_sessions().iter().map(|s| s.to_session_info()).collect();
+        let sessions: Vec<_> = manager
+            .get_active_sessions()
+            .iter()
+            .map(|s| s.to_session_info())
+            .collect();
         let sync_msg = ServiceToDashboardMessage::Sync {
             payload: SyncPayload { sessions },
         };
 
-        if let Ok(json) = serde_json::to_string(&sync_msg) {
-            if sender.send(Message::Text(json.into())).await.is_err() {
+        if let Ok(frame) = format.encode(&sync_msg) {
+            if sender.send(frame).await.is_err() {
                 error!("Failed to send SYNC message");
                 manager.remove_dashboard_sender(&tx);
                 return;
@@ -312,12 +541,14 @@ async fn handle_dashboard_connection(socket: WebSocket, state: AppState) {
     }
 
     // Spawn task to forward messages from rx to WebSocket
+    let stats = state.stats.clone();
     let forward_task = tokio::spawn(async move {
         while let Some(msg) = rx.recv().await {
-            if let Ok(json) = serde_json::to_string(&msg) {
-                if sender.send(Message::Text(json.into())).await.is_err() {
+            if let Ok(frame) = format.encode(&msg) {
+                if sender.send(frame).await.is_err() {
                     break;
                 }
+                stats.dashboard_messages.record();
             }
         }
     });
@@ -325,42 +556,20 @@ async fn handle_dashboard_connection(socket: WebSocket, state: AppState) {
     // Main message loop - handle incoming ACTION messages
     while let Some(result) = receiver.next().await {
         match result {
-            Ok(Message::Text(text)) => {
-                match serde_json::from_str::<DashboardToServiceMessage>(&text) {
+            Ok(frame @ (Message::Text(_) | Message::Binary(_))) => {
+                match format.decode::<DashboardToServiceMessage>(
+                    &frame,
+                    state.config().limits.max_message_size,
+                ) {
                     Ok(DashboardToServiceMessage::Action { payload }) => {
-                        // Forward ACTION to the appropriate app
-                        let mut manager = state.session_manager.lock().await;
-                        let session_id = &payload.session_id;
-
-                        // Handle network_clear action - also clear server-side accumulated requests
-                        if payload.action == "network_clear" {
-                            if let Some(session) = manager.get_session_mut(session_id) {
-                                session.clear_network_requests();
-                            }
-                        }
-
-                        if let Some(session) = manager.get_session(session_id) {
-                            if let Some(ref app_sender) = session.app_sender {
-                                let action_payload = crate::protocol::ActionPayload {
-                                    action_id: payload.action_id,
-                                    action: payload.action,
-                                    args: payload.args,
-                                };
-                                let action_msg = ServiceToAppMessage::Action {
-                                    timestamp: Utc::now(),
-                                    session_id: session_id.clone(),
-                                    payload: action_payload,
-                                };
-
-                                if app_sender.send(action_msg).await.is_err() {
-                                    warn!("Failed to send ACTION to app for session {}", session_id);
-                                }
-                            } else {
-                                warn!("Session {} has no active app connection", session_id);
-                            }
-                        } else {
-                            warn!("Session {} not found for ACTION", session_id);
-                        }
+                        let span = info_span!(
+                            "action",
+                            session_id = %payload.session_id,
+                            device_id = field::Empty
+                        );
+                        handle_dashboard_action(&state, &tx, role, payload)
+                            .instrument(span)
+                            .await;
                     }
                     Err(e) => {
                         warn!("Failed to parse dashboard message: {}", e);
@@ -372,7 +581,7 @@ async fn handle_dashboard_connection(socket: WebSocket, state: AppState) {
                 break;
             }
             Ok(_) => {
-                // Ignore binary, ping, pong
+                // Ignore ping, pong
             }
             Err(e) => {
                 error!("WebSocket error: {}", e);
@@ -392,6 +601,87 @@ async fn handle_dashboard_connection(socket: WebSocket, state: AppState) {
     info!("Dashboard disconnected");
 }
 
+/// Forwards an ACTION from a dashboard to the app of its session.
+async fn handle_dashboard_action(
+    state: &AppState,
+    tx: &mpsc::Sender<ServiceToDashboardMessage>,
+    role: Role,
+    payload: DashboardActionPayload,
+) {
+    if role == Role::ReadOnly {
+        warn!(
+            "Read-only dashboard user tried to send ACTION {}",
+            payload.action
+        );
+        let msg = ServiceToDashboardMessage::ActionResult {
+            timestamp: Utc::now(),
+            payload: ActionResultToDashboardPayload {
+                session_id: payload.session_id,
+                action_id: payload.action_id,
+                success: false,
+                message: Some("Read-only users can't send actions".to_string()),
+                data: None,
+            },
+        };
+        tx.send(msg).await.ok();
+        return;
+    }
+
+    // Forward ACTION to the appropriate app
+    let mut manager = state.session_manager.lock().await;
+    let session_id = &payload.session_id;
+
+    // Handle network_clear action - also clear server-side accumulated requests
+    if payload.action == "network_clear" {
+        if let Some(session) = manager.get_session_mut(session_id) {
+            session.clear_network_requests();
+        }
+    }
+
+    if let Some(session) = manager.get_session(session_id) {
+        Span::current().record("device_id", session.device.device_id.as_str());
+        if let Some(ref app_sender) = session.app_sender {
+            let action_payload = crate::protocol::ActionPayload {
+                action_id: payload.action_id,
+                action: payload.action,
+                args: payload.args,
+            };
+            let action_msg = ServiceToAppMessage::Action {
+                timestamp: Utc::now(),
+                session_id: session_id.clone(),
+                payload: action_payload,
+            };
+
+            if app_sender.send(action_msg).await.is_err() {
+                warn!("Failed to send ACTION to app for session {}", session_id);
+            }
+        } else {
+            warn!("Session {} has no active app connection", session_id);
+        }
+    } else {
+        warn!("Session {} not found for ACTION", session_id);
+    }
+}
+
+async fn send_blob_error(
+    app_sender: &mpsc::Sender<ServiceToAppMessage>,
+    blob_id: &str,
+    error: BlobError,
+) {
+    warn!("Blob upload {} failed: {}", blob_id, error);
+
+    let msg = ServiceToAppMessage::Error {
+        timestamp: Utc::now(),
+        payload: ErrorPayload {
+            code: error.code().to_string(),
+            message: format!("{}: {}", blob_id, error),
+        },
+    };
+    if app_sender.send(msg).await.is_err() {
+        warn!("Failed to send ERROR to app");
+    }
+}
+
 async fn broadcast_to_dashboards(
     manager: &crate::session::SessionManager,
     msg: ServiceToDashboardMessage,
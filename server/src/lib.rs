@@ -1,29 +1,112 @@
+pub mod api;
+pub mod attachments;
+pub mod auth;
+pub mod ca;
+pub mod certs;
+pub mod client;
 pub mod config;
+pub mod control;
+pub mod dashboard;
+pub mod dashboard_auth;
 pub mod discovery;
 pub mod handlers;
+pub mod logcat;
+pub mod logging;
+pub mod netif;
+pub mod onboarding;
 pub mod protocol;
+pub mod reload;
+pub mod service;
 pub mod session;
 pub mod state;
+pub mod stats;
+pub mod tls;
+pub mod wire;
 
-use axum::{routing::get, Router};
+use axum::{
+    http::{header, HeaderMap, Uri},
+    middleware,
+    response::Redirect,
+    routing::get,
+    Router,
+};
 use std::net::SocketAddr;
 use tokio::net::TcpListener;
 
 pub use config::Config;
 pub use state::AppState;
 
+/// Routes served on the HTTP port: the dashboard, its WebSocket and the HTTP API.
+/// Everything but `/login`, the certificate and the health endpoints sits behind
+/// [`dashboard_auth::require_login`].
+pub fn dashboard_routes(state: &AppState) -> Router<AppState> {
+    let protected = Router::new()
+        .route("/ws/dashboard", get(handlers::handle_dashboard_ws))
+        .route("/api/sessions", get(api::list_sessions))
+        .route("/api/sessions/:session_id", get(api::get_session))
+        .route(
+            "/api/sessions/:session_id/attachments",
+            get(api::list_attachments),
+        )
+        .route(
+            "/api/sessions/:session_id/attachments/:attachment_id",
+            get(api::download_attachment),
+        )
+        .route("/api/onboarding", get(api::onboarding))
+        .route("/api/onboarding/qr.svg", get(api::onboarding_qr))
+        .fallback(dashboard::serve_embedded)
+        .layer(middleware::from_fn_with_state(
+            state.clone(),
+            dashboard_auth::require_login,
+        ));
+
+    Router::new()
+        .route(
+            "/login",
+            get(dashboard_auth::login_page).post(dashboard_auth::login),
+        )
+        .route("/logout", get(dashboard_auth::logout))
+        .route("/api/certificate", get(api::certificate_info))
+        .route("/api/certificate.pem", get(api::certificate_pem))
+        .route("/api/certificate.der", get(api::certificate_der))
+        .route("/healthz", get(api::healthz))
+        .route("/readyz", get(api::readyz))
+        .route("/version", get(api::version))
+        .merge(protected)
+}
+
+/// Routes for the plain HTTP listener in front of an HTTPS dashboard: every
+/// request is redirected to the same path on `https_port`.
+pub fn https_redirect(https_port: u16) -> Router {
+    Router::new().fallback(move |headers: HeaderMap, uri: Uri| async move {
+        let host = headers
+            .get(header::HOST)
+            .and_then(|host| host.to_str().ok())
+            .unwrap_or("localhost");
+        // Strip the port, keeping IPv6 literals like [::1] intact
+        let host = match host.rsplit_once(':') {
+            Some((name, port)) if !name.is_empty() && !port.contains(']') => name,
+            _ => host,
+        };
+        let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+
+        Redirect::permanent(&format!("https://{}:{}{}", host, https_port, path))
+    })
+}
+
 /// Creates the Axum router for the server.
 /// Useful for testing - can be used with `axum::serve` directly.
 pub fn create_router(state: AppState) -> Router {
-    Router::new()
+    dashboard_routes(&state)
         .route("/ws/app", get(handlers::handle_app_ws))
-        .route("/ws/dashboard", get(handlers::handle_dashboard_ws))
         .with_state(state)
 }
 
 /// Starts the server on a random available port and returns the address.
 /// Useful for integration tests.
-pub async fn start_test_server(config: Config) -> std::io::Result<(SocketAddr, tokio::task::JoinHandle<()>)> {
+pub async fn start_test_server(
+    config: Config,
+) -> std::io::Result<(SocketAddr, tokio::task::JoinHandle<()>)> {
     let state = AppState::new(config);
     let app = create_router(state);
 
@@ -31,7 +114,12 @@ pub async fn start_test_server(config: Config) -> std::io::Result<(SocketAddr, t
     let addr = listener.local_addr()?;
 
     let handle = tokio::spawn(async move {
-        axum::serve(listener, app).await.ok();
+        axum::serve(
+            listener,
+            app.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .await
+        .ok();
     });
 
     Ok((addr, handle))
@@ -0,0 +1,184 @@
+//! Logcat-style rendering of app logs, for `androidoscopy logs`.
+
+use chrono::Local;
+
+use crate::protocol::{LogEntry, LogLevel};
+
+/// Which log entries to print.
+#[derive(Debug, Clone, Default)]
+pub struct LogFilter {
+    /// Lowest level printed; all levels when unset.
+    pub min_level: Option<LogLevel>,
+    /// Entries must have a tag containing one of these; any tag when empty.
+    pub tags: Vec<String>,
+}
+
+impl LogFilter {
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        if self
+            .min_level
+            .is_some_and(|min_level| entry.level < min_level)
+        {
+            return false;
+        }
+        self.tags.is_empty()
+            || entry
+                .tag
+                .as_deref()
+                .is_some_and(|tag| self.tags.iter().any(|wanted| tag.contains(wanted.as_str())))
+    }
+}
+
+/// Parses a level by name or by its logcat letter, ignoring case.
+pub fn parse_level(name: &str) -> Result<LogLevel, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "v" | "verbose" => Ok(LogLevel::Verbose),
+        "d" | "debug" => Ok(LogLevel::Debug),
+        "i" | "info" => Ok(LogLevel::Info),
+        "w" | "warn" => Ok(LogLevel::Warn),
+        "e" | "error" => Ok(LogLevel::Error),
+        _ => Err(format!(
+            "unknown level `{}`, expected verbose, debug, info, warn or error",
+            name
+        )),
+    }
+}
+
+fn letter(level: LogLevel) -> char {
+    match level {
+        LogLevel::Verbose => 'V',
+        LogLevel::Debug => 'D',
+        LogLevel::Info => 'I',
+        LogLevel::Warn => 'W',
+        LogLevel::Error => 'E',
+    }
+}
+
+fn color(level: LogLevel) -> &'static str {
+    match level {
+        LogLevel::Verbose => "\x1b[2m",
+        LogLevel::Debug => "\x1b[34m",
+        LogLevel::Info => "\x1b[32m",
+        LogLevel::Warn => "\x1b[33m",
+        LogLevel::Error => "\x1b[31m",
+    }
+}
+
+/// Formats an entry like `logcat -v time`, preceded by `prefix` (e.g. the
+/// device) and followed by the throwable indented on its own lines. Every
+/// line ends with a newline and, with `colored`, is colored by level.
+pub fn format_entry(entry: &LogEntry, prefix: Option<&str>, colored: bool) -> String {
+    let time = entry
+        .timestamp
+        .with_timezone(&Local)
+        .format("%m-%d %H:%M:%S%.3f");
+    let mut first = match prefix {
+        Some(prefix) => format!("[{}] ", prefix),
+        None => String::new(),
+    };
+    first.push_str(&format!("{} {} ", time, letter(entry.level)));
+    if let Some(tag) = &entry.tag {
+        first.push_str(&format!("{}: ", tag));
+    }
+
+    // Continuation lines of the message line up with its first line
+    let indent = " ".repeat(first.chars().count());
+    let mut lines: Vec<String> = entry
+        .message
+        .lines()
+        .enumerate()
+        .map(|(i, line)| {
+            if i == 0 {
+                format!("{}{}", first, line)
+            } else {
+                format!("{}{}", indent, line)
+            }
+        })
+        .collect();
+    if lines.is_empty() {
+        lines.push(first.trim_end().to_string());
+    }
+    if let Some(throwable) = &entry.throwable {
+        lines.extend(throwable.lines().map(|line| format!("    {}", line)));
+    }
+
+    let mut output = String::new();
+    for line in lines {
+        if colored {
+            output.push_str(&format!("{}{}\x1b[0m\n", color(entry.level), line));
+        } else {
+            output.push_str(&line);
+            output.push('\n');
+        }
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn entry(
+        level: LogLevel,
+        tag: Option<&str>,
+        message: &str,
+        throwable: Option<&str>,
+    ) -> LogEntry {
+        LogEntry {
+            timestamp: Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap(),
+            level,
+            tag: tag.map(str::to_string),
+            message: message.to_string(),
+            throwable: throwable.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_filter() {
+        let filter = LogFilter {
+            min_level: Some(LogLevel::Warn),
+            tags: vec!["Net".to_string()],
+        };
+
+        assert!(filter.matches(&entry(
+            LogLevel::Error,
+            Some("NetworkClient"),
+            "failed",
+            None
+        )));
+        assert!(!filter.matches(&entry(LogLevel::Info, Some("NetworkClient"), "ok", None)));
+        assert!(!filter.matches(&entry(LogLevel::Warn, Some("Database"), "slow", None)));
+        assert!(!filter.matches(&entry(LogLevel::Warn, None, "untagged", None)));
+        assert!(LogFilter::default().matches(&entry(LogLevel::Verbose, None, "anything", None)));
+    }
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(parse_level("warn").unwrap(), LogLevel::Warn);
+        assert_eq!(parse_level("E").unwrap(), LogLevel::Error);
+        assert!(parse_level("loud").is_err());
+    }
+
+    #[test]
+    fn test_format_entry() {
+        let log = entry(
+            LogLevel::Error,
+            Some("Net"),
+            "Request failed",
+            Some("java.io.IOException: timeout\n\tat Client.get(Client.kt:10)"),
+        );
+
+        let output = format_entry(&log, Some("Pixel 8"), false);
+        let lines: Vec<&str> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("[Pixel 8] 03-01 "));
+        assert!(lines[0].ends_with(".000 E Net: Request failed"));
+        assert_eq!(lines[1], "    java.io.IOException: timeout");
+        assert_eq!(lines[2], "    \tat Client.get(Client.kt:10)");
+
+        let output = format_entry(&log, None, true);
+        assert!(output.starts_with("\x1b[31m03-01 "));
+        assert!(output.lines().all(|line| line.ends_with("\x1b[0m")));
+    }
+}
@@ -0,0 +1,369 @@
+//! Server logs, as configured in `[logging]`.
+//!
+//! Logs go to stdout, and to `file` when set, as plain text or as one JSON
+//! object per line. The log file is rotated by size: `server.log` moves to
+//! `server.log.1`, `server.log.1` to `server.log.2` and so on, keeping
+//! `max_files` old files.
+//!
+//! App connections log inside an `app` span and dashboard ACTIONs inside an
+//! `action` span, both carrying `session_id` and `device_id`. In JSON lines,
+//! span fields are merged into the top-level object.
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::field::RecordFields;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields, MakeWriter};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{reload, EnvFilter, Layer, Registry};
+
+use crate::config::{LogFormat, LoggingConfig};
+
+/// Changes the log filter of the running server.
+pub type FilterHandle = reload::Handle<EnvFilter, Registry>;
+
+/// Sets up logging for the process.
+pub fn init(config: &LoggingConfig) -> Result<FilterHandle, Box<dyn std::error::Error>> {
+    let (filter, handle) = reload::Layer::new(filter(config)?);
+
+    let mut layers = vec![fmt_layer(config.format, io::stdout, true)];
+    if let Some(path) = &config.file {
+        let file = RotatingFile::open(path, config.max_file_size, config.max_files)
+            .map_err(|e| format!("failed to open log file {}: {}", path, e))?;
+        layers.push(fmt_layer(config.format, Mutex::new(file), false));
+    }
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layers)
+        .try_init()?;
+    Ok(handle)
+}
+
+/// Switches to the level in `config`.
+pub fn update_filter(handle: &FilterHandle, config: &LoggingConfig) -> Result<(), String> {
+    let filter = filter(config)?;
+    handle.reload(filter).map_err(|e| e.to_string())
+}
+
+/// The filter for `level`, for this crate and the HTTP layer. `RUST_LOG`
+/// overrides it.
+pub fn filter(config: &LoggingConfig) -> Result<EnvFilter, String> {
+    if let Ok(filter) = EnvFilter::try_from_default_env() {
+        return Ok(filter);
+    }
+
+    let level: LevelFilter = config.level.parse().map_err(|_| {
+        format!(
+            "invalid logging.level `{}`, expected trace, debug, info, warn, error or off",
+            config.level
+        )
+    })?;
+    Ok(EnvFilter::new(format!(
+        "androidoscopy={level},androidoscopy_server={level},tower_http={level}"
+    )))
+}
+
+fn fmt_layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    match format {
+        LogFormat::Text => layer.with_ansi(ansi).boxed(),
+        LogFormat::Json => layer
+            .with_ansi(false)
+            .event_format(JsonFormat)
+            .fmt_fields(JsonFields)
+            .boxed(),
+    }
+}
+
+/// Formats events as single-line JSON objects.
+pub struct JsonFormat;
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut object = Map::new();
+        object.insert(
+            "timestamp".to_string(),
+            Utc::now()
+                .to_rfc3339_opts(SecondsFormat::Millis, true)
+                .into(),
+        );
+        object.insert("level".to_string(), metadata.level().as_str().into());
+        object.insert("target".to_string(), metadata.target().into());
+
+        if let Some(scope) = ctx.event_scope() {
+            // Inner spans win over outer ones, and the event over all of them
+            for span in scope.from_root() {
+                let extensions = span.extensions();
+                let fields = extensions
+                    .get::<FormattedFields<N>>()
+                    .and_then(|fields| serde_json::from_str::<Map<String, Value>>(fields).ok());
+                object.extend(fields.unwrap_or_default());
+                object.insert("span".to_string(), span.name().into());
+            }
+        }
+
+        event.record(&mut JsonVisitor(&mut object));
+        writeln!(writer, "{}", Value::Object(object))
+    }
+}
+
+/// Formats span fields as a JSON object, for [`JsonFormat`].
+pub struct JsonFields;
+
+impl<'w> FormatFields<'w> for JsonFields {
+    fn format_fields<R: RecordFields>(&self, mut writer: Writer<'w>, fields: R) -> fmt::Result {
+        let mut object = Map::new();
+        fields.record(&mut JsonVisitor(&mut object));
+        write!(writer, "{}", Value::Object(object))
+    }
+
+    fn add_fields(
+        &self,
+        current: &'w mut FormattedFields<Self>,
+        fields: &tracing::span::Record<'_>,
+    ) -> fmt::Result {
+        let mut object: Map<String, Value> =
+            serde_json::from_str(&current.fields).unwrap_or_default();
+        fields.record(&mut JsonVisitor(&mut object));
+        current.fields = Value::Object(object).to_string();
+        Ok(())
+    }
+}
+
+struct JsonVisitor<'a>(&'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.into());
+    }
+
+    fn record_error(&mut self, field: &Field, value: &(dyn std::error::Error + 'static)) {
+        self.0
+            .insert(field.name().to_string(), value.to_string().into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_string(), format!("{:?}", value).into());
+    }
+}
+
+/// A log file rotated once it would grow past `max_size` bytes.
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: impl AsRef<Path>, max_size: u64, max_files: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self {
+            path,
+            max_size,
+            max_files,
+            file,
+            size,
+        })
+    }
+
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        PathBuf::from(path)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+
+        if self.max_files > 0 {
+            // The oldest file falls off the end
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(&from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // A line longer than max_size still goes into a file of its own
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tracing::info_span;
+
+    fn temp_dir() -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("androidoscopy-logging-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = temp_dir();
+        let path = dir.join("server.log");
+        let mut file = RotatingFile::open(&path, 10, 2).unwrap();
+
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        file.flush().unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(
+            fs::read_to_string(dir.join("server.log.1")).unwrap(),
+            "third\n"
+        );
+        assert_eq!(
+            fs::read_to_string(dir.join("server.log.2")).unwrap(),
+            "second\n"
+        );
+        assert!(!dir.join("server.log.3").exists());
+
+        // Appends to what is already there
+        drop(file);
+        let mut file = RotatingFile::open(&path, 100, 2).unwrap();
+        file.write_all(b"fifth\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\nfifth\n");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_format_includes_span_fields() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::registry().with(fmt_layer(
+            LogFormat::Json,
+            move || writer.clone(),
+            false,
+        ));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = info_span!(
+                "app",
+                session_id = tracing::field::Empty,
+                device_id = "device-1"
+            );
+            let _entered = span.enter();
+            span.record("session_id", "session-1");
+            tracing::warn!(blob_id = 7, "Upload failed");
+        });
+
+        let output = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let line: Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(line["level"], "WARN");
+        assert_eq!(line["message"], "Upload failed");
+        assert_eq!(line["blob_id"], 7);
+        assert_eq!(line["span"], "app");
+        assert_eq!(line["session_id"], "session-1");
+        assert_eq!(line["device_id"], "device-1");
+        assert!(line["timestamp"].is_string());
+    }
+
+    #[test]
+    fn test_invalid_level() {
+        let config = LoggingConfig {
+            level: "loud".to_string(),
+            ..LoggingConfig::default()
+        };
+        // RUST_LOG would take precedence over the level
+        if std::env::var_os("RUST_LOG").is_none() {
+            assert!(filter(&config).unwrap_err().contains("loud"));
+        }
+    }
+}
@@ -1,23 +1,31 @@
 use axum::{routing::get, Router};
 use axum_server::tls_rustls::RustlsConfig;
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
 use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpListener;
-use tracing::{info, warn};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
-mod config;
-mod dashboard;
-mod discovery;
-mod handlers;
-mod protocol;
-mod service;
-mod session;
-mod state;
-mod tls;
-
-use config::Config;
-use state::AppState;
+#[cfg(unix)]
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::{mpsc, watch, Notify};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use androidoscopy_server::ca::{self, LocalCa, DEVICE_CA_NAME};
+use androidoscopy_server::client::{self, Client, Credentials};
+use androidoscopy_server::config::{self, Config, ConfigOverride};
+use androidoscopy_server::discovery::Announcement;
+use androidoscopy_server::logcat::{self, LogFilter};
+use androidoscopy_server::onboarding::Onboarding;
+use androidoscopy_server::protocol::{LogLevel, ServiceToDashboardMessage};
+use androidoscopy_server::reload::ReloadRequest;
+use androidoscopy_server::state::AppState;
+use androidoscopy_server::{
+    auth, control, dashboard_auth, discovery, handlers, logging, reload, service, stats, tls,
+};
 
 #[derive(Parser)]
 #[command(name = "androidoscopy")]
@@ -25,6 +33,105 @@ use state::AppState;
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+    #[command(flatten)]
+    config: ConfigArgs,
+}
+
+/// Where the config comes from. Later sources win: the config file, then
+/// `ANDROIDOSCOPY_<TABLE>__<KEY>` environment variables, then `--set`, then
+/// the dedicated flags.
+#[derive(Args, Clone)]
+struct ConfigArgs {
+    /// Config file to use instead of ~/.androidoscopy/config.toml
+    #[arg(long, global = true, env = "ANDROIDOSCOPY_CONFIG", value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Set any config key, e.g. --set server.tls.enabled=false (repeatable)
+    #[arg(long = "set", global = true, value_name = "KEY=VALUE")]
+    overrides: Vec<String>,
+    /// Port of the dashboard (server.http_port)
+    #[arg(long, global = true, value_name = "PORT")]
+    http_port: Option<u16>,
+    /// Port apps connect to (server.websocket_port)
+    #[arg(long, global = true, value_name = "PORT")]
+    websocket_port: Option<u16>,
+    /// Address to listen on (server.bind_address)
+    #[arg(long, global = true, value_name = "ADDRESS")]
+    bind_address: Option<String>,
+    /// Serve apps over plain WebSocket (server.tls.enabled = false)
+    #[arg(long, global = true)]
+    no_tls: bool,
+    /// Disable UDP broadcast and mDNS discovery
+    #[arg(long, global = true)]
+    no_discovery: bool,
+    /// Log level (logging.level)
+    #[arg(long, global = true, value_name = "LEVEL")]
+    log_level: Option<String>,
+}
+
+impl ConfigArgs {
+    fn overrides(&self) -> Result<Vec<ConfigOverride>, String> {
+        let mut overrides = ConfigOverride::from_env(std::env::vars());
+
+        for assignment in &self.overrides {
+            overrides.push(ConfigOverride::parse(assignment, "--set")?);
+        }
+
+        let mut flag = |key: &str, value: String, origin: &str| {
+            overrides.push(ConfigOverride::new(key, value, origin))
+        };
+        if let Some(port) = self.http_port {
+            flag("server.http_port", port.to_string(), "--http-port");
+        }
+        if let Some(port) = self.websocket_port {
+            flag(
+                "server.websocket_port",
+                port.to_string(),
+                "--websocket-port",
+            );
+        }
+        if let Some(address) = &self.bind_address {
+            flag("server.bind_address", address.clone(), "--bind-address");
+        }
+        if self.no_tls {
+            flag("server.tls.enabled", "false".to_string(), "--no-tls");
+        }
+        if self.no_discovery {
+            flag(
+                "server.udp_discovery_enabled",
+                "false".to_string(),
+                "--no-discovery",
+            );
+            flag("server.mdns_enabled", "false".to_string(), "--no-discovery");
+        }
+        if let Some(level) = &self.log_level {
+            flag("logging.level", level.clone(), "--log-level");
+        }
+
+        Ok(overrides)
+    }
+
+    /// The config file in use, which may not exist.
+    fn path(&self) -> PathBuf {
+        self.config.clone().unwrap_or_else(Config::config_path)
+    }
+
+    /// Loads the config with all overrides applied.
+    fn try_load(&self) -> Result<Config, String> {
+        let overrides = self.overrides()?;
+        Config::load_with(self.config.as_deref(), &overrides).map_err(|e| e.to_string())
+    }
+
+    /// Loads the config, exiting on any error since running with anything
+    /// but what was asked for would be confusing.
+    fn load(&self) -> Config {
+        match self.try_load() {
+            Ok(config) => config,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -35,8 +142,168 @@ enum Commands {
     Install,
     /// Uninstall the systemd user service
     Uninstall,
-    /// Show service status
-    Status,
+    /// Show the running server's health and statistics, asked over the control socket
+    Status {
+        /// Print the status as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Stop, reload or inspect the running server over the control socket
+    Control {
+        #[command(subcommand)]
+        command: ControlCommand,
+    },
+    /// Show the pairing token apps need when `[server.auth]` is enabled
+    Token {
+        /// Replace the stored token; apps paired with the old one must be updated
+        #[arg(long)]
+        regenerate: bool,
+    },
+    /// Hash a password for `[[dashboard.auth.users]]`, read from the terminal or stdin
+    HashPassword,
+    /// Issue a client certificate for a device, exported as PKCS#12
+    Enroll {
+        /// Device name, used as the certificate's common name
+        device: String,
+        /// Output file (default: <device>.p12)
+        #[arg(long)]
+        out: Option<PathBuf>,
+        /// Password protecting the file (default: randomly generated)
+        #[arg(long)]
+        password: Option<String>,
+    },
+    /// Print a QR code devices can scan to connect to this server
+    Qr,
+    /// Create, check or print the config
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// List the sessions of the running server or show one's latest data
+    Sessions {
+        #[command(subcommand)]
+        command: Option<SessionsCommand>,
+        #[command(flatten)]
+        login: LoginArgs,
+    },
+    /// Print the logs of the running server's sessions, logcat style
+    Logs {
+        /// Session id, a prefix of it, or a package name; repeatable (default: all sessions)
+        #[arg(long, short)]
+        session: Vec<String>,
+        /// Keep printing new logs, also from sessions started later
+        #[arg(long, short)]
+        follow: bool,
+        /// Lowest level to print: verbose, debug, info, warn or error (or V, D, I, W, E)
+        #[arg(long, short, value_parser = logcat::parse_level)]
+        level: Option<LogLevel>,
+        /// Only print logs with a tag containing this; repeatable
+        #[arg(long, short)]
+        tag: Vec<String>,
+        #[command(flatten)]
+        login: LoginArgs,
+    },
+    /// Send an action to an app and print the data it returns as JSON
+    Action {
+        /// Action name, e.g. clear_cache or sqlite_query
+        action: String,
+        /// Session id, a prefix of it, or a package name (default: the only active session)
+        #[arg(long, short)]
+        session: Option<String>,
+        /// Device id or model of the session
+        #[arg(long, short)]
+        device: Option<String>,
+        /// Action arguments as a JSON object, e.g. '{"database":"app.db"}'
+        #[arg(long, short, value_parser = parse_action_args)]
+        args: Option<serde_json::Value>,
+        /// Seconds to wait for the app's result
+        #[arg(long, default_value_t = 30)]
+        timeout: u64,
+        #[command(flatten)]
+        login: LoginArgs,
+    },
+}
+
+fn parse_action_args(json: &str) -> Result<serde_json::Value, String> {
+    match serde_json::from_str(json) {
+        Ok(serde_json::Value::Object(args)) => Ok(serde_json::Value::Object(args)),
+        Ok(_) => Err("expected a JSON object".to_string()),
+        Err(e) => Err(format!("invalid JSON: {}", e)),
+    }
+}
+
+/// Login for commands talking to the running server, needed when
+/// `[dashboard.auth]` is enabled.
+#[derive(Args)]
+struct LoginArgs {
+    /// Dashboard user to log in as
+    #[arg(long, global = true, env = "ANDROIDOSCOPY_USER")]
+    user: Option<String>,
+    /// Password of --user
+    #[arg(
+        long,
+        global = true,
+        env = "ANDROIDOSCOPY_PASSWORD",
+        hide_env_values = true
+    )]
+    password: Option<String>,
+}
+
+impl LoginArgs {
+    fn client(&self, config: &Config) -> Client {
+        let credentials = self.user.clone().map(|user| Credentials {
+            user,
+            password: self.password.clone().unwrap_or_default(),
+        });
+        Client::local(config, credentials).unwrap_or_else(|e| exit_with_error(e))
+    }
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Write a config file with every setting at its default, explained
+    Init {
+        /// Replace an existing file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Check the config file and overrides, e.g. for clashing ports
+    Check,
+    /// Print the config in effect, after defaults and overrides
+    Show,
+}
+
+#[derive(Subcommand)]
+enum ControlCommand {
+    /// Stop the server
+    Shutdown,
+    /// Reload the config file, like SIGHUP, and print what changed
+    Reload,
+    /// Print the running config and sessions as JSON
+    Dump,
+}
+
+#[derive(Subcommand)]
+enum SessionsCommand {
+    /// List active and recently ended sessions (default)
+    List {
+        #[arg(long, short, value_enum, default_value_t = OutputFormat::Table)]
+        output: OutputFormat,
+    },
+    /// Print the latest data of a session as JSON
+    Show {
+        /// Session id, a prefix of it, or the package name of the app
+        session: String,
+        /// JSONPath selecting part of the data, e.g. '$.memory.heap_used'
+        #[arg(long)]
+        path: Option<String>,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Table,
+    Json,
 }
 
 #[tokio::main]
@@ -44,7 +311,7 @@ async fn main() {
     let cli = Cli::parse();
 
     match cli.command.unwrap_or(Commands::Run) {
-        Commands::Run => run_server().await,
+        Commands::Run => run_server(cli.config).await,
         Commands::Install => {
             if let Err(e) = service::install() {
                 eprintln!("Installation failed: {}", e);
@@ -57,35 +324,594 @@ async fn main() {
                 std::process::exit(1);
             }
         }
-        Commands::Status => {
-            if let Err(e) = service::status() {
-                eprintln!("Failed to get status: {}", e);
-                std::process::exit(1);
+        Commands::Status { json } => show_status(&cli.config.load(), json).await,
+        Commands::Control { command } => {
+            let client = control_client(&cli.config.load());
+            match command {
+                ControlCommand::Shutdown => shutdown_server(&client).await,
+                ControlCommand::Reload => reload_server(&client).await,
+                ControlCommand::Dump => dump_state(&client).await,
             }
         }
+        Commands::Token { regenerate } => show_token(cli.config.load(), regenerate),
+        Commands::HashPassword => hash_password(),
+        Commands::Enroll {
+            device,
+            out,
+            password,
+        } => enroll_device(cli.config.load(), &device, out, password),
+        Commands::Qr => show_qr(cli.config.load()),
+        Commands::Config { command } => match command {
+            ConfigCommand::Init { force } => init_config(&cli.config.path(), force),
+            ConfigCommand::Check => check_config(&cli.config),
+            ConfigCommand::Show => show_config(cli.config.load()),
+        },
+        Commands::Sessions { command, login } => {
+            let client = login.client(&cli.config.load());
+            match command.unwrap_or(SessionsCommand::List {
+                output: OutputFormat::Table,
+            }) {
+                SessionsCommand::List { output } => list_sessions(&client, output).await,
+                SessionsCommand::Show { session, path } => {
+                    show_session(&client, &session, path.as_deref()).await
+                }
+            }
+        }
+        Commands::Logs {
+            session,
+            follow,
+            level,
+            tag,
+            login,
+        } => {
+            let client = login.client(&cli.config.load());
+            let filter = LogFilter {
+                min_level: level,
+                tags: tag,
+            };
+            if follow {
+                follow_logs(&client, &session, &filter).await;
+            } else {
+                print_logs(&client, &session, &filter).await;
+            }
+        }
+        Commands::Action {
+            action,
+            session,
+            device,
+            args,
+            timeout,
+            login,
+        } => {
+            let client = login.client(&cli.config.load());
+            run_action(
+                &client,
+                &action,
+                session.as_deref(),
+                device.as_deref(),
+                args,
+                Duration::from_secs(timeout),
+            )
+            .await;
+        }
     }
 }
 
-async fn run_server() {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "androidoscopy_server=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+/// A client for the control socket of the server running with `config`.
+fn control_client(config: &Config) -> Client {
+    if config.server.control_socket.is_empty() {
+        exit_with_error("the control socket is disabled (server.control_socket)");
+    }
+    #[cfg(unix)]
+    return Client::control(&config.server.control_socket);
+    #[cfg(not(unix))]
+    exit_with_error("the control socket needs Unix domain sockets");
+}
+
+async fn show_status(config: &Config, json: bool) {
+    let client = control_client(config);
+    let status: control::ServerStatus = match client.get("/control/status").await {
+        Ok(status) => status,
+        Err(client::ClientError::Connect { .. }) => {
+            println!(
+                "androidoscopy is not running (no server on {})",
+                config.server.control_socket
+            );
+            std::process::exit(3);
+        }
+        Err(e) => exit_with_error(e),
+    };
+
+    if json {
+        print_json(&status);
+    } else {
+        print!("{}", control::status_report(&status, chrono::Utc::now()));
+    }
+}
+
+async fn shutdown_server(client: &Client) {
+    let _: bool = client
+        .post("/control/shutdown")
+        .await
+        .unwrap_or_else(|e| exit_with_error(e));
+    eprintln!("Server stopping");
+}
+
+async fn reload_server(client: &Client) {
+    let changes: reload::ConfigChanges = client
+        .post("/control/reload")
+        .await
+        .unwrap_or_else(|e| exit_with_error(e));
+
+    if changes.is_empty() {
+        println!("Config unchanged");
+    }
+    if !changes.applied.is_empty() {
+        println!("Applied: {}", changes.applied.join(", "));
+    }
+    if !changes.restart_required.is_empty() {
+        println!("Need a restart: {}", changes.restart_required.join(", "));
+    }
+}
+
+async fn dump_state(client: &Client) {
+    let state: control::ServerState = client
+        .get("/control/state")
+        .await
+        .unwrap_or_else(|e| exit_with_error(e));
+    print_json(&state);
+}
+
+/// Prints `error` and exits, for the commands talking to the running server.
+fn exit_with_error(error: impl std::fmt::Display) -> ! {
+    eprintln!("Error: {}", error);
+    std::process::exit(1);
+}
+
+async fn list_sessions(client: &Client, output: OutputFormat) {
+    let sessions = client
+        .sessions()
+        .await
+        .unwrap_or_else(|e| exit_with_error(e));
+
+    match output {
+        OutputFormat::Table if sessions.is_empty() => eprintln!("No sessions"),
+        OutputFormat::Table => print!("{}", client::sessions_table(&sessions)),
+        OutputFormat::Json => print_json(&sessions),
+    }
+}
+
+async fn show_session(client: &Client, query: &str, path: Option<&str>) {
+    let session = client
+        .find_session(query)
+        .await
+        .unwrap_or_else(|e| exit_with_error(e));
+    let session = client
+        .session(&session.session_id)
+        .await
+        .unwrap_or_else(|e| exit_with_error(e));
+
+    let data = session.latest_data.unwrap_or(serde_json::Value::Null);
+    match path {
+        Some(path) => {
+            print_json(&client::select(&data, path).unwrap_or_else(|e| exit_with_error(e)))
+        }
+        None => print_json(&data),
+    }
+}
+
+/// Whether `selectors` (from `logs --session`) pick the session; none picks
+/// every session.
+fn is_selected_session(session_id: &str, package_name: &str, selectors: &[String]) -> bool {
+    selectors.is_empty()
+        || selectors
+            .iter()
+            .any(|selector| client::session_matches(session_id, package_name, selector))
+}
+
+fn use_colors() -> bool {
+    std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+}
+
+/// Prints the logs the server still holds for the selected sessions,
+/// interleaved by time.
+async fn print_logs(client: &Client, selectors: &[String], filter: &LogFilter) {
+    let sessions = client
+        .sessions()
+        .await
+        .unwrap_or_else(|e| exit_with_error(e));
+    for selector in selectors {
+        if !sessions.iter().any(|session| {
+            client::session_matches(&session.session_id, &session.package_name, selector)
+        }) {
+            exit_with_error(format!("no session matches `{}`", selector));
+        }
+    }
+
+    let mut logs = Vec::new();
+    let mut selected = 0;
+    for summary in sessions.iter().filter(|session| {
+        is_selected_session(&session.session_id, &session.package_name, selectors)
+    }) {
+        let session = match client.session(&summary.session_id).await {
+            Ok(session) => session,
+            // Expired in the meantime
+            Err(client::ClientError::Status { status, .. }) if status == 404 => continue,
+            Err(e) => exit_with_error(e),
+        };
+        selected += 1;
+        let device = session.device.model;
+        logs.extend(
+            session
+                .recent_logs
+                .into_iter()
+                .filter(|entry| filter.matches(entry))
+                .map(|entry| (device.clone(), entry)),
+        );
+    }
+    logs.sort_by_key(|(_, entry)| entry.timestamp);
+
+    let colored = use_colors();
+    let mut stdout = std::io::stdout().lock();
+    for (device, entry) in logs {
+        let prefix = (selected > 1).then_some(device.as_str());
+        if stdout
+            .write_all(logcat::format_entry(&entry, prefix, colored).as_bytes())
+            .is_err()
+        {
+            // e.g. piped into `head`
+            return;
+        }
+    }
+}
+
+/// Prints the logs of the selected sessions as they arrive, starting with
+/// those the server still holds, until the server goes away.
+async fn follow_logs(client: &Client, selectors: &[String], filter: &LogFilter) {
+    let mut connection = client
+        .dashboard()
+        .await
+        .unwrap_or_else(|e| exit_with_error(e));
+    let colored = use_colors();
+    // Session id to device model, of the sessions followed
+    let mut devices: HashMap<String, String> = HashMap::new();
+
+    while let Some(message) = connection.next().await {
+        let mut logs = Vec::new();
+        match message.unwrap_or_else(|e| exit_with_error(e)) {
+            ServiceToDashboardMessage::Sync { payload } => {
+                for session in payload.sessions {
+                    if is_selected_session(&session.session_id, &session.package_name, selectors) {
+                        devices.insert(session.session_id.clone(), session.device.model.clone());
+                        logs.extend(
+                            session
+                                .recent_logs
+                                .into_iter()
+                                .map(|entry| (session.session_id.clone(), entry)),
+                        );
+                    }
+                }
+                logs.sort_by_key(|(_, entry)| entry.timestamp);
+            }
+            ServiceToDashboardMessage::SessionStarted { payload }
+            | ServiceToDashboardMessage::SessionResumed { payload } => {
+                let session = payload.session;
+                if is_selected_session(&session.session_id, &session.package_name, selectors) {
+                    devices.insert(session.session_id, session.device.model);
+                }
+            }
+            ServiceToDashboardMessage::SessionLog { payload, .. } => {
+                logs.push((payload.session_id, payload.log));
+            }
+            _ => {}
+        }
+
+        let mut stdout = std::io::stdout().lock();
+        for (session_id, entry) in logs {
+            let Some(device) = devices.get(&session_id) else {
+                continue;
+            };
+            if !filter.matches(&entry) {
+                continue;
+            }
+            let prefix = (devices.len() > 1).then_some(device.as_str());
+            let written =
+                stdout.write_all(logcat::format_entry(&entry, prefix, colored).as_bytes());
+            if written.and_then(|_| stdout.flush()).is_err() {
+                return;
+            }
+        }
+    }
+
+    exit_with_error("the server closed the connection");
+}
+
+/// Runs an action on the selected active session, printing the data it
+/// returns and exiting with an error when it fails.
+async fn run_action(
+    client: &Client,
+    action: &str,
+    session: Option<&str>,
+    device: Option<&str>,
+    args: Option<serde_json::Value>,
+    timeout: Duration,
+) {
+    let mut connection = client
+        .dashboard()
+        .await
+        .unwrap_or_else(|e| exit_with_error(e));
+    // The server starts with a SYNC of the active sessions
+    let sessions = match connection.next().await {
+        Some(Ok(ServiceToDashboardMessage::Sync { payload })) => payload.sessions,
+        Some(Err(e)) => exit_with_error(e),
+        _ => exit_with_error("the server did not send its sessions"),
+    };
+    let session = client::select_active_session(&sessions, session, device)
+        .unwrap_or_else(|e| exit_with_error(e));
+
+    let result = connection
+        .run_action(&session.session_id, action, args, timeout)
+        .await
+        .unwrap_or_else(|e| exit_with_error(e));
+    if let Some(data) = &result.data {
+        print_json(data);
+    }
+    if !result.success {
+        exit_with_error(result.message.as_deref().unwrap_or("the action failed"));
+    }
+    if let Some(message) = &result.message {
+        eprintln!("{}", message);
+    }
+}
+
+fn print_json(value: &impl serde::Serialize) {
+    match serde_json::to_string_pretty(value) {
+        Ok(json) => println!("{}", json),
+        Err(e) => exit_with_error(e),
+    }
+}
+
+fn init_config(path: &Path, force: bool) {
+    if path.exists() && !force {
+        eprintln!(
+            "{} already exists; use --force to replace it",
+            path.display()
+        );
+        std::process::exit(1);
+    }
+
+    let result = (|| -> std::io::Result<()> {
+        if let Some(parent) = path
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, config::DEFAULT_CONFIG)?;
+
+        // Dashboard passwords and the pairing token may end up in here
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+        }
+        Ok(())
+    })();
+
+    match result {
+        Ok(()) => println!("Wrote {}", path.display()),
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", path.display(), e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn check_config(args: &ConfigArgs) {
+    let path = args.path();
+    match args.try_load() {
+        Ok(_) if path.exists() => println!("{}: OK", path.display()),
+        Ok(_) => println!("No config file at {}; the defaults are OK", path.display()),
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn show_config(config: Config) {
+    match toml::to_string_pretty(&config) {
+        Ok(toml) => print!("{}", toml),
+        Err(e) => {
+            eprintln!("Failed to print the config: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn enroll_device(config: Config, device: &str, out: Option<PathBuf>, password: Option<String>) {
+    let client_auth = &config.server.tls.client_auth;
+
+    let result = LocalCa::load_or_create(
+        &client_auth.ca_cert_path,
+        &client_auth.ca_key_path,
+        DEVICE_CA_NAME,
+    )
+    .and_then(|ca| {
+        let issued = ca.issue_client_certificate(device, client_auth.cert_validity_days)?;
+        let password =
+            password.unwrap_or_else(|| Uuid::new_v4().simple().to_string()[..16].to_string());
+        let bytes = ca.export_pkcs12(&issued, device, &password)?;
+
+        let out = out.unwrap_or_else(|| PathBuf::from(ca::pkcs12_file_name(device)));
+        ca::write_file(&out, bytes, true)?;
+        Ok((out, password))
+    });
+
+    match result {
+        Ok((out, password)) => {
+            println!(
+                "Client certificate for {} written to {}",
+                device,
+                out.display()
+            );
+            println!("Password: {}", password);
+            println!("Valid for {} days", client_auth.cert_validity_days);
+            if !config.server.tls.client_auth.enabled {
+                eprintln!(
+                    "Note: client certificates are not required; enable [server.tls.client_auth]"
+                );
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to enroll {}: {}", device, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn show_qr(mut config: Config) {
+    let result = (|| -> Result<Onboarding, Box<dyn std::error::Error>> {
+        resolve_token(&mut config)?;
+        if config.server.tls.enabled {
+            // Creates the CA on a fresh install, so the pin matches what the server will use
+            tls::ensure_certificates(&config.server.tls)?;
+        }
+        Onboarding::from_config(&config)
+    })();
+
+    let onboarding = match result {
+        Ok(onboarding) => onboarding,
+        Err(e) => {
+            eprintln!("Failed to prepare onboarding details: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match onboarding.qr_terminal() {
+        Ok(qr) => {
+            println!("{}", qr);
+            println!("{}", onboarding.uri());
+            if config.server.tls.enabled {
+                let scheme = if config.dashboard.tls.enabled {
+                    "https"
+                } else {
+                    "http"
+                };
+                println!(
+                    "Certificate: {}://{}:{}/api/certificate.pem",
+                    scheme, onboarding.host, config.server.http_port
+                );
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to render QR code: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn show_token(config: Config, regenerate: bool) {
+    let auth = &config.server.auth;
+
+    if regenerate && auth.token.is_some() {
+        eprintln!("The token is set in the config file; change it there instead");
+        std::process::exit(1);
+    }
+
+    let result = if regenerate {
+        auth::regenerate_token(auth)
+    } else {
+        auth::load_or_create_token(auth)
+    };
+
+    match result {
+        Ok(token) => {
+            println!("{}", token);
+            if !auth.enabled {
+                eprintln!("Note: app authentication is disabled; enable it with [server.auth] enabled = true");
+            } else if regenerate {
+                eprintln!("Note: a running server accepts the old token until it is restarted");
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to read pairing token: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn hash_password() {
+    let password = if std::io::stdin().is_terminal() {
+        rpassword::prompt_password("Password: ")
+    } else {
+        let mut line = String::new();
+        std::io::stdin()
+            .read_line(&mut line)
+            .map(|_| line.trim_end_matches(['\r', '\n']).to_string())
+    };
+    let password = match password {
+        Ok(password) if !password.is_empty() => password,
+        Ok(_) => exit_with_error("The password is empty"),
+        Err(e) => exit_with_error(format!("Failed to read the password: {}", e)),
+    };
+
+    match dashboard_auth::hash_password(&password) {
+        Ok(hash) => println!("{}", hash),
+        Err(e) => exit_with_error(format!("Failed to hash the password: {}", e)),
+    }
+}
+
+/// Puts the pairing token into the config when app authentication is enabled.
+fn resolve_token(config: &mut Config) -> Result<(), Box<dyn std::error::Error>> {
+    if config.server.auth.enabled {
+        config.server.auth.token = Some(auth::load_or_create_token(&config.server.auth)?);
+    }
+    Ok(())
+}
+
+async fn run_server(args: ConfigArgs) {
+    let mut config = args.load();
+
+    let filter_handle = match logging::init(&config.logging) {
+        Ok(handle) => handle,
+        Err(e) => {
+            eprintln!("Error: failed to set up logging: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match resolve_token(&mut config) {
+        Ok(()) if config.server.auth.enabled => {
+            info!("App pairing token required; run `androidoscopy token` to show it");
+        }
+        Ok(()) => {}
+        Err(e) => {
+            // Without a token no app could connect, so don't pretend to be running
+            error!("Failed to load pairing token: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    if config.dashboard.auth.enabled && config.dashboard.auth.users.is_empty() {
+        warn!("Dashboard login is enabled but no users are configured; nobody can log in");
+    }
+    for user in &config.dashboard.auth.users {
+        if !dashboard_auth::is_password_hash(&user.password) {
+            warn!(
+                "Dashboard user {} has a plain text password; replace it with the output of `androidoscopy hash-password`",
+                user.name
+            );
+        }
+    }
 
-    let config = Config::load().unwrap_or_default();
     let state = AppState::new(config.clone());
 
-    // Start UDP discovery broadcast if enabled
-    if config.server.udp_discovery_enabled {
-        let websocket_port = config.server.websocket_port;
-        let http_port = config.server.http_port;
-        tokio::spawn(async move {
-            discovery::broadcast_presence(websocket_port, http_port).await;
-        });
+    // Sessions don't survive a restart, so neither do their attachments
+    if let Err(e) = state.attachment_store.purge() {
+        warn!("Failed to remove attachments from a previous run: {}", e);
     }
+    spawn_session_cleanup(state.clone());
 
     let bind_addr: std::net::IpAddr = config
         .server
@@ -93,63 +919,389 @@ async fn run_server() {
         .parse()
         .unwrap_or([127, 0, 0, 1].into());
 
-    // Start HTTP server for dashboard with embedded assets
     let http_addr = SocketAddr::from((bind_addr, config.server.http_port));
-    let http_state = state.clone();
-
-    tokio::spawn(async move {
-        let http_app = Router::new()
-            .route("/ws/dashboard", get(handlers::handle_dashboard_ws))
-            .fallback(dashboard::serve_embedded)
-            .with_state(http_state);
-
-        let listener = TcpListener::bind(http_addr).await.unwrap();
-        info!("Dashboard: http://{}", http_addr);
-        axum::serve(listener, http_app).await.unwrap();
-    });
-
-    // Start WSS server for Android app connections (TLS, no cleartext needed)
     let wss_addr = SocketAddr::from((bind_addr, config.server.websocket_port));
 
-    if config.server.tls.enabled {
-        let (cert_path, key_path) = match tls::ensure_certificates(&config.server.tls) {
-            Ok(paths) => paths,
+    let certificates = if config.server.tls.enabled {
+        match tls::ensure_certificates(&config.server.tls) {
+            Ok(paths) => Some(paths),
+            Err(e) if config.server.tls.client_auth.enabled || config.dashboard.tls.enabled => {
+                // Falling back to WS would let any device in, or expose the dashboard in cleartext
+                error!("Failed to setup TLS certificates: {}", e);
+                std::process::exit(1);
+            }
             Err(e) => {
                 warn!(
                     "Failed to setup TLS certificates: {}. Falling back to WS.",
                     e
                 );
-                start_ws_server(wss_addr, state).await;
+                None
+            }
+        }
+    } else {
+        warn!("TLS is disabled - Android apps will need cleartext permission");
+        None
+    };
+
+    let distributed_der = certificates.as_ref().and_then(|_| {
+        tls::get_certificate_der(tls::distributed_certificate_path(&config.server.tls)).ok()
+    });
+    let tls_pin = distributed_der
+        .as_deref()
+        .and_then(|der| tls::certificate_pin(der).ok());
+    if let Some(pin) = &tls_pin {
+        info!("Certificate pin: {}", pin);
+    }
+    let announcement = Announcement {
+        tls: certificates.is_some(),
+        tls_pin,
+        certificate_fingerprint: distributed_der.as_deref().map(tls::certificate_fingerprint),
+    };
+    tokio::spawn(discovery::supervise(state.config_updates(), announcement));
+
+    spawn_log_filter_updates(state.config_updates(), filter_handle);
+    let controls = control::Controls {
+        reload: spawn_config_reload(state.clone(), args),
+        shutdown: Arc::new(Notify::new()),
+    };
+    let control_socket = spawn_control_socket(&config, state.clone(), controls.clone());
+
+    tokio::select! {
+        _ = serve(&config, state, http_addr, wss_addr, certificates) => {}
+        _ = controls.shutdown.notified() => {}
+        _ = terminated() => {}
+    }
+    if let Some(path) = control_socket {
+        control::remove(&path);
+    }
+    info!("Server stopped");
+}
+
+/// Serves apps and the dashboard.
+async fn serve(
+    config: &Config,
+    state: AppState,
+    http_addr: SocketAddr,
+    wss_addr: SocketAddr,
+    certificates: Option<(String, String)>,
+) {
+    let Some((cert_path, key_path)) = certificates else {
+        spawn_dashboard(http_addr, state.clone(), None, None);
+        start_ws_server(wss_addr, state).await;
+        return;
+    };
+
+    // Start WSS server for Android app connections (TLS, no cleartext needed)
+    let tls_config = match tls::rustls_config(&config.server.tls, &cert_path, &key_path) {
+        Ok(tls_config) => tls_config,
+        Err(e) => {
+            error!("Failed to load TLS configuration: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Browsers can't present a device certificate, so with mTLS the dashboard needs its own config
+    let separate_dashboard_tls =
+        if config.dashboard.tls.enabled && config.server.tls.client_auth.enabled {
+            match tls::dashboard_rustls_config(&config.server.tls, &cert_path, &key_path) {
+                Ok(dashboard_tls) => Some(dashboard_tls),
+                Err(e) => {
+                    error!("Failed to load dashboard TLS configuration: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        } else {
+            None
+        };
+    let dashboard_tls = if config.dashboard.tls.enabled {
+        Some(
+            separate_dashboard_tls
+                .clone()
+                .unwrap_or_else(|| tls_config.clone()),
+        )
+    } else {
+        None
+    };
+    spawn_dashboard(
+        http_addr,
+        state.clone(),
+        dashboard_tls,
+        config.dashboard.tls.redirect_port,
+    );
+
+    info!("Run `androidoscopy qr` to onboard a device, or open the dashboard");
+    tokio::spawn(tls::watch_certificates(
+        state.config_updates(),
+        tls_config.clone(),
+        separate_dashboard_tls,
+    ));
+
+    // Bound up front, so the listener is only reported once apps can connect
+    let listener = match std::net::TcpListener::bind(wss_addr) {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Failed to listen on {}: {}", wss_addr, e);
+            std::process::exit(1);
+        }
+    };
+    state.stats.add_listener(
+        stats::APP_LISTENER,
+        format!("wss://{}/ws/app", wss_addr),
+        true,
+    );
+    let wss_app = Router::new()
+        .route("/ws/app", get(handlers::handle_app_ws))
+        .with_state(state);
+
+    info!("Android app: wss://{}/ws/app", wss_addr);
+
+    axum_server::from_tcp_rustls(listener, tls_config)
+        .serve(wss_app.into_make_service())
+        .await
+        .unwrap();
+}
+
+/// Resolves once the process is asked to stop with Ctrl-C or SIGTERM.
+async fn terminated() {
+    #[cfg(unix)]
+    {
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
+            Err(e) => {
+                warn!("Failed to listen for SIGTERM: {}", e);
+                let _ = tokio::signal::ctrl_c().await;
                 return;
             }
         };
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
 
-        let tls_config = RustlsConfig::from_pem_file(&cert_path, &key_path)
-            .await
-            .expect("Failed to load TLS configuration");
+/// Starts the control socket, returning its path to remove on shutdown.
+fn spawn_control_socket(
+    config: &Config,
+    state: AppState,
+    controls: control::Controls,
+) -> Option<PathBuf> {
+    if config.server.control_socket.is_empty() {
+        return None;
+    }
+    let path = PathBuf::from(&config.server.control_socket);
+
+    #[cfg(unix)]
+    match control::spawn(&path, state, controls) {
+        Ok(()) => Some(path),
+        Err(e) => {
+            // The server works without it, only the command line falls back to the dashboard port
+            warn!(
+                "Failed to start the control socket at {}: {}",
+                path.display(),
+                e
+            );
+            None
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (state, controls);
+        warn!(
+            "The control socket needs Unix domain sockets; not starting it at {}",
+            path.display()
+        );
+        None
+    }
+}
+
+/// How often expired sessions are dropped.
+const SESSION_CLEANUP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Drops sessions that ended longer than `ended_session_ttl_seconds` ago,
+/// together with their attachments.
+fn spawn_session_cleanup(state: AppState) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SESSION_CLEANUP_INTERVAL);
+        loop {
+            interval.tick().await;
+            let removed = state.session_manager.lock().await.cleanup_ended_sessions();
+            for session_id in removed {
+                if let Err(e) = state.attachment_store.remove_session(&session_id).await {
+                    warn!(
+                        "Failed to remove attachments of session {}: {}",
+                        session_id, e
+                    );
+                }
+            }
+        }
+    });
+}
+
+/// How often the config file is checked for changes.
+const CONFIG_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Reloads the config when its file changes, on SIGHUP and on the requests
+/// sent to the returned channel, see [`androidoscopy_server::reload`].
+fn spawn_config_reload(state: AppState, args: ConfigArgs) -> mpsc::Sender<ReloadRequest> {
+    let (reload_tx, mut reload_rx) = mpsc::channel::<ReloadRequest>(1);
+
+    let path = args.config.clone().unwrap_or_else(Config::config_path);
+    let file_tx = reload_tx.clone();
+    tokio::spawn(async move {
+        let modified = |path: &PathBuf| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        let mut last_modified = modified(&path);
+        loop {
+            tokio::time::sleep(CONFIG_POLL_INTERVAL).await;
+            let current = modified(&path);
+            if current != last_modified {
+                last_modified = current;
+                let _ = file_tx
+                    .send(ReloadRequest::new(format!("{} changed", path.display())))
+                    .await;
+            }
+        }
+    });
+
+    #[cfg(unix)]
+    let signal_tx = reload_tx.clone();
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(hangup) => hangup,
+            Err(e) => {
+                warn!("Failed to listen for SIGHUP: {}", e);
+                return;
+            }
+        };
+        while hangup.recv().await.is_some() {
+            let _ = signal_tx.send(ReloadRequest::new("received SIGHUP")).await;
+        }
+    });
 
-        let wss_app = Router::new()
-            .route("/ws/app", get(handlers::handle_app_ws))
-            .with_state(state);
+    tokio::spawn(async move {
+        while let Some(request) = reload_rx.recv().await {
+            info!("Reloading config: {}", request.reason);
+            let result = reload_config(&state, &args).await;
+            if let Some(reply) = request.reply {
+                let _ = reply.send(result);
+            }
+        }
+    });
 
-        info!("Android app: wss://{}/ws/app", wss_addr);
+    reload_tx
+}
 
-        axum_server::bind_rustls(wss_addr, tls_config)
-            .serve(wss_app.into_make_service())
+async fn reload_config(
+    state: &AppState,
+    args: &ConfigArgs,
+) -> Result<reload::ConfigChanges, String> {
+    let config = args.try_load().and_then(|mut config| {
+        resolve_token(&mut config).map_err(|e| format!("failed to load pairing token: {}", e))?;
+        Ok(config)
+    });
+    let config = match config {
+        Ok(config) => config,
+        Err(e) => {
+            error!("Failed to reload config, keeping the running one: {}", e);
+            return Err(e);
+        }
+    };
+
+    let changes = reload::apply(state, config).await;
+    if changes.is_empty() {
+        info!("Config unchanged");
+    }
+    if !changes.applied.is_empty() {
+        info!("Applied config changes: {}", changes.applied.join(", "));
+    }
+    if !changes.restart_required.is_empty() {
+        warn!(
+            "Config changes that need a restart to take effect: {}",
+            changes.restart_required.join(", ")
+        );
+    }
+    Ok(changes)
+}
+
+/// Switches the log filter when a reload changes `logging.level`.
+fn spawn_log_filter_updates(
+    mut updates: watch::Receiver<Arc<Config>>,
+    filter_handle: logging::FilterHandle,
+) {
+    let mut level = updates.borrow_and_update().logging.level.clone();
+    tokio::spawn(async move {
+        while updates.changed().await.is_ok() {
+            let logging = updates.borrow_and_update().logging.clone();
+            if logging.level == level {
+                continue;
+            }
+            if let Err(e) = logging::update_filter(&filter_handle, &logging) {
+                warn!("Failed to change the log level: {}", e);
+            }
+            level = logging.level;
+        }
+    });
+}
+
+/// Starts the dashboard with its embedded assets, over HTTPS when `tls_config` is set.
+fn spawn_dashboard(
+    addr: SocketAddr,
+    state: AppState,
+    tls_config: Option<RustlsConfig>,
+    redirect_port: Option<u16>,
+) {
+    let stats = state.stats.clone();
+    let app = androidoscopy_server::dashboard_routes(&state).with_state(state);
+
+    let Some(tls_config) = tls_config else {
+        tokio::spawn(async move {
+            let listener = TcpListener::bind(addr).await.unwrap();
+            info!("Dashboard: http://{}", addr);
+            stats.add_listener("Dashboard", format!("http://{}", addr), false);
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
             .await
             .unwrap();
-    } else {
-        warn!("TLS is disabled - Android apps will need cleartext permission");
-        start_ws_server(wss_addr, state).await;
+        });
+        return;
+    };
+
+    if let Some(redirect_port) = redirect_port {
+        let redirect_addr = SocketAddr::new(addr.ip(), redirect_port);
+        let stats = stats.clone();
+        tokio::spawn(async move {
+            let listener = TcpListener::bind(redirect_addr).await.unwrap();
+            info!("Redirecting http://{} to HTTPS", redirect_addr);
+            stats.add_listener("HTTPS redirect", format!("http://{}", redirect_addr), false);
+            axum::serve(listener, androidoscopy_server::https_redirect(addr.port()))
+                .await
+                .unwrap();
+        });
     }
+
+    stats.add_listener("Dashboard", format!("https://{}", addr), true);
+    tokio::spawn(async move {
+        info!("Dashboard: https://{}", addr);
+        axum_server::bind_rustls(addr, tls_config)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .unwrap();
+    });
 }
 
 async fn start_ws_server(addr: SocketAddr, state: AppState) {
+    let stats = state.stats.clone();
     let app = Router::new()
         .route("/ws/app", get(handlers::handle_app_ws))
         .with_state(state);
 
     let listener = TcpListener::bind(addr).await.unwrap();
     info!("Android app: ws://{}/ws/app", addr);
+    stats.add_listener(stats::APP_LISTENER, format!("ws://{}/ws/app", addr), false);
     axum::serve(listener, app).await.unwrap();
 }
@@ -0,0 +1,166 @@
+//! The machine's network interfaces, as used for certificate names and
+//! discovery.
+
+use std::net::{IpAddr, Ipv4Addr};
+use tracing::warn;
+
+/// One address of a network interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceAddr {
+    pub name: String,
+    pub ip: IpAddr,
+    /// Directed broadcast address of the subnet, for IPv4 addresses.
+    pub broadcast: Option<Ipv4Addr>,
+    /// Length of the subnet prefix.
+    pub prefix_len: u8,
+    /// OS interface index, the scope of IPv6 link-local multicast.
+    pub index: Option<u32>,
+}
+
+impl InterfaceAddr {
+    pub fn is_link_local(&self) -> bool {
+        match self.ip {
+            IpAddr::V4(ip) => ip.is_link_local(),
+            IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 == 0xfe80,
+        }
+    }
+
+    /// Whether `ip` is on this address's subnet.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.ip, ip) {
+            (IpAddr::V4(own), IpAddr::V4(other)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u32::from(own) & mask == u32::from(other) & mask
+            }
+            (IpAddr::V6(own), IpAddr::V6(other)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                u128::from(own) & mask == u128::from(other) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Addresses of all interfaces, except loopback ones.
+pub fn list() -> Vec<InterfaceAddr> {
+    let interfaces = match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(e) => {
+            warn!("Failed to list network interfaces: {}", e);
+            return Vec::new();
+        }
+    };
+
+    interfaces
+        .into_iter()
+        .filter(|interface| !interface.is_loopback())
+        .map(|interface| {
+            let prefix_len = match &interface.addr {
+                if_addrs::IfAddr::V4(addr) => addr.prefixlen,
+                if_addrs::IfAddr::V6(addr) => addr.prefixlen,
+            };
+            let broadcast = match &interface.addr {
+                // Point-to-point links, e.g. VPNs, have no subnet to broadcast to
+                if_addrs::IfAddr::V4(addr) => addr
+                    .broadcast
+                    .or_else(|| Some(directed_broadcast(addr.ip, addr.netmask)))
+                    .filter(|broadcast| *broadcast != addr.ip),
+                if_addrs::IfAddr::V6(_) => None,
+            };
+            InterfaceAddr {
+                ip: interface.ip(),
+                name: interface.name,
+                broadcast,
+                prefix_len,
+                index: interface.index,
+            }
+        })
+        .collect()
+}
+
+fn directed_broadcast(ip: Ipv4Addr, netmask: Ipv4Addr) -> Ipv4Addr {
+    Ipv4Addr::from(u32::from(ip) | !u32::from(netmask))
+}
+
+/// Whether `name` is selected by an include list (empty selects everything)
+/// and an exclude list. A trailing `*` in a pattern matches any suffix.
+pub fn is_selected(name: &str, include: &[String], exclude: &[String]) -> bool {
+    let matches = |pattern: &String| match pattern.strip_suffix('*') {
+        Some(prefix) => name.starts_with(prefix),
+        None => name == pattern,
+    };
+
+    (include.is_empty() || include.iter().any(matches)) && !exclude.iter().any(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directed_broadcast() {
+        assert_eq!(
+            directed_broadcast(
+                Ipv4Addr::new(192, 168, 1, 20),
+                Ipv4Addr::new(255, 255, 255, 0)
+            ),
+            Ipv4Addr::new(192, 168, 1, 255)
+        );
+        assert_eq!(
+            directed_broadcast(Ipv4Addr::new(10, 1, 2, 3), Ipv4Addr::new(255, 255, 240, 0)),
+            Ipv4Addr::new(10, 1, 15, 255)
+        );
+    }
+
+    #[test]
+    fn test_contains() {
+        let interface = |ip: &str, prefix_len: u8| InterfaceAddr {
+            name: "eth0".to_string(),
+            ip: ip.parse().unwrap(),
+            broadcast: None,
+            prefix_len,
+            index: None,
+        };
+
+        assert!(interface("192.168.1.20", 24).contains("192.168.1.99".parse().unwrap()));
+        assert!(!interface("192.168.1.20", 24).contains("192.168.2.99".parse().unwrap()));
+        assert!(!interface("10.8.0.2", 32).contains("10.8.0.1".parse().unwrap()));
+        assert!(interface("fe80::1", 64).contains("fe80::abcd".parse().unwrap()));
+        assert!(!interface("fe80::1", 64).contains("192.168.1.99".parse().unwrap()));
+        assert!(!interface("2001:db8::1", 64).contains("2001:db9::1".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_is_selected() {
+        let patterns = |names: &[&str]| {
+            names
+                .iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert!(is_selected("wlan0", &[], &[]));
+        assert!(is_selected("wlan0", &patterns(&["wlan0", "eth0"]), &[]));
+        assert!(!is_selected("eth1", &patterns(&["wlan0", "eth0"]), &[]));
+        assert!(is_selected("eth1", &patterns(&["eth*"]), &[]));
+        assert!(!is_selected(
+            "docker0",
+            &[],
+            &patterns(&["docker*", "veth*"])
+        ));
+        assert!(!is_selected(
+            "eth0",
+            &patterns(&["eth*"]),
+            &patterns(&["eth0"])
+        ));
+    }
+
+    #[test]
+    fn test_list_skips_loopback() {
+        assert!(list().iter().all(|interface| !interface.ip.is_loopback()));
+    }
+}
@@ -0,0 +1,151 @@
+//! Onboarding devices by scanning a QR code.
+//!
+//! The QR code encodes an `androidoscopy://connect` URI with everything the
+//! SDK needs to reach this server:
+//!
+//! ```text
+//! androidoscopy://connect?host=192.168.1.20&port=8889&tls=true&pin=sha256%2F...&token=...
+//! ```
+//!
+//! `pin` is only present with TLS enabled and `token` only with
+//! `[server.auth]` enabled. `androidoscopy qr` prints the code in the
+//! terminal; the dashboard shows it from `/api/onboarding/qr.svg`.
+
+use qrcode::render::{svg, unicode};
+use qrcode::QrCode;
+use serde::Serialize;
+use std::net::IpAddr;
+
+use crate::config::Config;
+use crate::tls;
+
+pub const URI_PREFIX: &str = "androidoscopy://connect";
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Onboarding {
+    pub host: String,
+    pub port: u16,
+    pub tls: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<String>,
+}
+
+impl Onboarding {
+    /// Describes how to reach the app port of a server running with `config`.
+    ///
+    /// The pairing token must already be resolved into `config.server.auth.token`.
+    pub fn from_config(config: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        let tls_config = &config.server.tls;
+        let pin = if tls_config.enabled {
+            let der = tls::get_certificate_der(tls::distributed_certificate_path(tls_config))?;
+            Some(tls::certificate_pin(&der)?)
+        } else {
+            None
+        };
+
+        let token = if config.server.auth.enabled {
+            config.server.auth.token.clone()
+        } else {
+            None
+        };
+
+        Ok(Self {
+            host: advertised_host(&config.server.bind_address),
+            port: config.server.websocket_port,
+            tls: tls_config.enabled,
+            pin,
+            token,
+        })
+    }
+
+    pub fn uri(&self) -> String {
+        // serde_urlencoded only fails on nested values, which this struct doesn't have
+        let query = serde_urlencoded::to_string(self).unwrap_or_default();
+        format!("{}?{}", URI_PREFIX, query)
+    }
+
+    pub fn qr_svg(&self) -> Result<String, qrcode::types::QrError> {
+        let code = QrCode::new(self.uri())?;
+        Ok(code
+            .render::<svg::Color>()
+            .min_dimensions(256, 256)
+            .quiet_zone(true)
+            .build())
+    }
+
+    /// Renders the QR code with half-block characters, light on dark so it
+    /// scans on dark terminal themes too.
+    pub fn qr_terminal(&self) -> Result<String, qrcode::types::QrError> {
+        let code = QrCode::new(self.uri())?;
+        Ok(code
+            .render::<unicode::Dense1x2>()
+            .dark_color(unicode::Dense1x2::Light)
+            .light_color(unicode::Dense1x2::Dark)
+            .quiet_zone(true)
+            .build())
+    }
+}
+
+/// The address devices should use: the bind address if it is a specific one,
+/// otherwise the first LAN IP.
+fn advertised_host(bind_address: &str) -> String {
+    match bind_address.parse::<IpAddr>() {
+        Ok(ip) if !ip.is_unspecified() => ip.to_string(),
+        _ => tls::get_local_ips()
+            .into_iter()
+            .find(|ip| ip.parse::<IpAddr>().is_ok_and(|ip| ip.is_ipv4()))
+            .unwrap_or_else(|| "127.0.0.1".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn onboarding() -> Onboarding {
+        Onboarding {
+            host: "192.168.1.20".to_string(),
+            port: 8889,
+            tls: true,
+            pin: Some("sha256/AbC+/=".to_string()),
+            token: Some("secret".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_uri() {
+        assert_eq!(
+            onboarding().uri(),
+            "androidoscopy://connect?host=192.168.1.20&port=8889&tls=true&pin=sha256%2FAbC%2B%2F%3D&token=secret"
+        );
+
+        let plain = Onboarding {
+            tls: false,
+            pin: None,
+            token: None,
+            ..onboarding()
+        };
+        assert_eq!(
+            plain.uri(),
+            "androidoscopy://connect?host=192.168.1.20&port=8889&tls=false"
+        );
+    }
+
+    #[test]
+    fn test_advertised_host() {
+        assert_eq!(advertised_host("127.0.0.1"), "127.0.0.1");
+        assert!(advertised_host("0.0.0.0")
+            .parse::<IpAddr>()
+            .unwrap()
+            .is_ipv4());
+    }
+
+    #[test]
+    fn test_qr_rendering() {
+        let svg = onboarding().qr_svg().unwrap();
+        assert!(svg.contains("<svg"));
+        assert!(!onboarding().qr_terminal().unwrap().is_empty());
+    }
+}
@@ -12,10 +12,12 @@
 //!   DATA → (forwarded to dashboards as SESSION_DATA)
 //!   LOG → (forwarded to dashboards as SESSION_LOG)
 //!   ACTION_RESULT → (forwarded to dashboards)
+//!   BLOB_BEGIN, BLOB_CHUNK..., BLOB_END → BLOB_STORED (response)
 //!
 //! Service → App:
 //!   REGISTERED (in response to REGISTER)
 //!   ACTION (forwarded from dashboard)
+//!   BLOB_STORED (in response to BLOB_END)
 //!   ERROR (on protocol errors)
 //!
 //! Dashboard ↔ Service:
@@ -26,12 +28,18 @@
 //!   ← SESSION_ENDED (when app disconnects)
 //!   → ACTION (sent to specific session)
 //!   ← ACTION_RESULT (response from app)
+//!   ← ATTACHMENT_ADDED (when an app finishes a BLOB upload)
 //! ```
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 
+use crate::config::LimitsConfig;
+
+/// Versions of this protocol apps may REGISTER with.
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["1.0"];
+
 // === App → Service Messages ===
 
 /// Messages sent from Android apps to the service.
@@ -61,6 +69,24 @@ pub enum AppMessage {
         session_id: String,
         payload: ActionResultPayload,
     },
+    #[serde(rename = "BLOB_BEGIN")]
+    BlobBegin {
+        timestamp: DateTime<Utc>,
+        session_id: String,
+        payload: BlobBeginPayload,
+    },
+    #[serde(rename = "BLOB_CHUNK")]
+    BlobChunk {
+        timestamp: DateTime<Utc>,
+        session_id: String,
+        payload: BlobChunkPayload,
+    },
+    #[serde(rename = "BLOB_END")]
+    BlobEnd {
+        timestamp: DateTime<Utc>,
+        session_id: String,
+        payload: BlobEndPayload,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -71,6 +97,9 @@ pub struct RegisterPayload {
     pub version_name: String,
     pub device: DeviceInfo,
     pub dashboard: Value,
+    /// Pairing token, for apps that can't set a header on the upgrade request.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth_token: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -91,7 +120,8 @@ pub struct LogPayload {
     pub throwable: Option<String>,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+/// Ordered from the most verbose to the most severe.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
 #[serde(rename_all = "UPPERCASE")]
 pub enum LogLevel {
     Verbose,
@@ -109,6 +139,33 @@ pub struct ActionResultPayload {
     pub data: Option<Value>,
 }
 
+/// Starts a chunked upload of a payload too large for a single message.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlobBeginPayload {
+    /// Client-chosen id referenced by the following chunks.
+    pub blob_id: String,
+    pub name: String,
+    pub mime_type: String,
+    /// Total size in bytes after reassembly.
+    pub size: u64,
+    /// Lowercase hex SHA-256 of the reassembled content.
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlobChunkPayload {
+    pub blob_id: String,
+    /// Zero-based position of the chunk; chunks must arrive in order.
+    pub index: u32,
+    /// Base64-encoded (standard alphabet, padded) chunk content.
+    pub data: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlobEndPayload {
+    pub blob_id: String,
+}
+
 // === Service → App Messages ===
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -125,6 +182,11 @@ pub enum ServiceToAppMessage {
         session_id: String,
         payload: ActionPayload,
     },
+    #[serde(rename = "BLOB_STORED")]
+    BlobStored {
+        timestamp: DateTime<Utc>,
+        payload: BlobStoredPayload,
+    },
     #[serde(rename = "ERROR")]
     Error {
         timestamp: DateTime<Utc>,
@@ -144,6 +206,12 @@ pub struct ActionPayload {
     pub args: Option<Value>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlobStoredPayload {
+    pub blob_id: String,
+    pub attachment: AttachmentInfo,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ErrorPayload {
     pub code: String,
@@ -181,6 +249,11 @@ pub enum ServiceToDashboardMessage {
         timestamp: DateTime<Utc>,
         payload: ActionResultToDashboardPayload,
     },
+    #[serde(rename = "ATTACHMENT_ADDED")]
+    AttachmentAdded {
+        timestamp: DateTime<Utc>,
+        payload: AttachmentAddedPayload,
+    },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -214,6 +287,12 @@ pub struct ActionResultToDashboardPayload {
     pub data: Option<Value>,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachmentAddedPayload {
+    pub session_id: String,
+    pub attachment: AttachmentInfo,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncPayload {
     pub sessions: Vec<SessionInfo>,
@@ -228,8 +307,39 @@ pub struct SessionInfo {
     pub device: DeviceInfo,
     pub dashboard: Value,
     pub started_at: DateTime<Utc>,
+    /// Only set for ended sessions, which dashboards don't get in SYNC but
+    /// the HTTP API lists until they expire.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ended_at: Option<DateTime<Utc>>,
     pub latest_data: Option<Value>,
     pub recent_logs: Vec<LogEntry>,
+    #[serde(default)]
+    pub attachments: Vec<AttachmentInfo>,
+}
+
+/// A session without its data and logs, as listed by `GET /api/sessions`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionSummary {
+    pub session_id: String,
+    pub app_name: String,
+    pub package_name: String,
+    pub version_name: String,
+    pub device: DeviceInfo,
+    pub started_at: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ended_at: Option<DateTime<Utc>>,
+}
+
+/// Metadata of a file uploaded by an app, downloadable from
+/// `/api/sessions/{session_id}/attachments/{id}`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttachmentInfo {
+    pub id: String,
+    pub name: String,
+    pub mime_type: String,
+    pub size: u64,
+    pub sha256: String,
+    pub created_at: DateTime<Utc>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -245,9 +355,7 @@ pub struct LogEntry {
 #[serde(tag = "type")]
 pub enum DashboardToServiceMessage {
     #[serde(rename = "ACTION")]
-    Action {
-        payload: DashboardActionPayload,
-    },
+    Action { payload: DashboardActionPayload },
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -258,47 +366,178 @@ pub struct DashboardActionPayload {
     pub args: Option<Value>,
 }
 
-// === Message Size Limits ===
-
-pub const MAX_MESSAGE_SIZE: usize = 1024 * 1024; // 1 MB
-pub const MAX_LOG_MESSAGE_SIZE: usize = 64 * 1024; // 64 KB
-pub const MAX_LOG_THROWABLE_SIZE: usize = 256 * 1024; // 256 KB
-
 // === Validation ===
 
 impl AppMessage {
-    pub fn validate(&self) -> Result<(), ValidationError> {
+    /// Checks the message against the configured size limits.
+    pub fn validate(&self, limits: &LimitsConfig) -> Result<(), ValidationError> {
         match self {
+            AppMessage::Data { payload, .. } => {
+                let depth = json_depth(payload);
+                if depth > limits.max_data_depth {
+                    return Err(ValidationError::DataTooDeep {
+                        depth,
+                        max: limits.max_data_depth,
+                    });
+                }
+                let size = json_size(payload);
+                if size > limits.max_data_size {
+                    return Err(ValidationError::DataTooLarge {
+                        size,
+                        max: limits.max_data_size,
+                    });
+                }
+                Ok(())
+            }
             AppMessage::Log { payload, .. } => {
-                if payload.message.len() > MAX_LOG_MESSAGE_SIZE {
-                    return Err(ValidationError::LogMessageTooLarge);
+                let size = payload.message.len();
+                if size > limits.max_log_message_size {
+                    return Err(ValidationError::LogMessageTooLarge {
+                        size,
+                        max: limits.max_log_message_size,
+                    });
                 }
                 if let Some(ref throwable) = payload.throwable {
-                    if throwable.len() > MAX_LOG_THROWABLE_SIZE {
-                        return Err(ValidationError::LogThrowableTooLarge);
+                    if throwable.len() > limits.max_log_throwable_size {
+                        return Err(ValidationError::LogThrowableTooLarge {
+                            size: throwable.len(),
+                            max: limits.max_log_throwable_size,
+                        });
                     }
                 }
                 Ok(())
             }
+            AppMessage::ActionResult { payload, .. } => {
+                if let Some(ref data) = payload.data {
+                    let size = json_size(data);
+                    if size > limits.max_action_result_size {
+                        return Err(ValidationError::ActionResultTooLarge {
+                            size,
+                            max: limits.max_action_result_size,
+                        });
+                    }
+                }
+                Ok(())
+            }
+            AppMessage::BlobBegin { payload, .. } => {
+                if payload.size > limits.max_blob_size {
+                    return Err(ValidationError::BlobTooLarge {
+                        size: payload.size,
+                        max: limits.max_blob_size,
+                    });
+                }
+                Ok(())
+            }
+            AppMessage::BlobChunk { payload, .. } => {
+                let size = payload.data.len();
+                if size > limits.max_blob_chunk_size {
+                    return Err(ValidationError::BlobChunkTooLarge {
+                        size,
+                        max: limits.max_blob_chunk_size,
+                    });
+                }
+                Ok(())
+            }
             _ => Ok(()),
         }
     }
 }
 
+/// Length of the value serialized as compact JSON, without allocating it.
+fn json_size(value: &Value) -> usize {
+    struct Counter(usize);
+
+    impl std::io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    // Writing a Value to an infallible writer can't fail
+    let _ = serde_json::to_writer(&mut counter, value);
+    counter.0
+}
+
+/// Nesting depth of objects and arrays; scalars have depth 0.
+fn json_depth(value: &Value) -> usize {
+    match value {
+        Value::Array(items) => 1 + items.iter().map(json_depth).max().unwrap_or(0),
+        Value::Object(fields) => 1 + fields.values().map(json_depth).max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ValidationError {
-    LogMessageTooLarge,
-    LogThrowableTooLarge,
+    DataTooLarge { size: usize, max: usize },
+    DataTooDeep { depth: usize, max: usize },
+    LogMessageTooLarge { size: usize, max: usize },
+    LogThrowableTooLarge { size: usize, max: usize },
+    ActionResultTooLarge { size: usize, max: usize },
+    BlobTooLarge { size: u64, max: u64 },
+    BlobChunkTooLarge { size: usize, max: usize },
+}
+
+impl ValidationError {
+    /// Error code reported to the app in an ERROR message.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ValidationError::DataTooLarge { .. } => "DATA_TOO_LARGE",
+            ValidationError::DataTooDeep { .. } => "DATA_TOO_DEEP",
+            ValidationError::LogMessageTooLarge { .. } => "LOG_MESSAGE_TOO_LARGE",
+            ValidationError::LogThrowableTooLarge { .. } => "LOG_THROWABLE_TOO_LARGE",
+            ValidationError::ActionResultTooLarge { .. } => "ACTION_RESULT_TOO_LARGE",
+            ValidationError::BlobTooLarge { .. } => "BLOB_TOO_LARGE",
+            ValidationError::BlobChunkTooLarge { .. } => "BLOB_CHUNK_TOO_LARGE",
+        }
+    }
 }
 
 impl std::fmt::Display for ValidationError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            ValidationError::LogMessageTooLarge => {
-                write!(f, "Log message exceeds maximum size of {} bytes", MAX_LOG_MESSAGE_SIZE)
+            ValidationError::DataTooLarge { size, max } => {
+                write!(
+                    f,
+                    "DATA payload is {} bytes, maximum is {} bytes",
+                    size, max
+                )
+            }
+            ValidationError::DataTooDeep { depth, max } => {
+                write!(
+                    f,
+                    "DATA payload is nested {} levels deep, maximum is {}",
+                    depth, max
+                )
+            }
+            ValidationError::LogMessageTooLarge { size, max } => {
+                write!(f, "Log message is {} bytes, maximum is {} bytes", size, max)
+            }
+            ValidationError::LogThrowableTooLarge { size, max } => {
+                write!(
+                    f,
+                    "Log throwable is {} bytes, maximum is {} bytes",
+                    size, max
+                )
             }
-            ValidationError::LogThrowableTooLarge => {
-                write!(f, "Log throwable exceeds maximum size of {} bytes", MAX_LOG_THROWABLE_SIZE)
+            ValidationError::ActionResultTooLarge { size, max } => {
+                write!(
+                    f,
+                    "Action result data is {} bytes, maximum is {} bytes",
+                    size, max
+                )
+            }
+            ValidationError::BlobTooLarge { size, max } => {
+                write!(f, "Blob is {} bytes, maximum is {} bytes", size, max)
+            }
+            ValidationError::BlobChunkTooLarge { size, max } => {
+                write!(f, "Blob chunk is {} bytes, maximum is {} bytes", size, max)
             }
         }
     }
@@ -361,7 +600,11 @@ mod tests {
 
         let msg: AppMessage = serde_json::from_value(json).unwrap();
         match msg {
-            AppMessage::Data { session_id, payload, .. } => {
+            AppMessage::Data {
+                session_id,
+                payload,
+                ..
+            } => {
                 assert_eq!(session_id, "session-123");
                 assert!(payload.get("memory").is_some());
             }
@@ -395,7 +638,8 @@ mod tests {
 
     #[test]
     fn test_validate_log_message_too_large() {
-        let large_message = "x".repeat(MAX_LOG_MESSAGE_SIZE + 1);
+        let limits = LimitsConfig::default();
+        let large_message = "x".repeat(limits.max_log_message_size + 1);
         let msg = AppMessage::Log {
             timestamp: Utc::now(),
             session_id: "session-123".to_string(),
@@ -407,12 +651,19 @@ mod tests {
             },
         };
 
-        assert_eq!(msg.validate(), Err(ValidationError::LogMessageTooLarge));
+        assert_eq!(
+            msg.validate(&limits),
+            Err(ValidationError::LogMessageTooLarge {
+                size: limits.max_log_message_size + 1,
+                max: limits.max_log_message_size,
+            })
+        );
     }
 
     #[test]
     fn test_validate_log_throwable_too_large() {
-        let large_throwable = "x".repeat(MAX_LOG_THROWABLE_SIZE + 1);
+        let limits = LimitsConfig::default();
+        let large_throwable = "x".repeat(limits.max_log_throwable_size + 1);
         let msg = AppMessage::Log {
             timestamp: Utc::now(),
             session_id: "session-123".to_string(),
@@ -424,7 +675,131 @@ mod tests {
             },
         };
 
-        assert_eq!(msg.validate(), Err(ValidationError::LogThrowableTooLarge));
+        assert!(matches!(
+            msg.validate(&limits),
+            Err(ValidationError::LogThrowableTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_blob_messages() {
+        let begin = json!({
+            "type": "BLOB_BEGIN",
+            "timestamp": "2024-12-02T14:30:00.000Z",
+            "session_id": "session-123",
+            "payload": {
+                "blob_id": "blob-1",
+                "name": "screenshot.png",
+                "mime_type": "image/png",
+                "size": 3,
+                "sha256": "039058c6f2c0cb492c533b0a4d14ef77cc0f78abccced5287d84a1a2011cfb81"
+            }
+        });
+        let chunk = json!({
+            "type": "BLOB_CHUNK",
+            "timestamp": "2024-12-02T14:30:00.000Z",
+            "session_id": "session-123",
+            "payload": { "blob_id": "blob-1", "index": 0, "data": "AQID" }
+        });
+
+        match serde_json::from_value::<AppMessage>(begin).unwrap() {
+            AppMessage::BlobBegin { payload, .. } => {
+                assert_eq!(payload.name, "screenshot.png");
+                assert_eq!(payload.size, 3);
+            }
+            _ => panic!("Expected BlobBegin message"),
+        }
+        match serde_json::from_value::<AppMessage>(chunk).unwrap() {
+            AppMessage::BlobChunk { payload, .. } => {
+                assert_eq!(payload.index, 0);
+                assert_eq!(payload.data, "AQID");
+            }
+            _ => panic!("Expected BlobChunk message"),
+        }
+    }
+
+    #[test]
+    fn test_validate_blob_chunk_too_large() {
+        let limits = LimitsConfig::default();
+        let msg = AppMessage::BlobChunk {
+            timestamp: Utc::now(),
+            session_id: "session-123".to_string(),
+            payload: BlobChunkPayload {
+                blob_id: "blob-1".to_string(),
+                index: 0,
+                data: "A".repeat(limits.max_blob_chunk_size + 4),
+            },
+        };
+
+        assert!(matches!(
+            msg.validate(&limits),
+            Err(ValidationError::BlobChunkTooLarge { .. })
+        ));
+    }
+
+    fn data_message(payload: Value) -> AppMessage {
+        AppMessage::Data {
+            timestamp: Utc::now(),
+            session_id: "session-123".to_string(),
+            payload,
+        }
+    }
+
+    #[test]
+    fn test_validate_data_size() {
+        let limits = LimitsConfig {
+            max_data_size: 32,
+            ..LimitsConfig::default()
+        };
+
+        let small = data_message(json!({ "fps": 60 }));
+        assert_eq!(small.validate(&limits), Ok(()));
+
+        let large = data_message(json!({ "body": "x".repeat(32) }));
+        assert_eq!(
+            large.validate(&limits),
+            Err(ValidationError::DataTooLarge { size: 43, max: 32 })
+        );
+    }
+
+    #[test]
+    fn test_validate_data_depth() {
+        let limits = LimitsConfig {
+            max_data_depth: 3,
+            ..LimitsConfig::default()
+        };
+
+        let ok = data_message(json!({ "a": { "b": [1, 2] } }));
+        assert_eq!(ok.validate(&limits), Ok(()));
+
+        let deep = data_message(json!({ "a": { "b": [[1]] } }));
+        assert_eq!(
+            deep.validate(&limits),
+            Err(ValidationError::DataTooDeep { depth: 4, max: 3 })
+        );
+    }
+
+    #[test]
+    fn test_validate_action_result_size() {
+        let limits = LimitsConfig {
+            max_action_result_size: 16,
+            ..LimitsConfig::default()
+        };
+        let msg = AppMessage::ActionResult {
+            timestamp: Utc::now(),
+            session_id: "session-123".to_string(),
+            payload: ActionResultPayload {
+                action_id: "action-1".to_string(),
+                success: true,
+                message: None,
+                data: Some(json!({ "dump": "x".repeat(16) })),
+            },
+        };
+
+        assert!(matches!(
+            msg.validate(&limits),
+            Err(ValidationError::ActionResultTooLarge { max: 16, .. })
+        ));
     }
 
     #[test]
@@ -444,9 +819,7 @@ mod tests {
     #[test]
     fn test_serialize_sync_message() {
         let msg = ServiceToDashboardMessage::Sync {
-            payload: SyncPayload {
-                sessions: vec![],
-            },
+            payload: SyncPayload { sessions: vec![] },
         };
 
         let json = serde_json::to_value(&msg).unwrap();
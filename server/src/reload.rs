@@ -0,0 +1,262 @@
+//! Applying config changes to a running server.
+//!
+//! `androidoscopy run` reloads its config when the file changes, on SIGHUP
+//! or when asked over the control socket. Settings matching [`LIVE_KEYS`] take effect right away: buffer
+//! sizes for sessions created afterwards, the session TTL, attachment and
+//! message limits, the log level, discovery, the names and lifetime of the
+//! auto-generated TLS certificate and the dashboard users.
+//! Everything else, such as ports and TLS, keeps its running value and is
+//! reported as needing a restart.
+
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tokio::sync::oneshot;
+
+use crate::config::{self, Config};
+use crate::state::AppState;
+
+/// Config keys, or tables of keys, applied without a restart.
+pub const LIVE_KEYS: &[&str] = &[
+    "session.data_buffer_size",
+    "session.log_buffer_size",
+    "session.ended_session_ttl_seconds",
+    "session.max_attachments_per_session",
+    "session.attachment_quota_bytes",
+    "limits",
+    "logging.level",
+    "server.udp_discovery_enabled",
+    "server.mdns_enabled",
+    "server.discovery",
+    "server.tls.extra_hostnames",
+    "server.tls.leaf_validity_days",
+    "dashboard.auth.enabled",
+    "dashboard.auth.users",
+];
+
+/// The keys that differ between two configs.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConfigChanges {
+    pub applied: Vec<String>,
+    pub restart_required: Vec<String>,
+}
+
+impl ConfigChanges {
+    pub fn is_empty(&self) -> bool {
+        self.applied.is_empty() && self.restart_required.is_empty()
+    }
+}
+
+/// Asks the server to reload its config.
+pub struct ReloadRequest {
+    /// Why, for the server log.
+    pub reason: String,
+    /// Told what changed, or why the new config was rejected.
+    pub reply: Option<oneshot::Sender<Result<ConfigChanges, String>>>,
+}
+
+impl ReloadRequest {
+    pub fn new(reason: impl Into<String>) -> Self {
+        Self {
+            reason: reason.into(),
+            reply: None,
+        }
+    }
+}
+
+pub fn is_live(key: &str) -> bool {
+    LIVE_KEYS.iter().any(|live| {
+        key.strip_prefix(live)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    })
+}
+
+/// Compares `old` and `new` key by key.
+pub fn diff(old: &Config, new: &Config) -> ConfigChanges {
+    let old = flatten(old);
+    let new = flatten(new);
+
+    let mut changes = ConfigChanges::default();
+    let keys: std::collections::BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    for key in keys {
+        if old.get(key) == new.get(key) {
+            continue;
+        }
+        if is_live(key) {
+            changes.applied.push(key.clone());
+        } else {
+            changes.restart_required.push(key.clone());
+        }
+    }
+    changes
+}
+
+/// Switches `state` to `new`, except for the keys needing a restart, which
+/// keep their running values.
+///
+/// `new` must be prepared like the config the server started with, e.g. with
+/// the pairing token resolved.
+pub async fn apply(state: &AppState, new: Config) -> ConfigChanges {
+    let old = state.config();
+    let changes = diff(&old, &new);
+    if changes.is_empty() {
+        return changes;
+    }
+
+    let effective = keep_running_values(&old, new, &changes.restart_required);
+
+    let session = &effective.session;
+    {
+        let mut manager = state.session_manager.lock().await;
+        manager.set_buffer_sizes(session.data_buffer_size, session.log_buffer_size);
+        manager.set_ended_session_ttl(session.ended_session_ttl_seconds);
+    }
+    state.attachment_store.set_limits(
+        session.max_attachments_per_session,
+        session.attachment_quota_bytes,
+    );
+
+    // Limits and dashboard users are read from the state on use; the log
+    // level, discovery and the certificate watcher follow `AppState::config_updates`
+    state.set_config(effective);
+    changes
+}
+
+fn keep_running_values(old: &Config, new: Config, keys: &[String]) -> Config {
+    if keys.is_empty() {
+        return new;
+    }
+
+    let old_values = flatten(old);
+    let Ok(toml::Value::Table(mut table)) = toml::Value::try_from(&new) else {
+        return old.clone();
+    };
+    for key in keys {
+        let path: Vec<&str> = key.split('.').collect();
+        match old_values.get(key) {
+            Some(value) => {
+                let _ = config::insert(&mut table, &path, value.clone());
+            }
+            None => remove(&mut table, &path),
+        }
+    }
+
+    // Both halves come from valid configs, so this only fails on a bug
+    toml::Value::Table(table)
+        .try_into()
+        .unwrap_or_else(|_| old.clone())
+}
+
+fn remove(table: &mut toml::Table, path: &[&str]) {
+    match path {
+        [] => {}
+        [last] => {
+            table.remove(*last);
+        }
+        [first, rest @ ..] => {
+            if let Some(toml::Value::Table(child)) = table.get_mut(*first) {
+                remove(child, rest);
+            }
+        }
+    }
+}
+
+/// The config as dotted keys; arrays are single values.
+fn flatten(config: &Config) -> BTreeMap<String, toml::Value> {
+    fn walk(prefix: &str, value: toml::Value, out: &mut BTreeMap<String, toml::Value>) {
+        match value {
+            toml::Value::Table(table) => {
+                for (key, value) in table {
+                    let key = if prefix.is_empty() {
+                        key
+                    } else {
+                        format!("{}.{}", prefix, key)
+                    };
+                    walk(&key, value, out);
+                }
+            }
+            value => {
+                out.insert(prefix.to_string(), value);
+            }
+        }
+    }
+
+    let mut out = BTreeMap::new();
+    if let Ok(value) = toml::Value::try_from(config) {
+        walk("", value, &mut out);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_live() {
+        assert!(is_live("limits.max_message_size"));
+        assert!(is_live("session.data_buffer_size"));
+        assert!(is_live("server.discovery.interfaces"));
+        assert!(is_live("server.tls.extra_hostnames"));
+        assert!(!is_live("server.tls.cert_path"));
+        assert!(!is_live("session.attachments_dir"));
+        assert!(!is_live("server.http_port"));
+        assert!(!is_live("server.mdns_enabled_extra"));
+    }
+
+    #[test]
+    fn test_diff() {
+        let old = Config::default();
+        let mut new = Config::default();
+        new.session.data_buffer_size = 10;
+        new.server.http_port = 9000;
+        new.server.auth.token = Some("secret".to_string());
+        new.server.discovery.ipv6_enabled = false;
+
+        assert_eq!(
+            diff(&old, &new),
+            ConfigChanges {
+                applied: vec![
+                    "server.discovery.ipv6_enabled".to_string(),
+                    "session.data_buffer_size".to_string(),
+                ],
+                restart_required: vec![
+                    "server.auth.token".to_string(),
+                    "server.http_port".to_string()
+                ],
+            }
+        );
+        assert!(diff(&old, &old).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_apply() {
+        let state = AppState::new(Config::default());
+        let updates = state.config_updates();
+
+        let mut new = Config::default();
+        new.limits.max_message_size = 1024;
+        new.session.max_attachments_per_session = 0;
+        new.server.http_port = 9000;
+        new.server.auth.token = Some("secret".to_string());
+
+        let changes = apply(&state, new).await;
+        assert_eq!(
+            changes.applied,
+            vec![
+                "limits.max_message_size",
+                "session.max_attachments_per_session"
+            ]
+        );
+        assert_eq!(
+            changes.restart_required,
+            vec!["server.auth.token", "server.http_port"]
+        );
+
+        let config = state.config();
+        assert_eq!(config.limits.max_message_size, 1024);
+        assert_eq!(config.server.http_port, 8880);
+        assert_eq!(config.server.auth.token, None);
+        assert!(updates.has_changed().unwrap());
+        assert!(state.attachment_store.check_quota(&[], 1).is_err());
+    }
+}
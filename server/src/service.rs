@@ -1,5 +1,5 @@
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
 const SERVICE_NAME: &str = "androidoscopy";
@@ -97,14 +97,6 @@ pub fn uninstall() -> Result<(), Box<dyn std::error::Error>> {
     Ok(())
 }
 
-pub fn status() -> Result<(), Box<dyn std::error::Error>> {
-    let status = Command::new("systemctl")
-        .args(["--user", "status", SERVICE_NAME])
-        .status()?;
-
-    std::process::exit(status.code().unwrap_or(1));
-}
-
 fn get_bin_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
     let home = dirs::home_dir().ok_or("Could not find home directory")?;
     Ok(home.join(".local").join("bin"))
@@ -115,7 +107,7 @@ fn get_service_dir() -> Result<PathBuf, Box<dyn std::error::Error>> {
     Ok(config.join("systemd").join("user"))
 }
 
-fn generate_service_file(binary_path: &PathBuf) -> String {
+fn generate_service_file(binary_path: &Path) -> String {
     format!(
         r#"[Unit]
 Description=Androidoscopy Server
@@ -124,6 +116,7 @@ After=network.target
 [Service]
 Type=simple
 ExecStart={binary_path} run
+ExecReload=/bin/kill -HUP $MAINPID
 Restart=on-failure
 RestartSec=5
 
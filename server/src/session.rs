@@ -13,8 +13,8 @@ use tokio::sync::mpsc;
 use uuid::Uuid;
 
 use crate::protocol::{
-    DeviceInfo, LogEntry, LogPayload, RegisterPayload, ServiceToAppMessage, ServiceToDashboardMessage,
-    SessionInfo,
+    AttachmentInfo, DeviceInfo, LogEntry, LogPayload, RegisterPayload, ServiceToAppMessage,
+    ServiceToDashboardMessage, SessionInfo, SessionSummary,
 };
 
 // === Ring Buffer ===
@@ -101,6 +101,7 @@ pub struct Session {
     data_buffer: RingBuffer<DataMessage>,
     log_buffer: RingBuffer<LogMessage>,
     network_requests: RingBuffer<Value>,
+    attachments: Vec<AttachmentInfo>,
     pub app_sender: Option<mpsc::Sender<ServiceToAppMessage>>,
 }
 
@@ -123,6 +124,7 @@ impl Session {
             data_buffer: RingBuffer::new(data_buffer_size),
             log_buffer: RingBuffer::new(log_buffer_size),
             network_requests: RingBuffer::new(500), // Store up to 500 network requests
+            attachments: Vec::new(),
             app_sender: Some(app_sender),
         }
     }
@@ -139,6 +141,18 @@ impl Session {
         self.log_buffer.push(LogMessage { timestamp, payload });
     }
 
+    pub fn add_attachment(&mut self, attachment: AttachmentInfo) {
+        self.attachments.push(attachment);
+    }
+
+    pub fn get_attachment(&self, attachment_id: &str) -> Option<&AttachmentInfo> {
+        self.attachments.iter().find(|a| a.id == attachment_id)
+    }
+
+    pub fn attachments(&self) -> &[AttachmentInfo] {
+        &self.attachments
+    }
+
     pub fn get_latest_data(&self) -> Option<Value> {
         self.data_buffer.iter().last().map(|d| d.payload.clone())
     }
@@ -170,8 +184,22 @@ impl Session {
             device: self.device.clone(),
             dashboard: self.dashboard_schema.clone(),
             started_at: self.started_at,
+            ended_at: self.ended_at,
             latest_data: self.get_latest_data(),
             recent_logs: self.get_recent_logs(),
+            attachments: self.attachments.clone(),
+        }
+    }
+
+    pub fn to_session_summary(&self) -> SessionSummary {
+        SessionSummary {
+            session_id: self.id.clone(),
+            app_name: self.app_name.clone(),
+            package_name: self.package_name.clone(),
+            version_name: self.version_name.clone(),
+            device: self.device.clone(),
+            started_at: self.started_at,
+            ended_at: self.ended_at,
         }
     }
 }
@@ -187,7 +215,11 @@ pub struct SessionManager {
 }
 
 impl SessionManager {
-    pub fn new(data_buffer_size: usize, log_buffer_size: usize, ended_session_ttl_seconds: u64) -> Self {
+    pub fn new(
+        data_buffer_size: usize,
+        log_buffer_size: usize,
+        ended_session_ttl_seconds: u64,
+    ) -> Self {
         Self {
             sessions: HashMap::new(),
             dashboard_senders: Vec::new(),
@@ -203,7 +235,9 @@ impl SessionManager {
         app_sender: mpsc::Sender<ServiceToAppMessage>,
     ) -> (String, bool) {
         // Check for existing ended session from same device + package
-        let existing_session_id = self.sessions.iter()
+        let existing_session_id = self
+            .sessions
+            .iter()
             .find(|(_, s)| {
                 s.ended_at.is_some()
                     && s.device.device_id == register.device.device_id
@@ -231,6 +265,16 @@ impl SessionManager {
         }
     }
 
+    /// Changes the buffer sizes of sessions created from now on.
+    pub fn set_buffer_sizes(&mut self, data_buffer_size: usize, log_buffer_size: usize) {
+        self.data_buffer_size = data_buffer_size;
+        self.log_buffer_size = log_buffer_size;
+    }
+
+    pub fn set_ended_session_ttl(&mut self, ended_session_ttl_seconds: u64) {
+        self.ended_session_ttl_seconds = ended_session_ttl_seconds;
+    }
+
     pub fn end_session(&mut self, session_id: &str) {
         if let Some(session) = self.sessions.get_mut(session_id) {
             session.end();
@@ -275,6 +319,15 @@ impl SessionManager {
         }
     }
 
+    pub fn add_attachment(&mut self, session_id: &str, attachment: AttachmentInfo) -> bool {
+        if let Some(session) = self.sessions.get_mut(session_id) {
+            session.add_attachment(attachment);
+            true
+        } else {
+            false
+        }
+    }
+
     pub fn add_dashboard_sender(&mut self, sender: mpsc::Sender<ServiceToDashboardMessage>) {
         self.dashboard_senders.push(sender);
     }
@@ -287,18 +340,23 @@ impl SessionManager {
         &self.dashboard_senders
     }
 
-    pub fn cleanup_ended_sessions(&mut self) {
+    /// Drops sessions that ended longer than the TTL ago and returns their ids.
+    pub fn cleanup_ended_sessions(&mut self) -> Vec<String> {
         let now = Utc::now();
         let ttl_seconds = self.ended_session_ttl_seconds as i64;
+        let mut removed = Vec::new();
 
-        self.sessions.retain(|_, session| {
+        self.sessions.retain(|id, session| {
             if let Some(ended_at) = session.ended_at {
                 let elapsed = now.signed_duration_since(ended_at).num_seconds();
-                elapsed < ttl_seconds
-            } else {
-                true
+                if elapsed >= ttl_seconds {
+                    removed.push(id.clone());
+                    return false;
+                }
             }
+            true
         });
+        removed
     }
 
     pub fn session_count(&self) -> usize {
@@ -331,6 +389,7 @@ mod tests {
                 is_emulator: false,
             },
             dashboard: json!({ "sections": [] }),
+            auth_token: None,
         }
     }
 
@@ -498,7 +557,7 @@ mod tests {
             session.ended_at = Some(Utc::now() - chrono::Duration::seconds(2));
         }
 
-        manager.cleanup_ended_sessions();
+        assert_eq!(manager.cleanup_ended_sessions(), vec![session_id]);
         assert_eq!(manager.session_count(), 0);
     }
 
@@ -514,4 +573,30 @@ mod tests {
         assert_eq!(info.app_name, "TestApp");
         assert_eq!(info.latest_data.unwrap()["test"], 456);
     }
+
+    #[test]
+    fn test_session_attachments() {
+        let (tx, _rx) = mpsc::channel(10);
+        let register = create_test_register_payload();
+        let mut session = Session::new(register, 100, 500, tx);
+
+        session.add_attachment(AttachmentInfo {
+            id: "att-1".to_string(),
+            name: "screenshot.png".to_string(),
+            mime_type: "image/png".to_string(),
+            size: 3,
+            sha256: "abc".to_string(),
+            created_at: Utc::now(),
+        });
+
+        assert_eq!(
+            session.get_attachment("att-1").unwrap().name,
+            "screenshot.png"
+        );
+        assert!(session.get_attachment("att-2").is_none());
+
+        let info = session.to_session_info();
+        assert_eq!(info.attachments.len(), 1);
+        assert_eq!(info.attachments[0].size, 3);
+    }
 }
@@ -1,13 +1,20 @@
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex};
 
+use crate::attachments::AttachmentStore;
 use crate::config::Config;
+use crate::dashboard_auth::LoginStore;
 use crate::session::SessionManager;
+use crate::stats::Stats;
 
 #[derive(Clone)]
 pub struct AppState {
     pub session_manager: Arc<Mutex<SessionManager>>,
-    pub config: Arc<Config>,
+    pub attachment_store: Arc<AttachmentStore>,
+    pub logins: Arc<LoginStore>,
+    pub stats: Arc<Stats>,
+    config: Arc<watch::Sender<Arc<Config>>>,
 }
 
 impl AppState {
@@ -18,9 +25,36 @@ impl AppState {
             config.session.ended_session_ttl_seconds,
         );
 
+        let attachment_store = AttachmentStore::new(
+            &config.session.attachments_dir,
+            config.session.max_attachments_per_session,
+            config.session.attachment_quota_bytes,
+        );
+
+        let logins = LoginStore::new(Duration::from_secs(
+            config.dashboard.auth.login_ttl_hours * 3600,
+        ));
+
         Self {
             session_manager: Arc::new(Mutex::new(session_manager)),
-            config: Arc::new(config),
+            attachment_store: Arc::new(attachment_store),
+            logins: Arc::new(logins),
+            stats: Arc::new(Stats::new()),
+            config: Arc::new(watch::Sender::new(Arc::new(config))),
         }
     }
+
+    /// The running config. It can change with a reload, see [`crate::reload`].
+    pub fn config(&self) -> Arc<Config> {
+        self.config.borrow().clone()
+    }
+
+    /// Notified of every config the server switches to.
+    pub fn config_updates(&self) -> watch::Receiver<Arc<Config>> {
+        self.config.subscribe()
+    }
+
+    pub(crate) fn set_config(&self, config: Config) {
+        self.config.send_replace(Arc::new(config));
+    }
 }
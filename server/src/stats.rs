@@ -0,0 +1,191 @@
+//! Runtime statistics of the server, reported by `androidoscopy status`.
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Name of the listener apps connect to.
+pub const APP_LISTENER: &str = "Android app";
+
+/// Seconds of history message rates are averaged over.
+pub const RATE_WINDOW_SECONDS: u64 = 60;
+
+pub struct Stats {
+    pub started_at: DateTime<Utc>,
+    listeners: Mutex<Vec<Listener>>,
+    /// Messages received from apps.
+    pub app_messages: MessageCounter,
+    /// Messages sent to dashboards.
+    pub dashboard_messages: MessageCounter,
+}
+
+/// Something the server listens on.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Listener {
+    /// What connects to it, e.g. "Dashboard".
+    pub name: String,
+    pub url: String,
+    /// Whether it serves TLS, which a TLS listener that fell back to plain
+    /// connections doesn't.
+    #[serde(default)]
+    pub tls: bool,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Self {
+            started_at: Utc::now(),
+            listeners: Mutex::new(Vec::new()),
+            app_messages: MessageCounter::new(),
+            dashboard_messages: MessageCounter::new(),
+        }
+    }
+
+    pub fn add_listener(&self, name: &str, url: String, tls: bool) {
+        self.listeners.lock().unwrap().push(Listener {
+            name: name.to_string(),
+            url,
+            tls,
+        });
+    }
+
+    pub fn listeners(&self) -> Vec<Listener> {
+        self.listeners.lock().unwrap().clone()
+    }
+
+    /// The [`APP_LISTENER`], once it is bound.
+    pub fn app_listener(&self) -> Option<Listener> {
+        self.listeners
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|listener| listener.name == APP_LISTENER)
+            .cloned()
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Counts messages, keeping per-second counts of the last
+/// [`RATE_WINDOW_SECONDS`] for the current rate.
+pub struct MessageCounter {
+    start: Instant,
+    inner: Mutex<Counts>,
+}
+
+#[derive(Default)]
+struct Counts {
+    total: u64,
+    /// Second since `start` and the messages counted in it, oldest first.
+    seconds: VecDeque<(u64, u64)>,
+}
+
+/// A [`MessageCounter`] at one point in time.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MessageRate {
+    pub total: u64,
+    /// Average over the last [`RATE_WINDOW_SECONDS`], or since the start
+    /// when that is more recent.
+    pub per_second: f64,
+}
+
+impl MessageCounter {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            inner: Mutex::new(Counts::default()),
+        }
+    }
+
+    pub fn record(&self) {
+        self.record_at(self.start.elapsed().as_secs());
+    }
+
+    pub fn rate(&self) -> MessageRate {
+        self.rate_at(self.start.elapsed().as_secs())
+    }
+
+    fn record_at(&self, second: u64) {
+        let mut counts = self.inner.lock().unwrap();
+        counts.total += 1;
+        match counts.seconds.back_mut() {
+            Some((last, count)) if *last == second => *count += 1,
+            _ => counts.seconds.push_back((second, 1)),
+        }
+        while counts
+            .seconds
+            .front()
+            .is_some_and(|(oldest, _)| oldest + RATE_WINDOW_SECONDS <= second)
+        {
+            counts.seconds.pop_front();
+        }
+    }
+
+    fn rate_at(&self, second: u64) -> MessageRate {
+        let counts = self.inner.lock().unwrap();
+        // The current second is still filling up, so it isn't counted
+        let recent: u64 = counts
+            .seconds
+            .iter()
+            .filter(|(at, _)| *at < second && at + RATE_WINDOW_SECONDS >= second)
+            .map(|(_, count)| count)
+            .sum();
+        let window = second.min(RATE_WINDOW_SECONDS);
+
+        MessageRate {
+            total: counts.total,
+            per_second: if window == 0 {
+                0.0
+            } else {
+                recent as f64 / window as f64
+            },
+        }
+    }
+}
+
+impl Default for MessageCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_rate() {
+        let counter = MessageCounter::new();
+        assert_eq!(counter.rate_at(0).per_second, 0.0);
+
+        for second in 0..10 {
+            for _ in 0..6 {
+                counter.record_at(second);
+            }
+        }
+        assert_eq!(
+            counter.rate_at(10),
+            MessageRate {
+                total: 60,
+                per_second: 6.0
+            }
+        );
+
+        // Once the window is full, older seconds stop counting
+        counter.record_at(100);
+        assert_eq!(
+            counter.rate_at(100),
+            MessageRate {
+                total: 61,
+                per_second: 0.0
+            }
+        );
+        assert_eq!(counter.rate_at(101).per_second, 1.0 / 60.0);
+    }
+}
@@ -1,54 +1,51 @@
-use local_ip_address::list_afinet_netifas;
-use rcgen::{CertifiedKey, generate_simple_self_signed};
+use axum_server::tls_rustls::RustlsConfig;
+use base64::Engine;
+use rustls::pki_types::{pem::PemObject, CertificateDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::RootCertStore;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
 use std::fs;
+use std::net::IpAddr;
 use std::path::Path;
-use tracing::{info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, error, info, warn};
+use x509_parser::prelude::{parse_x509_certificate, GeneralName, X509Certificate};
 
-use crate::config::TlsConfig;
+use crate::ca::{write_file, LocalCa, DEVICE_CA_NAME, SERVER_CA_NAME};
+use crate::certs;
+use crate::config::{Config, TlsConfig};
+use crate::netif;
 
 /// Collects local network IP addresses from all network interfaces.
-fn get_local_ips() -> Vec<String> {
+pub fn get_local_ips() -> Vec<String> {
     let mut ips = Vec::new();
 
-    if let Ok(network_interfaces) = list_afinet_netifas() {
-        for (name, ip) in network_interfaces {
-            let ip_str = ip.to_string();
-            // Skip loopback and link-local addresses, we add those explicitly
-            if !ip.is_loopback() && !ip_str.starts_with("fe80") {
-                info!("Found network interface {}: {}", name, ip_str);
-                ips.push(ip_str);
-            }
+    // IPv6 link-local addresses are skipped, loopback ones are added explicitly
+    for interface in netif::list() {
+        if interface.ip.is_ipv6() && interface.is_link_local() {
+            continue;
+        }
+        let ip = interface.ip.to_string();
+        if !ips.contains(&ip) {
+            debug!("Found network interface {}: {}", interface.name, ip);
+            ips.push(ip);
         }
     }
 
     ips
 }
 
-/// Ensures TLS certificates exist, generating them if needed.
-/// Returns the paths to the certificate and key files.
-pub fn ensure_certificates(config: &TlsConfig) -> Result<(String, String), Box<dyn std::error::Error>> {
-    let cert_path = Path::new(&config.cert_path);
-    let key_path = Path::new(&config.key_path);
-
-    // Check if certificates already exist
-    if cert_path.exists() && key_path.exists() {
-        info!("Using existing TLS certificates");
-        return Ok((config.cert_path.clone(), config.key_path.clone()));
-    }
-
-    // Generate new certificates if auto_generate is enabled
-    if !config.auto_generate {
-        return Err("TLS certificates not found and auto_generate is disabled".into());
-    }
-
-    info!("Generating self-signed TLS certificates...");
-    generate_certificates(&config.cert_path, &config.key_path)?;
+/// How often the certificate watcher checks the local IPs and the certificate expiry.
+const ROTATION_CHECK_INTERVAL: Duration = Duration::from_secs(60);
 
-    Ok((config.cert_path.clone(), config.key_path.clone()))
-}
+/// Common name of the self-signed certificates generated before the local CA.
+const LEGACY_SELF_SIGNED_NAME: &str = "rcgen self signed cert";
 
-/// Generates a self-signed certificate and saves it to the specified paths.
-fn generate_certificates(cert_path: &str, key_path: &str) -> Result<(), Box<dyn std::error::Error>> {
+/// Host names and IPs the server certificate has to cover.
+fn subject_alt_names(config: &TlsConfig) -> Vec<String> {
     // Start with standard local addresses
     let mut subject_alt_names = vec![
         "localhost".to_string(),
@@ -57,81 +54,386 @@ fn generate_certificates(cert_path: &str, key_path: &str) -> Result<(), Box<dyn
     ];
 
     // Add all detected local network IPs
-    let local_ips = get_local_ips();
-    for ip in &local_ips {
-        if !subject_alt_names.contains(ip) {
-            subject_alt_names.push(ip.clone());
+    for ip in get_local_ips() {
+        if !subject_alt_names.contains(&ip) {
+            subject_alt_names.push(ip);
+        }
+    }
+
+    for name in &config.extra_hostnames {
+        if !subject_alt_names.contains(name) {
+            subject_alt_names.push(name.clone());
+        }
+    }
+
+    subject_alt_names
+}
+
+/// Ensures TLS certificates exist, issuing them if needed.
+/// Returns the paths to the certificate and key files.
+pub fn ensure_certificates(
+    config: &TlsConfig,
+) -> Result<(String, String), Box<dyn std::error::Error>> {
+    renew_certificates(config)?;
+    Ok((config.cert_path.clone(), config.key_path.clone()))
+}
+
+/// Why the server certificate has to be re-issued.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum RenewalReason {
+    Missing,
+    Unreadable,
+    /// Left over from when the server generated self-signed certificates,
+    /// or issued by a previous local CA.
+    NotIssuedByCa,
+    /// The key at `key_path` doesn't belong to the certificate, e.g. after a
+    /// renewal that was interrupted.
+    KeyMismatch,
+    SubjectAltNamesChanged,
+    NearExpiry,
+}
+
+impl std::fmt::Display for RenewalReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenewalReason::Missing => write!(f, "no certificate yet"),
+            RenewalReason::Unreadable => write!(f, "existing certificate is unreadable"),
+            RenewalReason::NotIssuedByCa => {
+                write!(f, "existing certificate was not issued by the local CA")
+            }
+            RenewalReason::KeyMismatch => write!(f, "existing key doesn't match the certificate"),
+            RenewalReason::SubjectAltNamesChanged => write!(f, "local IP addresses changed"),
+            RenewalReason::NearExpiry => write!(f, "certificate is about to expire"),
+        }
+    }
+}
+
+/// Re-issues the server certificate from the local root CA when needed.
+/// Returns whether a new certificate was written.
+///
+/// Does nothing when `auto_generate` is disabled, or when the certificate
+/// at `cert_path` wasn't generated by this server, since it was then provided
+/// by the user.
+pub fn renew_certificates(config: &TlsConfig) -> Result<bool, Box<dyn std::error::Error>> {
+    let cert_exists = Path::new(&config.cert_path).exists();
+    let key_exists = Path::new(&config.key_path).exists();
+
+    if !config.auto_generate {
+        if cert_exists && key_exists {
+            return Ok(false);
         }
+        return Err("TLS certificates not found and auto_generate is disabled".into());
     }
 
+    let ca = LocalCa::load_or_create(&config.ca_cert_path, &config.ca_key_path, SERVER_CA_NAME)?;
+    let subject_alt_names = subject_alt_names(config);
+
+    let reason = if cert_exists && key_exists {
+        match renewal_reason(config, &ca, &subject_alt_names)? {
+            Some(reason) => reason,
+            None => return Ok(false),
+        }
+    } else {
+        RenewalReason::Missing
+    };
+
     info!(
-        "Generating certificate with SANs: {:?}",
-        subject_alt_names
+        "Issuing TLS certificate ({}) with SANs: {:?}",
+        reason, subject_alt_names
     );
+    if reason != RenewalReason::Missing {
+        info!("Replacing {} and {}", config.cert_path, config.key_path);
+    }
+    let issued = ca.issue_server_certificate(&subject_alt_names, config.leaf_validity_days)?;
+
+    // Key first: if the certificate write doesn't happen, the mismatch is
+    // noticed and fixed on the next check. Serve the chain, so clients that
+    // only trust the root CA can verify it.
+    write_file(&config.key_path, &issued.key_pem, true)?;
+    write_file(
+        &config.cert_path,
+        format!("{}{}", issued.cert_pem, ca.cert_pem()),
+        false,
+    )?;
 
-    let CertifiedKey { cert, key_pair } = generate_simple_self_signed(subject_alt_names)?;
+    info!("TLS certificates written:");
+    info!("  Certificate: {}", config.cert_path);
+    info!("  Private key: {}", config.key_path);
+    info!("  Root CA: {}", config.ca_cert_path);
 
-    // Create parent directories if they don't exist
-    if let Some(parent) = Path::new(cert_path).parent() {
-        fs::create_dir_all(parent)?;
+    Ok(true)
+}
+
+/// Checks the certificate at `cert_path` and its key; `None` means they can be kept.
+fn renewal_reason(
+    config: &TlsConfig,
+    ca: &LocalCa,
+    subject_alt_names: &[String],
+) -> Result<Option<RenewalReason>, Box<dyn std::error::Error>> {
+    let cert_path = &config.cert_path;
+    let pem = fs::read(cert_path)?;
+    let der = match CertificateDer::pem_slice_iter(&pem).next() {
+        Some(Ok(der)) => der,
+        _ => return Ok(Some(RenewalReason::Unreadable)),
+    };
+    let Ok((_, cert)) = parse_x509_certificate(&der) else {
+        return Ok(Some(RenewalReason::Unreadable));
+    };
+    let (_, ca_cert) = parse_x509_certificate(ca.cert_der())?;
+
+    if cert.issuer() != ca_cert.subject() {
+        let common_name = cert
+            .subject()
+            .iter_common_name()
+            .next()
+            .and_then(|name| name.as_str().ok());
+        if cert.issuer() == cert.subject() && common_name == Some(LEGACY_SELF_SIGNED_NAME) {
+            return Ok(Some(RenewalReason::NotIssuedByCa));
+        }
+        warn!(
+            "{} was issued by {}, not renewing it",
+            cert_path,
+            cert.issuer()
+        );
+        return Ok(None);
     }
-    if let Some(parent) = Path::new(key_path).parent() {
-        fs::create_dir_all(parent)?;
+    if cert.verify_signature(Some(ca_cert.public_key())).is_err() {
+        return Ok(Some(RenewalReason::NotIssuedByCa));
     }
 
-    // Write certificate and key
-    fs::write(cert_path, cert.pem())?;
-    fs::write(key_path, key_pair.serialize_pem())?;
+    let key_matches = certs::load_private_key(&config.key_path, config.key_password.as_deref())
+        .and_then(|key| certs::check_key_matches(&der, &key, cert_path, &config.key_path));
+    if key_matches.is_err() {
+        return Ok(Some(RenewalReason::KeyMismatch));
+    }
 
-    info!("TLS certificates generated:");
-    info!("  Certificate: {}", cert_path);
-    info!("  Private key: {}", key_path);
+    // Renew once less than a third of the validity is left
+    let remaining = cert.validity().not_after.timestamp() - chrono::Utc::now().timestamp();
+    if remaining < i64::from(config.leaf_validity_days) * 24 * 3600 / 3 {
+        return Ok(Some(RenewalReason::NearExpiry));
+    }
 
-    // Also output the certificate for bundling in Android
-    warn!("To use certificate pinning in Android, copy the certificate to your app's assets.");
+    let wanted: BTreeSet<String> = subject_alt_names
+        .iter()
+        .map(|name| normalize_name(name))
+        .collect();
+    if certificate_names(&cert) != wanted {
+        return Ok(Some(RenewalReason::SubjectAltNamesChanged));
+    }
 
-    Ok(())
+    Ok(None)
 }
 
-/// Returns the certificate in DER format for embedding in clients.
-pub fn get_certificate_der(cert_path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    let pem_content = fs::read_to_string(cert_path)?;
+/// Host names and IPs in the certificate's subject alternative names.
+fn certificate_names(cert: &X509Certificate<'_>) -> BTreeSet<String> {
+    let Ok(Some(extension)) = cert.subject_alternative_name() else {
+        return BTreeSet::new();
+    };
 
-    // Parse PEM and extract DER
-    let pem = pem_content
-        .lines()
-        .filter(|line| !line.starts_with("-----"))
-        .collect::<String>();
-
-    let der = base64_decode(&pem)?;
-    Ok(der)
+    extension
+        .value
+        .general_names
+        .iter()
+        .filter_map(|name| match name {
+            GeneralName::DNSName(dns) => Some(dns.to_string()),
+            GeneralName::IPAddress(bytes) => match bytes.len() {
+                4 => <[u8; 4]>::try_from(*bytes)
+                    .ok()
+                    .map(|b| IpAddr::from(b).to_string()),
+                16 => <[u8; 16]>::try_from(*bytes)
+                    .ok()
+                    .map(|b| IpAddr::from(b).to_string()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect()
 }
 
-fn base64_decode(input: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    // Simple base64 decode
-    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+fn normalize_name(name: &str) -> String {
+    match name.parse::<IpAddr>() {
+        Ok(ip) => ip.to_string(),
+        Err(_) => name.to_string(),
+    }
+}
 
-    let input = input.replace(['\n', '\r', ' '], "");
-    let mut result = Vec::new();
-    let mut buffer = 0u32;
-    let mut bits = 0;
+/// Periodically re-issues the server certificate when needed and hot-reloads
+/// it into `rustls_config`, so devices keep connecting after a network change
+/// without restarting the server. `dashboard` is the dashboard's own TLS
+/// configuration, when it can't share the app port's one.
+///
+/// The TLS settings are taken from `updates`, and checked again right away
+/// when they change, e.g. when `extra_hostnames` is edited.
+pub async fn watch_certificates(
+    mut updates: watch::Receiver<Arc<Config>>,
+    rustls_config: RustlsConfig,
+    dashboard: Option<RustlsConfig>,
+) {
+    let mut config = updates.borrow_and_update().server.tls.clone();
+    let mut interval = tokio::time::interval(ROTATION_CHECK_INTERVAL);
+    // The first tick completes immediately, and the certificate was just checked
+    interval.tick().await;
 
-    for c in input.chars() {
-        if c == '=' {
-            break;
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            changed = updates.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                // Only these two can change without a restart, see `reload::LIVE_KEYS`
+                let updated = updates.borrow_and_update().server.tls.clone();
+                if updated.extra_hostnames == config.extra_hostnames
+                    && updated.leaf_validity_days == config.leaf_validity_days
+                {
+                    continue;
+                }
+                config = updated;
+            }
         }
-        let value = ALPHABET.iter().position(|&b| b == c as u8)
-            .ok_or("Invalid base64 character")? as u32;
-        buffer = (buffer << 6) | value;
-        bits += 6;
-        if bits >= 8 {
-            bits -= 8;
-            result.push((buffer >> bits) as u8);
-            buffer &= (1 << bits) - 1;
+
+        match renew_certificates(&config) {
+            Ok(true) => {
+                let reloaded = build_server_config(
+                    &config,
+                    &config.cert_path,
+                    &config.key_path,
+                    config.client_auth.enabled,
+                )
+                .map(|server_config| rustls_config.reload_from_config(Arc::new(server_config)))
+                .and_then(|_| match &dashboard {
+                    Some(dashboard) => {
+                        build_server_config(&config, &config.cert_path, &config.key_path, false)
+                            .map(|server_config| {
+                                dashboard.reload_from_config(Arc::new(server_config))
+                            })
+                    }
+                    None => Ok(()),
+                });
+
+                match reloaded {
+                    Ok(()) => info!("Reloaded TLS certificate"),
+                    Err(e) => error!("Failed to load renewed TLS certificate: {}", e),
+                }
+            }
+            Ok(false) => {}
+            Err(e) => warn!("Failed to renew TLS certificate: {}", e),
         }
     }
+}
 
-    Ok(result)
+/// Builds the TLS configuration for the app port from the server certificate.
+///
+/// With `client_auth` enabled, clients must present a certificate issued by
+/// the device CA, which is created on first use.
+pub fn rustls_config(
+    config: &TlsConfig,
+    cert_path: &str,
+    key_path: &str,
+) -> Result<RustlsConfig, Box<dyn std::error::Error>> {
+    let server_config =
+        build_server_config(config, cert_path, key_path, config.client_auth.enabled)?;
+
+    if config.client_auth.enabled {
+        info!("Client certificates required; enroll devices with `androidoscopy enroll <name>`");
+    }
+    Ok(RustlsConfig::from_config(Arc::new(server_config)))
+}
+
+/// Builds the TLS configuration for the dashboard, which never asks for
+/// client certificates since browsers don't have one from the device CA.
+pub fn dashboard_rustls_config(
+    config: &TlsConfig,
+    cert_path: &str,
+    key_path: &str,
+) -> Result<RustlsConfig, Box<dyn std::error::Error>> {
+    let server_config = build_server_config(config, cert_path, key_path, false)?;
+    Ok(RustlsConfig::from_config(Arc::new(server_config)))
+}
+
+fn build_server_config(
+    config: &TlsConfig,
+    cert_path: &str,
+    key_path: &str,
+    client_auth: bool,
+) -> Result<rustls::ServerConfig, Box<dyn std::error::Error>> {
+    let certs = certs::load_certificates(cert_path)?;
+    let key = certs::load_private_key(key_path, config.key_password.as_deref())?;
+    certs::check_key_matches(&certs[0], &key, cert_path, key_path)?;
+
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let mut server_config = if client_auth {
+        let client_auth = &config.client_auth;
+        let ca = LocalCa::load_or_create(
+            &client_auth.ca_cert_path,
+            &client_auth.ca_key_path,
+            DEVICE_CA_NAME,
+        )?;
+
+        let mut roots = RootCertStore::empty();
+        roots.add(ca.cert_der().clone())?;
+        let verifier =
+            WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider).build()?;
+
+        builder
+            .with_client_cert_verifier(verifier)
+            .with_single_cert(certs, key)?
+    } else {
+        builder.with_no_client_auth().with_single_cert(certs, key)?
+    };
+    // Same as RustlsConfig::from_pem_file
+    server_config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+    Ok(server_config)
+}
+
+/// The certificate clients should trust or pin: the local root CA when
+/// certificates are auto-generated, since the server certificate is re-issued
+/// regularly, otherwise the configured server certificate.
+pub fn distributed_certificate_path(config: &TlsConfig) -> &str {
+    if config.auto_generate {
+        &config.ca_cert_path
+    } else {
+        &config.cert_path
+    }
+}
+
+/// Returns the SHA-256 pin of a certificate's public key, in the
+/// `sha256/<base64>` form used by OkHttp's `CertificatePinner`.
+pub fn certificate_pin(cert_der: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+    let (_, cert) = parse_x509_certificate(cert_der)?;
+    let digest = Sha256::digest(cert.public_key().raw);
+    Ok(format!(
+        "sha256/{}",
+        base64::engine::general_purpose::STANDARD.encode(digest)
+    ))
+}
+
+/// Returns the SHA-256 fingerprint of a certificate, as colon-separated hex.
+pub fn certificate_fingerprint(cert_der: &[u8]) -> String {
+    Sha256::digest(cert_der)
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Returns when a certificate expires.
+pub fn certificate_expiry(
+    cert_der: &[u8],
+) -> Result<chrono::DateTime<chrono::Utc>, Box<dyn std::error::Error>> {
+    let (_, cert) = parse_x509_certificate(cert_der)?;
+    chrono::DateTime::from_timestamp(cert.validity().not_after.timestamp(), 0)
+        .ok_or_else(|| "certificate expiry out of range".into())
+}
+
+/// Returns the first certificate of the file in DER format, for embedding in clients.
+pub fn get_certificate_der(cert_path: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut certs = certs::load_certificates(cert_path)?;
+    Ok(certs.remove(0).to_vec())
 }
 
 #[cfg(test)]
@@ -159,6 +461,200 @@ mod tests {
         let mut unique = ips.clone();
         unique.sort();
         unique.dedup();
-        assert_eq!(ips.len(), unique.len(), "get_local_ips should not return duplicates");
+        assert_eq!(
+            ips.len(),
+            unique.len(),
+            "get_local_ips should not return duplicates"
+        );
+    }
+
+    #[test]
+    fn test_certificate_pin_and_fingerprint() {
+        let config = temp_config();
+        renew_certificates(&config).unwrap();
+
+        let der = get_certificate_der(distributed_certificate_path(&config)).unwrap();
+        let pin = certificate_pin(&der).unwrap();
+        assert!(pin.starts_with("sha256/"));
+        assert_eq!(
+            base64::engine::general_purpose::STANDARD
+                .decode(&pin[7..])
+                .unwrap()
+                .len(),
+            32
+        );
+
+        let fingerprint = certificate_fingerprint(&der);
+        assert_eq!(fingerprint.split(':').count(), 32);
+
+        // The pin survives re-issuing the server certificate
+        fs::remove_file(&config.cert_path).unwrap();
+        renew_certificates(&config).unwrap();
+        let der = get_certificate_der(distributed_certificate_path(&config)).unwrap();
+        assert_eq!(certificate_pin(&der).unwrap(), pin);
+
+        remove_temp_dir(&config);
+    }
+
+    fn temp_config() -> TlsConfig {
+        let dir = std::env::temp_dir().join(format!("androidoscopy-tls-{}", uuid::Uuid::new_v4()));
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+
+        TlsConfig {
+            enabled: true,
+            cert_path: path("cert.pem"),
+            key_path: path("key.pem"),
+            ca_cert_path: path("ca.pem"),
+            ca_key_path: path("ca-key.pem"),
+            ..TlsConfig::default()
+        }
+    }
+
+    fn remove_temp_dir(config: &TlsConfig) {
+        fs::remove_dir_all(Path::new(&config.cert_path).parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn test_server_certificate_is_issued_by_local_ca() {
+        let config = temp_config();
+
+        assert!(renew_certificates(&config).unwrap());
+        assert!(
+            !renew_certificates(&config).unwrap(),
+            "A fresh certificate should be kept"
+        );
+
+        let pem = fs::read(&config.cert_path).unwrap();
+        let chain = CertificateDer::pem_slice_iter(&pem)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(
+            chain.len(),
+            2,
+            "The CA certificate should be served with the leaf"
+        );
+
+        let ca = LocalCa::load_or_create(&config.ca_cert_path, &config.ca_key_path, SERVER_CA_NAME)
+            .unwrap();
+        assert_eq!(&chain[1], ca.cert_der());
+        let (_, leaf) = parse_x509_certificate(&chain[0]).unwrap();
+        let (_, ca_cert) = parse_x509_certificate(ca.cert_der()).unwrap();
+        leaf.verify_signature(Some(ca_cert.public_key())).unwrap();
+
+        let names = certificate_names(&leaf);
+        assert!(names.contains("localhost"));
+        assert!(names.contains("::1"));
+
+        remove_temp_dir(&config);
+    }
+
+    #[test]
+    fn test_server_certificate_is_renewed() {
+        let config = temp_config();
+        let ca = LocalCa::load_or_create(&config.ca_cert_path, &config.ca_key_path, SERVER_CA_NAME)
+            .unwrap();
+        let write_leaf = |names: &[String], days: u32| {
+            let issued = ca.issue_server_certificate(names, days).unwrap();
+            write_file(&config.cert_path, &issued.cert_pem, false).unwrap();
+            write_file(&config.key_path, &issued.key_pem, true).unwrap();
+        };
+
+        // Local IPs changed
+        write_leaf(
+            &["localhost".to_string(), "192.0.2.1".to_string()],
+            config.leaf_validity_days,
+        );
+        assert_eq!(
+            renewal_reason(&config, &ca, &subject_alt_names(&config)).unwrap(),
+            Some(RenewalReason::SubjectAltNamesChanged)
+        );
+        assert!(renew_certificates(&config).unwrap());
+
+        // A host name was added to the config
+        let mut with_hostname = config.clone();
+        with_hostname.extra_hostnames = vec!["devbox.local".to_string()];
+        assert!(renew_certificates(&with_hostname).unwrap());
+        assert!(!renew_certificates(&with_hostname).unwrap());
+
+        // Less than a third of the validity left
+        write_leaf(&subject_alt_names(&config), 5);
+        assert_eq!(
+            renewal_reason(&config, &ca, &subject_alt_names(&config)).unwrap(),
+            Some(RenewalReason::NearExpiry)
+        );
+
+        // The key was replaced but the certificate wasn't
+        assert!(renew_certificates(&config).unwrap());
+        let other = ca
+            .issue_server_certificate(&subject_alt_names(&config), config.leaf_validity_days)
+            .unwrap();
+        write_file(&config.key_path, &other.key_pem, true).unwrap();
+        assert_eq!(
+            renewal_reason(&config, &ca, &subject_alt_names(&config)).unwrap(),
+            Some(RenewalReason::KeyMismatch)
+        );
+        assert!(renew_certificates(&config).unwrap());
+        assert!(!renew_certificates(&config).unwrap());
+
+        remove_temp_dir(&config);
+    }
+
+    #[test]
+    fn test_user_provided_certificate_is_kept() {
+        let config = temp_config();
+        let other_dir = Path::new(&config.cert_path).parent().unwrap();
+        let other_ca = LocalCa::load_or_create(
+            &other_dir.join("other-ca.pem").to_string_lossy(),
+            &other_dir.join("other-ca-key.pem").to_string_lossy(),
+            "Other CA",
+        )
+        .unwrap();
+
+        let issued = other_ca
+            .issue_server_certificate(&["example.com".to_string()], 5)
+            .unwrap();
+        write_file(&config.cert_path, &issued.cert_pem, false).unwrap();
+        write_file(&config.key_path, &issued.key_pem, true).unwrap();
+
+        assert!(!renew_certificates(&config).unwrap());
+        assert_eq!(
+            fs::read_to_string(&config.cert_path).unwrap(),
+            issued.cert_pem
+        );
+
+        remove_temp_dir(&config);
+    }
+
+    #[test]
+    fn test_self_signed_certificate_is_replaced() {
+        let config = temp_config();
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        write_file(&config.cert_path, cert.pem(), false).unwrap();
+        write_file(&config.key_path, key_pair.serialize_pem(), true).unwrap();
+
+        assert!(renew_certificates(&config).unwrap());
+        assert_ne!(fs::read_to_string(&config.cert_path).unwrap(), cert.pem());
+
+        remove_temp_dir(&config);
+    }
+
+    #[test]
+    fn test_user_self_signed_certificate_is_kept() {
+        let config = temp_config();
+        let mut params = rcgen::CertificateParams::new(vec!["devbox.local".to_string()]).unwrap();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, "devbox.local");
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key_pair).unwrap();
+        write_file(&config.cert_path, cert.pem(), false).unwrap();
+        write_file(&config.key_path, key_pair.serialize_pem(), true).unwrap();
+
+        assert!(!renew_certificates(&config).unwrap());
+        assert_eq!(fs::read_to_string(&config.cert_path).unwrap(), cert.pem());
+
+        remove_temp_dir(&config);
     }
 }
@@ -0,0 +1,259 @@
+//! Wire formats for WebSocket frames.
+//!
+//! By default every message is a JSON text frame. Clients can opt into a
+//! different encoding by requesting one of these subprotocols in the
+//! `Sec-WebSocket-Protocol` header of the upgrade request:
+//!
+//! - `androidoscopy.msgpack` - every message is a MessagePack map in a binary
+//!   frame, using the same field names and `type` tags as the JSON protocol.
+//! - `androidoscopy.json.zstd` - the WebSocket stack doesn't implement
+//!   `permessage-deflate`, so compression is done per message. Messages sent by
+//!   the service are compressed into binary frames when the JSON is at least
+//!   [`COMPRESSION_THRESHOLD`] bytes; smaller messages stay text frames.
+//!
+//! Text frames are always parsed as JSON. Binary frames are parsed as
+//! MessagePack on `androidoscopy.msgpack` connections and as zstd-compressed
+//! JSON on all others, whether or not compression was negotiated.
+
+use axum::extract::ws::Message;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+pub const SUBPROTOCOL_MSGPACK: &str = "androidoscopy.msgpack";
+pub const SUBPROTOCOL_JSON_ZSTD: &str = "androidoscopy.json.zstd";
+
+/// Subprotocols offered to clients, in order of preference.
+pub const SUPPORTED_SUBPROTOCOLS: [&str; 2] = [SUBPROTOCOL_MSGPACK, SUBPROTOCOL_JSON_ZSTD];
+
+/// Messages smaller than this are not worth compressing.
+pub const COMPRESSION_THRESHOLD: usize = 1024;
+
+const COMPRESSION_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    #[default]
+    Json,
+    JsonZstd,
+    MessagePack,
+}
+
+impl WireFormat {
+    /// Returns the wire format for the subprotocol selected during the upgrade.
+    pub fn from_subprotocol(protocol: Option<&str>) -> Self {
+        match protocol {
+            Some(SUBPROTOCOL_MSGPACK) => WireFormat::MessagePack,
+            Some(SUBPROTOCOL_JSON_ZSTD) => WireFormat::JsonZstd,
+            _ => WireFormat::Json,
+        }
+    }
+
+    pub fn encode<T: Serialize>(self, msg: &T) -> Result<Message, WireError> {
+        if self == WireFormat::MessagePack {
+            // Named fields keep the internally tagged enums decodable on the other side
+            let bytes = rmp_serde::to_vec_named(msg).map_err(WireError::MessagePackEncode)?;
+            return Ok(Message::Binary(bytes));
+        }
+
+        let json = serde_json::to_string(msg).map_err(WireError::Json)?;
+
+        match self {
+            WireFormat::JsonZstd if json.len() >= COMPRESSION_THRESHOLD => {
+                let compressed = zstd::bulk::compress(json.as_bytes(), COMPRESSION_LEVEL)
+                    .map_err(WireError::Compression)?;
+                Ok(Message::Binary(compressed))
+            }
+            _ => Ok(Message::Text(json)),
+        }
+    }
+
+    /// Decodes a text or binary frame. Control frames (ping, pong, close) carry no message.
+    ///
+    /// `max_size` bounds the decompressed size of zstd frames, so a small frame
+    /// can't expand into an unbounded allocation.
+    pub fn decode<T: DeserializeOwned>(
+        self,
+        msg: &Message,
+        max_size: usize,
+    ) -> Result<T, WireError> {
+        match msg {
+            Message::Text(text) => serde_json::from_str(text).map_err(WireError::Json),
+            Message::Binary(bytes) if self == WireFormat::MessagePack => {
+                rmp_serde::from_slice(bytes).map_err(WireError::MessagePackDecode)
+            }
+            Message::Binary(bytes) => decode_zstd_json(bytes, max_size),
+            _ => Err(WireError::ControlFrame),
+        }
+    }
+}
+
+fn decode_zstd_json<T: DeserializeOwned>(bytes: &[u8], max_size: usize) -> Result<T, WireError> {
+    let json = zstd::bulk::decompress(bytes, max_size).map_err(WireError::Compression)?;
+    serde_json::from_slice(&json).map_err(WireError::Json)
+}
+
+#[derive(Debug)]
+pub enum WireError {
+    Json(serde_json::Error),
+    Compression(std::io::Error),
+    MessagePackEncode(rmp_serde::encode::Error),
+    MessagePackDecode(rmp_serde::decode::Error),
+    ControlFrame,
+}
+
+impl std::fmt::Display for WireError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WireError::Json(e) => write!(f, "invalid JSON: {}", e),
+            WireError::Compression(e) => write!(f, "invalid zstd frame: {}", e),
+            WireError::MessagePackEncode(e) => write!(f, "failed to encode MessagePack: {}", e),
+            WireError::MessagePackDecode(e) => write!(f, "invalid MessagePack: {}", e),
+            WireError::ControlFrame => write!(f, "control frames carry no message"),
+        }
+    }
+}
+
+impl std::error::Error for WireError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::{
+        AppMessage, LogLevel, LogPayload, ServiceToDashboardMessage, SessionDataPayload,
+    };
+    use chrono::Utc;
+    use serde_json::{json, Value};
+
+    const MAX_SIZE: usize = 1024 * 1024;
+
+    #[test]
+    fn test_from_subprotocol() {
+        assert_eq!(WireFormat::from_subprotocol(None), WireFormat::Json);
+        assert_eq!(
+            WireFormat::from_subprotocol(Some("unknown")),
+            WireFormat::Json
+        );
+        assert_eq!(
+            WireFormat::from_subprotocol(Some(SUBPROTOCOL_JSON_ZSTD)),
+            WireFormat::JsonZstd
+        );
+        assert_eq!(
+            WireFormat::from_subprotocol(Some(SUBPROTOCOL_MSGPACK)),
+            WireFormat::MessagePack
+        );
+    }
+
+    #[test]
+    fn test_json_format_always_sends_text() {
+        let large = json!({ "body": "x".repeat(COMPRESSION_THRESHOLD * 4) });
+        let msg = WireFormat::Json.encode(&large).unwrap();
+        assert!(matches!(msg, Message::Text(_)));
+    }
+
+    #[test]
+    fn test_zstd_format_keeps_small_messages_as_text() {
+        let small = json!({ "memory": 1000 });
+        let msg = WireFormat::JsonZstd.encode(&small).unwrap();
+        assert!(matches!(msg, Message::Text(_)));
+    }
+
+    #[test]
+    fn test_zstd_round_trip() {
+        let large = json!({ "body": "x".repeat(COMPRESSION_THRESHOLD * 4) });
+        let msg = WireFormat::JsonZstd.encode(&large).unwrap();
+
+        match &msg {
+            Message::Binary(bytes) => assert!(bytes.len() < COMPRESSION_THRESHOLD),
+            _ => panic!("Expected binary frame"),
+        }
+
+        let decoded: Value = WireFormat::Json.decode(&msg, MAX_SIZE).unwrap();
+        assert_eq!(decoded, large);
+    }
+
+    #[test]
+    fn test_decode_rejects_invalid_zstd() {
+        let msg = Message::Binary(b"not zstd".to_vec());
+        let result = WireFormat::JsonZstd.decode::<Value>(&msg, MAX_SIZE);
+        assert!(matches!(result, Err(WireError::Compression(_))));
+    }
+
+    #[test]
+    fn test_decode_bounds_decompressed_size() {
+        let large = json!({ "body": "x".repeat(COMPRESSION_THRESHOLD * 4) });
+        let msg = WireFormat::JsonZstd.encode(&large).unwrap();
+        let result = WireFormat::JsonZstd.decode::<Value>(&msg, COMPRESSION_THRESHOLD);
+        assert!(matches!(result, Err(WireError::Compression(_))));
+    }
+
+    #[test]
+    fn test_decode_rejects_control_frames() {
+        let msg = Message::Ping(vec![]);
+        let result = WireFormat::Json.decode::<Value>(&msg, MAX_SIZE);
+        assert!(matches!(result, Err(WireError::ControlFrame)));
+    }
+
+    #[test]
+    fn test_msgpack_round_trip_app_message() {
+        let msg = AppMessage::Log {
+            timestamp: Utc::now(),
+            session_id: "session-123".to_string(),
+            payload: LogPayload {
+                level: LogLevel::Warn,
+                tag: Some("Net".to_string()),
+                message: "Slow response".to_string(),
+                throwable: None,
+            },
+        };
+
+        let frame = WireFormat::MessagePack.encode(&msg).unwrap();
+        assert!(matches!(frame, Message::Binary(_)));
+
+        match WireFormat::MessagePack
+            .decode::<AppMessage>(&frame, MAX_SIZE)
+            .unwrap()
+        {
+            AppMessage::Log {
+                session_id,
+                payload,
+                ..
+            } => {
+                assert_eq!(session_id, "session-123");
+                assert_eq!(payload.level, LogLevel::Warn);
+                assert_eq!(payload.tag, Some("Net".to_string()));
+            }
+            _ => panic!("Expected Log message"),
+        }
+    }
+
+    #[test]
+    fn test_msgpack_round_trip_dashboard_message() {
+        let msg = ServiceToDashboardMessage::SessionData {
+            timestamp: Utc::now(),
+            payload: SessionDataPayload {
+                session_id: "session-123".to_string(),
+                data: json!({ "memory": { "heap_used_bytes": 1000000 }, "fps": 59.5 }),
+            },
+        };
+
+        let frame = WireFormat::MessagePack.encode(&msg).unwrap();
+
+        match WireFormat::MessagePack
+            .decode::<ServiceToDashboardMessage>(&frame, MAX_SIZE)
+            .unwrap()
+        {
+            ServiceToDashboardMessage::SessionData { payload, .. } => {
+                assert_eq!(payload.data["memory"]["heap_used_bytes"], 1000000);
+                assert_eq!(payload.data["fps"], 59.5);
+            }
+            _ => panic!("Expected SessionData message"),
+        }
+    }
+
+    #[test]
+    fn test_msgpack_connection_still_accepts_json_text() {
+        let msg = Message::Text(r#"{"memory": 1000}"#.to_string());
+        let decoded: Value = WireFormat::MessagePack.decode(&msg, MAX_SIZE).unwrap();
+        assert_eq!(decoded["memory"], 1000);
+    }
+}
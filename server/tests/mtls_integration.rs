@@ -0,0 +1,175 @@
+use std::sync::Arc;
+
+use androidoscopy_server::ca::{LocalCa, DEVICE_CA_NAME};
+use androidoscopy_server::config::{Config, TlsConfig};
+use androidoscopy_server::state::AppState;
+use androidoscopy_server::{create_router, tls};
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, SignatureScheme};
+use tokio_tungstenite::{connect_async_tls_with_config, Connector};
+
+/// Accepts the server's self-signed certificate, like the Android SDK does.
+#[derive(Debug)]
+struct AcceptAnyServerCert(Arc<rustls::crypto::CryptoProvider>);
+
+impl ServerCertVerifier for AcceptAnyServerCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+fn client_config(client_cert: Option<(Vec<u8>, Vec<u8>)>) -> Arc<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::aws_lc_rs::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AcceptAnyServerCert(provider)));
+
+    let config = match client_cert {
+        Some((cert_der, key_der)) => builder
+            .with_client_auth_cert(
+                vec![CertificateDer::from(cert_der)],
+                PrivateKeyDer::from(PrivatePkcs8KeyDer::from(key_der)),
+            )
+            .unwrap(),
+        None => builder.with_no_client_auth(),
+    };
+
+    Arc::new(config)
+}
+
+#[tokio::test]
+async fn test_mtls_requires_enrolled_client_certificate() {
+    let dir = std::env::temp_dir().join(format!("androidoscopy-mtls-{}", uuid::Uuid::new_v4()));
+    let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+
+    let mut tls_config = TlsConfig {
+        enabled: true,
+        cert_path: path("cert.pem"),
+        key_path: path("key.pem"),
+        ca_cert_path: path("ca.pem"),
+        ca_key_path: path("ca-key.pem"),
+        ..TlsConfig::default()
+    };
+    tls_config.client_auth.enabled = true;
+    tls_config.client_auth.ca_cert_path = path("device-ca.pem");
+    tls_config.client_auth.ca_key_path = path("device-ca-key.pem");
+
+    let (cert_path, key_path) = tls::ensure_certificates(&tls_config).unwrap();
+    let rustls_config = tls::rustls_config(&tls_config, &cert_path, &key_path).unwrap();
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let mut config = Config::default();
+    config.server.udp_discovery_enabled = false;
+    let app = create_router(AppState::new(config));
+    tokio::spawn(async move {
+        axum_server::from_tcp_rustls(listener, rustls_config)
+            .serve(app.into_make_service())
+            .await
+            .unwrap();
+    });
+
+    let url = format!("wss://localhost:{}/ws/app", addr.port());
+
+    // No client certificate
+    let result = connect_async_tls_with_config(
+        &url,
+        None,
+        false,
+        Some(Connector::Rustls(client_config(None))),
+    )
+    .await;
+    assert!(
+        result.is_err(),
+        "Connection without a client certificate should fail"
+    );
+
+    // Certificate from another CA
+    let other_ca =
+        LocalCa::load_or_create(&path("other-ca.pem"), &path("other-ca-key.pem"), "Other CA")
+            .unwrap();
+    let stranger = other_ca.issue_client_certificate("stranger", 1).unwrap();
+    let result = connect_async_tls_with_config(
+        &url,
+        None,
+        false,
+        Some(Connector::Rustls(client_config(Some((
+            stranger.cert_der,
+            stranger.key_der,
+        ))))),
+    )
+    .await;
+    assert!(
+        result.is_err(),
+        "Connection with a foreign certificate should fail"
+    );
+
+    // Enrolled device
+    let ca = LocalCa::load_or_create(
+        &tls_config.client_auth.ca_cert_path,
+        &tls_config.client_auth.ca_key_path,
+        DEVICE_CA_NAME,
+    )
+    .unwrap();
+    let device = ca.issue_client_certificate("pixel-5", 1).unwrap();
+    let result = connect_async_tls_with_config(
+        &url,
+        None,
+        false,
+        Some(Connector::Rustls(client_config(Some((
+            device.cert_der,
+            device.key_der,
+        ))))),
+    )
+    .await;
+    assert!(
+        result.is_ok(),
+        "Enrolled device should connect: {:?}",
+        result.err()
+    );
+
+    std::fs::remove_dir_all(&dir).ok();
+}
@@ -1,18 +1,10 @@
 use std::time::Duration;
 
-use serde::Deserialize;
 use tokio::net::UdpSocket;
 use tokio::time::timeout;
 
-use androidoscopy_server::discovery::broadcast_presence;
-
-#[derive(Debug, Deserialize)]
-struct DiscoveryMessage {
-    service: String,
-    version: String,
-    websocket_port: u16,
-    http_port: u16,
-}
+use androidoscopy_server::config::DiscoveryConfig;
+use androidoscopy_server::discovery::{broadcast_presence, serve_probes, DiscoveryMessage};
 
 #[tokio::test]
 async fn test_udp_discovery_broadcast_and_message_format() {
@@ -27,7 +19,11 @@ async fn test_udp_discovery_broadcast_and_message_format() {
 
     // Start the broadcast in a separate task
     let broadcast_handle = tokio::spawn(async move {
-        broadcast_presence(websocket_port, http_port).await;
+        broadcast_presence(
+            DiscoveryMessage::new(websocket_port, http_port, None),
+            DiscoveryConfig::default(),
+        )
+        .await;
     });
 
     // Wait for a broadcast message (with timeout)
@@ -48,6 +44,8 @@ async fn test_udp_discovery_broadcast_and_message_format() {
     assert_eq!(message.version, "1.0");
     assert_eq!(message.websocket_port, websocket_port);
     assert_eq!(message.http_port, http_port);
+    assert!(!message.instance_id.is_empty());
+    assert!(!message.hostname.is_empty());
 
     // Wait for a second broadcast to verify it repeats (should arrive within ~5 seconds)
     let result2 = timeout(Duration::from_secs(10), receiver.recv_from(&mut buf)).await;
@@ -61,3 +59,37 @@ async fn test_udp_discovery_broadcast_and_message_format() {
     // Abort the broadcast task
     broadcast_handle.abort();
 }
+
+#[tokio::test]
+async fn test_udp_discovery_answers_probes() {
+    let responder = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let responder_addr = responder.local_addr().unwrap();
+    let message = DiscoveryMessage::new(9999, 8080, Some("AB:CD".to_string()));
+    let responder_handle = tokio::spawn(serve_probes(
+        responder,
+        message.clone(),
+        DiscoveryConfig::default(),
+    ));
+
+    let app = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let mut buf = [0u8; 1024];
+
+    // Anything but a probe goes unanswered
+    app.send_to(b"HELLO", responder_addr).await.unwrap();
+    assert!(timeout(Duration::from_millis(300), app.recv_from(&mut buf))
+        .await
+        .is_err());
+
+    app.send_to(b"DISCOVER", responder_addr).await.unwrap();
+    let (len, src) = timeout(Duration::from_secs(5), app.recv_from(&mut buf))
+        .await
+        .expect("Timeout waiting for probe reply")
+        .unwrap();
+    assert_eq!(src, responder_addr);
+
+    let reply: DiscoveryMessage = serde_json::from_slice(&buf[..len]).unwrap();
+    assert_eq!(reply, message);
+    assert_eq!(reply.certificate_fingerprint.as_deref(), Some("AB:CD"));
+
+    responder_handle.abort();
+}